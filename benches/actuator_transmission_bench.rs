@@ -2,7 +2,9 @@ use criterion::{criterion_group, criterion_main, Criterion};
 use Real_time_systems_repo::{
     actuator_lib::compute_arm_movement,
     controller_lib::generate_sensor_data,
-    data_structure::*,
+    data_structure::SensorArmData,
+    recording::{load_recording, Stream},
+    simulation::{Scenario, SensorSimulator},
 };
 use std::sync::Arc;
 use tokio::{runtime::Runtime, sync::Mutex};
@@ -14,11 +16,38 @@ fn bench_arm_processing(c: &mut Criterion) {
 
     c.bench_function("actuator arm computation", |b| {
         b.to_async(&rt).iter(|| async { 
-            let sample_data = generate_sensor_data(1, shared_feedback.clone()).await;
+            let mut simulator = SensorSimulator::new(1, Scenario::default());
+            let sample_data = generate_sensor_data(&mut simulator, 1, shared_feedback.clone())
+                .await
+                .expect("the default scenario always has a frame");
             let _ = compute_arm_movement(sample_data);
         });
     });
 }
 
-criterion_group!(benches, bench_arm_processing);
+// frames recorded by an actuator with RECORD=1, RECORDING=recording_actuator_1.jsonl
+fn bench_arm_processing_recorded(c: &mut Criterion) {
+    let Ok(path) = std::env::var("RECORDING") else {
+        println!("RECORDING not set, skipping actuator arm computation (recorded)");
+        return;
+    };
+    let frames: Vec<SensorArmData> = load_recording(&path)
+        .unwrap_or_else(|e| panic!("Invalid recording {}", e))
+        .into_iter()
+        .filter(|message| message.stream == Stream::Sensor)
+        .filter_map(|message| serde_json::from_value(message.payload).ok())
+        .collect();
+    assert!(!frames.is_empty(), "no sensor frames in {}", path);
+
+    let mut next = 0;
+    c.bench_function("actuator arm computation (recorded)", |b| {
+        b.iter(|| {
+            let frame = frames[next % frames.len()].clone();
+            next += 1;
+            std::hint::black_box(compute_arm_movement(frame))
+        });
+    });
+}
+
+criterion_group!(benches, bench_arm_processing, bench_arm_processing_recorded);
 criterion_main!(benches);
\ No newline at end of file
@@ -1,24 +1,15 @@
 use criterion::{criterion_group, criterion_main, Criterion};
-use tokio::sync::mpsc;
-use std::hint::black_box;
 use std::sync::Arc;
-use tokio::{runtime::Runtime, sync::Mutex};
-use std::time::{Instant, Duration};
+use tokio::sync::Mutex;
+use std::time::Instant;
 
 use Real_time_systems_repo::controller_lib::{
     generate_sensor_data,
     process_sensor_data,
-    detect_anomaly,
-    generate_anomalous_object_data,
-    publish,
 };
-use Real_time_systems_repo::data_structure::{FeedbackData, Filters};
-use lapin::{
-    options::{BasicPublishOptions, QueueDeclareOptions},
-    types::FieldTable,
-    BasicProperties, Channel, Connection, ConnectionProperties,
-};
-use serde::{Serialize, Deserialize};
+use Real_time_systems_repo::data_structure::{Filters, SensorArmData};
+use Real_time_systems_repo::recording::{load_recording, Stream};
+use Real_time_systems_repo::simulation::{Scenario, SensorSimulator};
 
 //no blackbox
 // fn bench_generate_sensor_data(c: &mut Criterion) {
@@ -61,10 +52,13 @@ fn bench_sensor_generation_interval(c: &mut Criterion) {
             let tx = tx.clone();
 
             async move {
+                let mut simulator = SensorSimulator::new(1, Scenario::default());
                 let start = Instant::now();
 
                 for i in 0..iters {
-                    let data = generate_sensor_data(i as u64, shared_feedback.clone()).await;
+                    let data = generate_sensor_data(&mut simulator, i, shared_feedback.clone())
+                        .await
+                        .expect("the default scenario always has a frame");
 
                     let mut filters = shared_filters.lock().await;
                     let (processed, anomaly) = process_sensor_data(data, &mut filters);
@@ -88,6 +82,37 @@ fn bench_sensor_generation_interval(c: &mut Criterion) {
 }
 
 
+// frames recorded by an actuator with RECORD=1, RECORDING=recording_actuator_1.jsonl
+fn bench_process_recorded_frames(c: &mut Criterion) {
+    let Ok(path) = std::env::var("RECORDING") else {
+        println!("RECORDING not set, skipping process_sensor_data_recorded");
+        return;
+    };
+    let frames: Vec<SensorArmData> = load_recording(&path)
+        .unwrap_or_else(|e| panic!("Invalid recording {}", e))
+        .into_iter()
+        .filter(|message| message.stream == Stream::Sensor)
+        .filter_map(|message| serde_json::from_value(message.payload).ok())
+        .collect();
+    assert!(!frames.is_empty(), "no sensor frames in {}", path);
+
+    c.bench_function("process_sensor_data_recorded", |b| {
+        b.iter_custom(|iters| {
+            let mut filters = Filters::new();
+            let start = Instant::now();
+            for i in 0..iters {
+                let frame = frames[i as usize % frames.len()].clone();
+                let (processed, anomaly) = process_sensor_data(frame, &mut filters);
+                if anomaly {
+                    filters.reset();
+                }
+                std::hint::black_box(processed);
+            }
+            start.elapsed()
+        });
+    });
+}
+
 // fn bench_send_sensor_data(c: &mut Criterion) {
 //     let rt = Runtime::new().unwrap();
 
@@ -184,5 +209,6 @@ fn bench_sensor_generation_interval(c: &mut Criterion) {
 criterion_group!(
     benches,
     bench_sensor_generation_interval,
+    bench_process_recorded_frames,
 );
 criterion_main!(benches);
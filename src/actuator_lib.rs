@@ -4,8 +4,8 @@ use crate::data_structure::SensorArmData;
 /// Returns the modified `SensorArmData` and the time taken in microseconds.
 pub fn compute_arm_movement(mut data: SensorArmData) -> SensorArmData {
     // target never goes negative x
-    let mut target_x = data.object_data.object_x;
-    let mut target_y = data.object_data.object_y;
+    let target_x = data.object_data.object_x;
+    let target_y = data.object_data.object_y;
 
     // Arm segment lengths
     let l1 = 3.0; // shoulder to elbow
@@ -0,0 +1,260 @@
+// decides which arm tries to catch each incoming object
+// uses the position, reach and busy state every arm reports in its FeedbackData
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::config::parse_env;
+use crate::data_structure::{FeedbackData, ObjectData, ARM_REACH};
+
+//used when an arm has not reported a velocity yet
+const DEFAULT_ARM_SPEED: f32 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocationStrategy {
+    // free arm whose wrist is closest to the object and that can reach it
+    NearestReachable,
+    // arm that gets its wrist to the object first, counting the time it is still busy
+    EarliestIntercept,
+}
+
+impl FromStr for AllocationStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" | "nearest-reachable" => Ok(AllocationStrategy::NearestReachable),
+            "intercept" | "earliest-intercept" => Ok(AllocationStrategy::EarliestIntercept),
+            other => Err(format!("unknown allocation strategy: {}", other)),
+        }
+    }
+}
+
+//what the allocator knows about one arm
+#[derive(Debug, Clone)]
+pub struct ArmStatus {
+    pub arm_id: u32,
+    pub base: (f32, f32),
+    pub wrist: (f32, f32),
+    pub reach: f32,
+    pub speed: f32,
+    pub busy_until: u128,
+}
+
+impl ArmStatus {
+    /// Arm that has not reported yet, assumed at rest in the default pose of `SensorArmData::new`.
+    pub fn idle(arm_id: u32) -> Self {
+        ArmStatus {
+            arm_id,
+            base: (0.0, 0.0),
+            wrist: (0.0, 3.0),
+            reach: ARM_REACH,
+            speed: DEFAULT_ARM_SPEED,
+            busy_until: 0,
+        }
+    }
+
+    pub fn from_feedback(feedback: &FeedbackData) -> Self {
+        ArmStatus {
+            arm_id: feedback.arm_id,
+            base: (feedback.joints.shoulder_x, feedback.joints.shoulder_y),
+            wrist: (feedback.wrist.wrist_x, feedback.wrist.wrist_y),
+            reach: if feedback.reach > 0.0 { feedback.reach } else { ARM_REACH },
+            speed: if feedback.arm_velocity > 0.0 { feedback.arm_velocity } else { DEFAULT_ARM_SPEED },
+            busy_until: feedback.busy_until,
+        }
+    }
+
+    pub fn is_busy(&self, now: u128) -> bool {
+        self.busy_until > now
+    }
+
+    pub fn can_reach(&self, object: &ObjectData) -> bool {
+        distance(self.base, (object.object_x, object.object_y)) <= self.reach
+    }
+
+    //µs until the wrist can be at the object, assuming speed is in cm/ms
+    fn intercept_time(&self, object: &ObjectData, now: u128) -> u128 {
+        let travel_ms = distance(self.wrist, (object.object_x, object.object_y)) / self.speed;
+        self.busy_until.max(now) + (travel_ms * 1000.0) as u128
+    }
+}
+
+fn distance(a: (f32, f32), b: (f32, f32)) -> f32 {
+    ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt()
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ArmAllocationStats {
+    pub assigned: u64,
+    // assigned even though no arm could reach the object, the arm stretches towards it
+    pub out_of_reach: u64,
+    // assigned while busy because every arm was busy
+    pub while_busy: u64,
+    // feedback received from the arm, whether or not it caught anything
+    pub answered: u64,
+    // objects the catch evaluator scored as caught by the arm
+    pub catches: u64,
+}
+
+/// Allocation metrics, shared with the feedback consumer which records the answers, and the catch evaluator.
+#[derive(Debug, Default)]
+pub struct AllocationStats {
+    per_arm: Mutex<BTreeMap<u32, ArmAllocationStats>>,
+    unassigned: AtomicU64,
+}
+
+impl AllocationStats {
+    fn update(&self, arm_id: u32, f: impl FnOnce(&mut ArmAllocationStats)) {
+        let mut per_arm = self.per_arm.lock().unwrap();
+        f(per_arm.entry(arm_id).or_default());
+    }
+
+    pub fn record_answer(&self, arm_id: u32) {
+        self.update(arm_id, |s| s.answered += 1);
+    }
+
+    pub fn record_catch(&self, arm_id: u32) {
+        self.update(arm_id, |s| s.catches += 1);
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<u32, ArmAllocationStats> {
+        self.per_arm.lock().unwrap().clone()
+    }
+
+    pub fn unassigned(&self) -> u64 {
+        self.unassigned.load(Ordering::Relaxed)
+    }
+
+    pub fn print_summary(&self) {
+        println!("> Allocation per arm:");
+        for (arm_id, s) in self.snapshot() {
+            println!(
+                ">   arm {}: assigned={} answered={} catches={} out_of_reach={} while_busy={}",
+                arm_id, s.assigned, s.answered, s.catches, s.out_of_reach, s.while_busy
+            );
+        }
+        println!(">   unassigned objects: {}", self.unassigned());
+    }
+}
+
+pub struct Allocator {
+    pub strategy: AllocationStrategy,
+    pub stats: std::sync::Arc<AllocationStats>,
+}
+
+impl Allocator {
+    pub fn new(strategy: AllocationStrategy) -> Self {
+        Allocator {
+            strategy,
+            stats: Default::default(),
+        }
+    }
+
+    /// Strategy from the `ALLOCATION` env var (`nearest` or `intercept`), nearest by default.
+    pub fn from_env() -> Self {
+        let strategy = parse_env("ALLOCATION").unwrap_or(AllocationStrategy::NearestReachable);
+        Self::new(strategy)
+    }
+
+    /// Picks the arm for `object`. Free arms that can reach the object come first,
+    /// then busy arms that can reach it, then whatever arm is closest.
+    /// Only returns None when there are no arms at all.
+    pub fn allocate(&self, object: &ObjectData, arms: &[ArmStatus], now: u128) -> Option<u32> {
+        let target = (object.object_x, object.object_y);
+        let score = |arm: &ArmStatus| -> f64 {
+            match self.strategy {
+                AllocationStrategy::NearestReachable => distance(arm.wrist, target) as f64,
+                AllocationStrategy::EarliestIntercept => arm.intercept_time(object, now) as f64,
+            }
+        };
+        let best = |candidates: &mut dyn Iterator<Item = &ArmStatus>| {
+            candidates.min_by(|a, b| score(a).total_cmp(&score(b))).cloned()
+        };
+
+        let reachable: Vec<&ArmStatus> = arms.iter().filter(|a| a.can_reach(object)).collect();
+        let chosen = best(&mut reachable.iter().copied().filter(|a| !a.is_busy(now)))
+            .or_else(|| best(&mut reachable.iter().copied()))
+            .or_else(|| {
+                arms.iter()
+                    .min_by(|a, b| distance(a.base, target).total_cmp(&distance(b.base, target)))
+                    .cloned()
+            });
+
+        let Some(arm) = chosen else {
+            self.stats.unassigned.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+        let out_of_reach = !arm.can_reach(object);
+        let busy = arm.is_busy(now);
+        self.stats.update(arm.arm_id, |s| {
+            s.assigned += 1;
+            s.out_of_reach += out_of_reach as u64;
+            s.while_busy += busy as u64;
+        });
+        Some(arm.arm_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_structure::SensorArmData;
+
+    fn object(x: f32, y: f32) -> ObjectData {
+        ObjectData {
+            // 1.5m at 10 m/s, 150ms to the ground
+            object_velocity: 10.0,
+            object_mass: 3.0,
+            object_size: 4.5,
+            object_x: x,
+            object_y: y,
+            object_height: 1.5,
+        }
+    }
+
+    // the feedback an arm sends back for `object` at `now`, as the actuator builds it
+    fn answer(arm_id: u32, object: &ObjectData, now: u128) -> ArmStatus {
+        let mut data = SensorArmData::new(object.clone());
+        data.arm_id = arm_id;
+        let eta = now + object.time_to_reach().unwrap() as u128;
+        ArmStatus::from_feedback(&data.to_feedback(eta))
+    }
+
+    #[test]
+    fn arm_stays_busy_for_the_whole_fall() {
+        let now = 1_000_000;
+        let object = object(1.0, 3.0);
+        assert_eq!(object.time_to_reach(), Some(150_000));
+        let arm = answer(1, &object, now);
+        assert!(arm.is_busy(now + 1_000));
+        assert!(arm.is_busy(now + 149_999));
+        assert!(!arm.is_busy(now + 150_000));
+    }
+
+    #[test]
+    fn busy_arm_is_passed_over_until_its_object_lands() {
+        let now = 1_000_000;
+        let allocator = Allocator::new(AllocationStrategy::NearestReachable);
+        let busy = answer(1, &object(0.0, 3.0), now);
+        let free = ArmStatus {
+            wrist: (3.0, 3.0),
+            ..ArmStatus::idle(2)
+        };
+        let arms = [busy, free];
+        // arm 1's wrist is on the next object, but it is still catching the last one
+        let next = object(0.0, 3.0);
+        assert_eq!(allocator.allocate(&next, &arms, now + 100_000), Some(2));
+        assert_eq!(allocator.allocate(&next, &arms, now + 150_000), Some(1));
+    }
+
+    #[test]
+    fn no_velocity_no_time_to_reach() {
+        let object = ObjectData {
+            object_velocity: 0.0,
+            ..object(0.0, 0.0)
+        };
+        assert_eq!(object.time_to_reach(), None);
+    }
+}
@@ -0,0 +1,317 @@
+// bounded hand-off between the 5ms generator and the publisher
+// what happens when the publisher falls behind is an explicit policy, so a slow transport
+// only costs frames and never delays generation, unless blocking is asked for
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+
+use crate::config::parse_env;
+use crate::now_micros;
+
+//overridden by PIPELINE_CAPACITY
+pub const DEFAULT_PIPELINE_CAPACITY: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackpressurePolicy {
+    // wait for room, generation is delayed by the transport
+    Block,
+    // make room by dropping the oldest queued frame
+    DropOldest,
+    // drop the frame being sent, the queue keeps its order
+    DropNewest,
+    // a new frame replaces the queued one with the same key, only the latest per key is kept
+    Coalesce,
+}
+
+impl FromStr for BackpressurePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block" => Ok(BackpressurePolicy::Block),
+            "drop-oldest" => Ok(BackpressurePolicy::DropOldest),
+            "drop-newest" => Ok(BackpressurePolicy::DropNewest),
+            "coalesce" => Ok(BackpressurePolicy::Coalesce),
+            other => Err(format!("unknown backpressure policy: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PipelineStats {
+    pub sent: AtomicU64,
+    pub dropped_oldest: AtomicU64,
+    pub dropped_newest: AtomicU64,
+    pub coalesced: AtomicU64,
+    // sends that had to wait for room, and how long they waited in total
+    pub blocked: AtomicU64,
+    pub blocked_us: AtomicU64,
+    pub peak_depth: AtomicU64,
+}
+
+impl PipelineStats {
+    pub fn print_summary(&self, policy: BackpressurePolicy) {
+        let get = |c: &AtomicU64| c.load(Ordering::Relaxed);
+        println!(
+            "> Pipeline ({:?}): sent={} dropped_oldest={} dropped_newest={} coalesced={} blocked={} ({}µs) peak_depth={}",
+            policy,
+            get(&self.sent),
+            get(&self.dropped_oldest),
+            get(&self.dropped_newest),
+            get(&self.coalesced),
+            get(&self.blocked),
+            get(&self.blocked_us),
+            get(&self.peak_depth)
+        );
+    }
+}
+
+struct Inner<T> {
+    queue: Mutex<VecDeque<T>>,
+    capacity: usize,
+    policy: BackpressurePolicy,
+    key: fn(&T) -> u64,
+    not_empty: Notify,
+    not_full: Notify,
+    closed: AtomicBool,
+    stats: Arc<PipelineStats>,
+}
+
+/// Single producer side, dropping it closes the pipeline once the queue is drained.
+pub struct PipelineSender<T> {
+    inner: Arc<Inner<T>>,
+}
+
+/// Single consumer side.
+pub struct PipelineReceiver<T> {
+    inner: Arc<Inner<T>>,
+}
+
+/// Bounded pipeline with the given policy, `key` groups frames for `Coalesce`.
+pub fn pipeline<T>(
+    capacity: usize,
+    policy: BackpressurePolicy,
+    key: fn(&T) -> u64,
+) -> (PipelineSender<T>, PipelineReceiver<T>) {
+    let inner = Arc::new(Inner {
+        queue: Mutex::new(VecDeque::with_capacity(capacity)),
+        capacity: capacity.max(1),
+        policy,
+        key,
+        not_empty: Notify::new(),
+        not_full: Notify::new(),
+        closed: AtomicBool::new(false),
+        stats: Default::default(),
+    });
+    (
+        PipelineSender {
+            inner: Arc::clone(&inner),
+        },
+        PipelineReceiver { inner },
+    )
+}
+
+/// Policy from `BACKPRESSURE` (`block`, `drop-oldest`, `drop-newest`, `coalesce`), drop-oldest by default,
+/// and capacity from `PIPELINE_CAPACITY`.
+pub fn pipeline_from_env<T>(key: fn(&T) -> u64) -> (PipelineSender<T>, PipelineReceiver<T>) {
+    let policy = parse_env("BACKPRESSURE").unwrap_or(BackpressurePolicy::DropOldest);
+    let capacity = parse_env("PIPELINE_CAPACITY").unwrap_or(DEFAULT_PIPELINE_CAPACITY);
+    pipeline(capacity, policy, key)
+}
+
+impl<T> PipelineSender<T> {
+    pub fn policy(&self) -> BackpressurePolicy {
+        self.inner.policy
+    }
+
+    pub fn stats(&self) -> Arc<PipelineStats> {
+        Arc::clone(&self.inner.stats)
+    }
+
+    /// Queues a frame under the policy, only `Block` ever waits.
+    /// Err hands the frame back when the receiver is gone.
+    pub async fn send(&self, item: T) -> Result<(), T> {
+        let inner = &self.inner;
+        let mut waited_since = None;
+        let mut item = item;
+        loop {
+            if inner.closed.load(Ordering::Acquire) {
+                return Err(item);
+            }
+            match self.offer(item) {
+                Some(full) => {
+                    // only the block policy hands the frame back
+                    item = full;
+                    if waited_since.is_none() {
+                        inner.stats.blocked.fetch_add(1, Ordering::Relaxed);
+                        waited_since = Some(now_micros());
+                    }
+                    // a permit stored by notify_one covers a pop between the check and the wait
+                    inner.not_full.notified().await;
+                }
+                None => {
+                    if let Some(since) = waited_since {
+                        inner
+                            .stats
+                            .blocked_us
+                            .fetch_add(now_micros().saturating_sub(since) as u64, Ordering::Relaxed);
+                    }
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    // queues or drops the frame, Some(frame) when it has to wait for room
+    fn offer(&self, item: T) -> Option<T> {
+        let inner = &self.inner;
+        let stats = &inner.stats;
+        let mut queue = inner.queue.lock().unwrap();
+        match inner.policy {
+            BackpressurePolicy::Coalesce => {
+                let key = (inner.key)(&item);
+                if let Some(queued) = queue.iter_mut().find(|queued| (inner.key)(queued) == key) {
+                    *queued = item;
+                    stats.coalesced.fetch_add(1, Ordering::Relaxed);
+                } else {
+                    if queue.len() >= inner.capacity {
+                        queue.pop_front();
+                        stats.dropped_oldest.fetch_add(1, Ordering::Relaxed);
+                    }
+                    queue.push_back(item);
+                }
+            }
+            _ if queue.len() < inner.capacity => queue.push_back(item),
+            BackpressurePolicy::DropOldest => {
+                queue.pop_front();
+                stats.dropped_oldest.fetch_add(1, Ordering::Relaxed);
+                queue.push_back(item);
+            }
+            BackpressurePolicy::DropNewest => {
+                stats.dropped_newest.fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+            BackpressurePolicy::Block => return Some(item),
+        }
+        stats.peak_depth.fetch_max(queue.len() as u64, Ordering::Relaxed);
+        drop(queue);
+        stats.sent.fetch_add(1, Ordering::Relaxed);
+        inner.not_empty.notify_one();
+        None
+    }
+}
+
+impl<T> Drop for PipelineSender<T> {
+    fn drop(&mut self) {
+        self.inner.closed.store(true, Ordering::Release);
+        self.inner.not_empty.notify_one();
+    }
+}
+
+impl<T> PipelineReceiver<T> {
+    /// Next frame, None once the sender is gone and the queue is drained.
+    pub async fn recv(&mut self) -> Option<T> {
+        loop {
+            if let Some(item) = self.try_recv() {
+                return Some(item);
+            }
+            if self.inner.closed.load(Ordering::Acquire) {
+                // the sender may have queued one last frame before closing
+                return self.try_recv();
+            }
+            self.inner.not_empty.notified().await;
+        }
+    }
+
+    pub fn try_recv(&mut self) -> Option<T> {
+        let item = self.inner.queue.lock().unwrap().pop_front();
+        if item.is_some() {
+            self.inner.not_full.notify_one();
+        }
+        item
+    }
+}
+
+impl<T> Drop for PipelineReceiver<T> {
+    fn drop(&mut self) {
+        self.inner.closed.store(true, Ordering::Release);
+        self.inner.not_full.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // frames are plain numbers, coalesced by their tens
+    fn by_tens(item: &u32) -> u64 {
+        (*item / 10) as u64
+    }
+
+    async fn fill(policy: BackpressurePolicy, items: &[u32]) -> (Vec<u32>, Arc<PipelineStats>) {
+        let (tx, mut rx) = pipeline(2, policy, by_tens);
+        for item in items {
+            tx.send(*item).await.unwrap();
+        }
+        let stats = tx.stats();
+        drop(tx);
+        let mut received = Vec::new();
+        while let Some(item) = rx.recv().await {
+            received.push(item);
+        }
+        (received, stats)
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_keeps_the_latest_frames_at_capacity() {
+        let (received, stats) = fill(BackpressurePolicy::DropOldest, &[10, 20, 30]).await;
+        assert_eq!(received, vec![20, 30]);
+        assert_eq!(stats.dropped_oldest.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.sent.load(Ordering::Relaxed), 3);
+    }
+
+    #[tokio::test]
+    async fn drop_newest_refuses_frames_at_capacity() {
+        let (received, stats) = fill(BackpressurePolicy::DropNewest, &[10, 20, 30]).await;
+        assert_eq!(received, vec![10, 20]);
+        assert_eq!(stats.dropped_newest.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.sent.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn coalesce_replaces_the_queued_frame_with_the_same_key() {
+        let (received, stats) = fill(BackpressurePolicy::Coalesce, &[10, 20, 11]).await;
+        assert_eq!(received, vec![11, 20]);
+        assert_eq!(stats.coalesced.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.dropped_oldest.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn coalesce_drops_the_oldest_key_at_capacity() {
+        let (received, stats) = fill(BackpressurePolicy::Coalesce, &[10, 20, 30]).await;
+        assert_eq!(received, vec![20, 30]);
+        assert_eq!(stats.dropped_oldest.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn block_waits_for_room() {
+        let (tx, mut rx) = pipeline(1, BackpressurePolicy::Block, by_tens);
+        tx.send(10).await.unwrap();
+        assert!(tokio::time::timeout(Duration::from_millis(20), tx.send(20)).await.is_err());
+
+        let send = tokio::spawn(async move {
+            tx.send(30).await.unwrap();
+            tx.stats()
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(rx.recv().await, Some(10));
+        let stats = send.await.unwrap();
+        assert_eq!(rx.recv().await, Some(30));
+        assert_eq!(rx.recv().await, None);
+        assert_eq!(stats.blocked.load(Ordering::Relaxed), 2);
+    }
+}
@@ -1,50 +1,202 @@
 #![allow(unused_imports, unused_variables, unused_mut)]
-use futures_util::stream::StreamExt;
+use futures_util::{stream::StreamExt, FutureExt};
+use lapin::message::Delivery;
 use lapin::BasicProperties;
 use lapin::{options::*, types::FieldTable, Channel, Connection, ConnectionProperties, Consumer};
-use serde_json;
 use std::f32::consts::PI;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::sync::mpsc::{self, UnboundedSender};
 use tokio::sync::Barrier;
-use Real_time_systems_repo::{data_structure::*, now_micros};
+use Real_time_systems_repo::{
+    broker::*, config::*, data_structure::*, estop::*, freshness::*, grip::*, obstacles::*, safety::*, telemetry::*, heartbeat::*, histogram::*, latency_log::*, netfault::*, now_micros, recording::*, registry::*, routing::*,
+    shutdown::*,
+};
+
+// arm id from `--arm <id>`, then the ARM_ID env var, then the default arm
+fn arm_id_from_args() -> u32 {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--arm" {
+            return args
+                .next()
+                .and_then(|id| id.parse().ok())
+                .expect("--arm expects a numeric arm id");
+        }
+    }
+    parse_env("ARM_ID").unwrap_or(DEFAULT_ARM_ID)
+}
+
+// what this actuator tells the controller about itself when registering
+fn registration(arm_id: u32) -> ArmRegistration {
+    ArmRegistration {
+        arm_id,
+        model: std::env::var("ARM_MODEL").unwrap_or_else(|_| "two_link_planar".to_string()),
+        reach: ARM_REACH,
+        capabilities: vec!["shoulder".to_string(), "elbow".to_string(), "feedback".to_string()],
+        timestamp: now_micros(),
+    }
+}
+
+// feedback for one cycle of one arm, already serialized
+type PendingFeedback = (u64, u32, Vec<u8>);
+
+// the feedback link with an outbox behind it, feedback the link failed to deliver goes out again with the next
+struct FeedbackLink {
+    link: FaultyLink,
+    // OUTBOX_CAPACITY / OUTBOX_POLICY, as for the controller's frames
+    outbox: Outbox<PendingFeedback>,
+    failed_tx: mpsc::UnboundedSender<PendingFeedback>,
+    failed_rx: mpsc::UnboundedReceiver<PendingFeedback>,
+}
+
+impl FeedbackLink {
+    fn new(link: FaultyLink) -> Self {
+        let (failed_tx, failed_rx) = mpsc::unbounded_channel();
+        FeedbackLink {
+            link,
+            outbox: Outbox::from_env(),
+            failed_tx,
+            failed_rx,
+        }
+    }
+
+    // a closed channel fails before anything is queued, so the frame is redelivered like without the link
+    fn send(&mut self, channel: &Channel, feedback: PendingFeedback) -> lapin::Result<()> {
+        self.outbox.requeue_failed(&mut self.failed_rx);
+        if !channel.status().connected() {
+            return Err(lapin::Error::InvalidChannelState(channel.status().state()));
+        }
+        self.outbox.push(feedback);
+        while let Some((cycle, arm_id, payload)) = self.outbox.pop() {
+            let routing_key = feedback_routing_key(arm_id);
+            let mut copy = 0;
+            self.link.send(cycle, arm_id, || {
+                let channel = channel.clone();
+                let routing_key = routing_key.clone();
+                let payload = payload.clone();
+                // a duplicate that fails was never the network's to resend
+                let failed = (copy == 0).then(|| self.failed_tx.clone());
+                copy += 1;
+                async move {
+                    if let Err(e) = publish_feedback(&channel, &routing_key, &payload).await {
+                        eprintln!("Failed to deliver feedback for cycle {}: {:?}", cycle, e);
+                        if let Some(failed) = failed {
+                            let _ = failed.send((cycle, arm_id, payload));
+                        }
+                    }
+                }
+            });
+        }
+        Ok(())
+    }
+
+    // delivers what is still in flight, whatever failed on the way out counts as undelivered
+    async fn close(mut self) {
+        self.link.close().await;
+        self.outbox.requeue_failed(&mut self.failed_rx);
+        println!(
+            "> Feedback outbox ({:?}): dropped={} peak={} undelivered={}",
+            self.outbox.policy,
+            self.outbox.dropped(),
+            self.outbox.peak(),
+            self.outbox.len()
+        );
+    }
+}
+
+// the planner and the safety envelope, shared with the watchdog so a park passes the same checks as a frame
+struct Motion {
+    planner: MotionPlanner,
+    safety: SafetyEnvelope,
+}
+
+impl Motion {
+    fn new(workspace: Workspace, safety_config: SafetyConfig) -> Self {
+        // both links are half the reach
+        let planner = MotionPlanner::new(workspace, ARM_REACH / 2.0, ARM_REACH / 2.0);
+        let safety = SafetyEnvelope::new(safety_config, planner.l1, planner.l2);
+        Motion { planner, safety }
+    }
+
+    // sends the arm towards its rest pose, as far as the envelope lets it go in one move
+    fn park(
+        &mut self,
+        arm_id: u32,
+        shoulder_tx: &mpsc::UnboundedSender<ActuatorInstruction>,
+        elbow_tx: &mpsc::UnboundedSender<ActuatorInstruction>,
+    ) -> Option<SafetyReport> {
+        let (mut shoulder, mut elbow) = park_instructions(arm_id);
+        let report = self.safety.check(&mut shoulder, &mut elbow, &self.planner.workspace);
+        // the next plan starts from where the joints were actually sent
+        self.planner.set_current(self.safety.angles());
+        if report.as_ref().is_none_or(|report| report.action == LimitAction::Clamp) {
+            let _ = shoulder_tx.send(shoulder);
+            let _ = elbow_tx.send(elbow);
+        }
+        report
+    }
+}
+
+// rest pose the arm is moved to when parked, same as `SensorArmData::new`
+fn park_instructions(arm_id: u32) -> (ActuatorInstruction, ActuatorInstruction) {
+    let instruction = |x, y, angle| ActuatorInstruction {
+        arm_id,
+        x,
+        y,
+        strength: 0.0,
+        angle,
+        time_to_reach: 0,
+        timestamp: now_micros(),
+        trace: FrameTrace::default(),
+    };
+    (instruction(0.0, 0.0, REST_ANGLES.0), instruction(0.0, 3.0, REST_ANGLES.1))
+}
 
 #[tokio::main]
 async fn main() {
-    start().await;
+    start(arm_id_from_args()).await;
 }
 
 //start function
-pub async fn start() {
-    let channel = create_channel().await;
-
-    // Set up mpsc channel for latency logging
-    let (lat_tx, lat_rx) = mpsc::unbounded_channel();
-    let (lat_shoulder_tx, lat_shoulder_rx) = mpsc::unbounded_channel();
-    let (lat_elbow_tx, lat_elbow_rx) = mpsc::unbounded_channel();
-    let (cycle_tx, cycle_rx) = mpsc::unbounded_channel();
+pub async fn start(arm_id: u32) {
+    // Ctrl-C / SIGTERM stops consuming, parks the arm and flushes the logs
+    let shutdown = Shutdown::new();
+    shutdown.trigger_on_signal();
+    // one log per arm so several actuators can run from the same directory
+    let run = RunInfo::from_env(&format!("actuator_{}", arm_id));
+    let histograms = Arc::new(LatencyHistograms::new(&ACTUATOR_STAGES));
+    let snapshot_handle = tokio::spawn(start_histogram_snapshots(
+        Arc::clone(&histograms),
+        run.clone(),
+        std::time::Duration::from_secs(1),
+    ));
+
+    // every stage latency goes through one bounded queue to a single writer, TELEMETRY_* variables
+    let (telemetry_tx, telemetry) = Telemetry::start(&run, Arc::clone(&histograms), TelemetryConfig::from_env());
     // channels for joint tasks
     let (shoulder_tx, mut shoulder_rx) = mpsc::unbounded_channel::<ActuatorInstruction>();
     let (elbow_tx, mut elbow_rx) = mpsc::unbounded_channel::<ActuatorInstruction>();
 
     let sync_barrier = Arc::new(Barrier::new(2)); // Two parties: shoulder and elbow
 
-    // Thread 2: Log latency
-    tokio::spawn(start_latency(
-        lat_rx,
-        lat_elbow_rx,
-        lat_shoulder_rx,
-        cycle_rx,
-    ))
-    .await
-    .expect("Failed to spawn latency thread");
+    // emergency stops arrive on their own connection so they never wait behind sensor frames
+    let estop = Arc::new(EStopLatch::default());
+    let estop_handle = tokio::spawn(consume_estops(arm_id, Arc::clone(&estop), shutdown.clone()));
 
     //SPAWN SHOULDER JOINT, ELBOW JOINT THREADS and CHANNEL
     // shoudler thread
     let shoulder_barrier = Arc::clone(&sync_barrier);
-    tokio::spawn(async move {
+    let shoulder_estop = Arc::clone(&estop);
+    let shoulder_telemetry = telemetry_tx.clone();
+    let shoulder_handle = tokio::spawn(async move {
         while let Some(pos) = shoulder_rx.recv().await {
+            if shoulder_estop.is_engaged() {
+                // queued instruction cancelled, the joint stays where it is
+                shoulder_estop.cancel();
+                shoulder_barrier.wait().await;
+                continue;
+            }
             let start_time = now_micros();
             // println!("[SHOULDER] Moving to position: {:?}", pos);
             // tokio::time::sleep(tokio::time::Duration::from_micros(pos.time_to_reach)).await; // simulate actuation time
@@ -53,17 +205,22 @@ pub async fn start() {
             //     now_micros().saturating_sub(start_time),
             //     pos.time_to_reach
             // );
-            lat_shoulder_tx
-                .send(start_time)
-                .expect("Failed to send shoulder latency");
+            shoulder_telemetry.record("shoulder_actuation", pos.trace.cycle, start_time, now_micros());
             shoulder_barrier.wait().await; // wait for elbow to finish
         }
     });
 
     //elbow thread
     let elbow_barrier = Arc::clone(&sync_barrier);
-    tokio::spawn(async move {
+    let elbow_estop = Arc::clone(&estop);
+    let elbow_telemetry = telemetry_tx.clone();
+    let elbow_handle = tokio::spawn(async move {
         while let Some(pos) = elbow_rx.recv().await {
+            if elbow_estop.is_engaged() {
+                elbow_estop.cancel();
+                elbow_barrier.wait().await;
+                continue;
+            }
             let start_time = now_micros();
             // println!("[ELBOW] Moving to position: {:?}", pos);
             // tokio::time::sleep(tokio::time::Duration::from_micros(pos.time_to_reach)).await; // simulate actuation time
@@ -72,235 +229,590 @@ pub async fn start() {
             //     now_micros().saturating_sub(start_time),
             //     pos.time_to_reach
             // );
-            lat_elbow_tx
-                .send(start_time)
-                .expect("Failed to send shoulder latency");
+            elbow_telemetry.record("elbow_actuation", pos.trace.cycle, start_time, now_micros());
             elbow_barrier.wait().await; // wait for shoulder to finish
         }
     });
 
-    // Thread 1: Simulate arm
-    let _ = tokio::spawn(consume_sensor_data(
-        channel.clone(),
-        lat_tx,
-        shoulder_tx,
-        elbow_tx,
-        cycle_tx,
+    // OBSTACLES_FILE and SAFETY_FILE, every move and park is planned and checked here
+    let motion = Arc::new(Mutex::new(Motion::new(Workspace::from_env(), SafetyConfig::from_env())));
+
+    // watchdog over the controller's heartbeats, CONTROLLER_TIMEOUT_MS
+    let watchdog = Arc::new(Watchdog::new(duration_from_env(
+        "CONTROLLER_TIMEOUT_MS",
+        DEFAULT_CONTROLLER_TIMEOUT,
+    )));
+    let watchdog_handle = tokio::spawn(watch_controller(
+        arm_id,
+        Arc::clone(&watchdog),
+        SafeState::from_env(),
+        Arc::clone(&motion),
+        shoulder_tx.clone(),
+        elbow_tx.clone(),
+    ));
+
+    // NET_FAULTS_FILE delays, drops, duplicates or reorders the feedback on its way to the controller
+    let feedback_link = FaultyLink::from_env("feedback", &run).map(FeedbackLink::new);
+    // how hard the hand closes on each object, GRIP_* variables
+    let grip_model = GripModel::from_env();
+
+    // Thread 1: Simulate arm, returns once shutdown is requested
+    let frames = tokio::spawn(consume_sensor_data(
+        arm_id,
+        Arc::clone(&watchdog),
+        Arc::clone(&estop),
+        shutdown.clone(),
+        telemetry_tx,
+        shoulder_tx.clone(),
+        elbow_tx.clone(),
+        feedback_link,
+        grip_model.clone(),
+        Arc::clone(&motion),
     ))
-    .await;
+    .await
+    .expect("Sensor consumer panicked");
+
+    watchdog_handle.abort();
+    // park the arm unless an emergency stop holds it frozen, then let the joints finish
+    if estop.is_engaged() {
+        println!("> Arm {} left frozen under emergency stop", arm_id);
+    } else {
+        match motion.lock().unwrap().park(arm_id, &shoulder_tx, &elbow_tx) {
+            None => println!("> Arm {} parked", arm_id),
+            Some(report) => println!("> Arm {} park {:?}: {:?}", arm_id, report.action, report.violations),
+        }
+    }
+    drop(shoulder_tx);
+    drop(elbow_tx);
+    let _ = shoulder_handle.await;
+    let _ = elbow_handle.await;
+    // the joint tasks held the last telemetry senders, the writer drains the queue and exits
+    let telemetry_stats = telemetry.finish().await;
+    let _ = estop_handle.await;
+    snapshot_handle.abort();
+    if let Err(e) = histograms.write_snapshot(&run, snapshot_path(&run)) {
+        eprintln!("Failed to write histogram snapshot: {}", e);
+    }
+
+    println!("> Arm {} summary, run {}:", arm_id, run.run_id);
+    println!(
+        ">   frames handled={} dropped in safe state={} dropped under emergency stop={}",
+        frames.handled, frames.dropped_in_safe_state, frames.dropped_while_stopped
+    );
+    println!(
+        ">   stale frames dropped={} superseded by newer={}",
+        frames.stale, frames.superseded
+    );
+    frames.grip.print_summary(&grip_model);
+    let motion = motion.lock().unwrap();
+    motion.planner.stats.print_summary("  motion plans");
+    motion.safety.print_summary();
+    println!(">   controller watchdog tripped {} time(s)", watchdog.trips());
+    println!(
+        ">   emergency stops: {}, instructions cancelled: {}",
+        estop.stops(),
+        estop.cancelled()
+    );
+    telemetry_stats.print_summary();
+    histograms.print_summary();
+    if let Err(e) = histograms.write_distribution(&run, distribution_path(&run)) {
+        eprintln!("Failed to write latency histograms: {}", e);
+    }
+    println!("Shutdown complete. Exiting.");
+}
+
+// what the frame consumer did over the whole run
+struct FrameCounts {
+    handled: u64,
+    dropped_in_safe_state: u64,
+    dropped_while_stopped: u64,
+    stale: u64,
+    superseded: u64,
+    grip: GripStats,
 }
 
-async fn create_channel() -> Channel {
-    let conn = Connection::connect("amqp://127.0.0.1:5672/%2f", ConnectionProperties::default())
-        .await
-        .expect("Connection error");
+// tells the controller this arm is leaving, it times the arm out otherwise
+async fn leave(channel: &Channel, arm_id: u32) {
+    let message = RegistryMessage::Deregister {
+        arm_id,
+        timestamp: now_micros(),
+    };
+    if let Err(e) = announce(channel, &message).await {
+        eprintln!("Failed to deregister arm {}: {:?}", arm_id, e);
+    }
+}
 
-    // Create a channel
-    let channel = conn.create_channel().await.expect("Channel creation error");
+// unacked frames the broker may push ahead with latest-value semantics, the newest of them is acted on
+const LATEST_PREFETCH: u16 = 32;
 
-    // Set Quality of Service
-    channel
-        .basic_qos(1, BasicQosOptions::default())
-        .await
-        .expect("Failed to set QoS");
+// per session setup of the main channel: the prefetch window and this arm's queues
+async fn setup_channel(channel: Channel, arm_id: u32, prefetch: u16) -> lapin::Result<()> {
+    channel.basic_qos(prefetch, BasicQosOptions::default()).await?;
+    declare_actuator_topology(&channel, arm_id).await
+}
 
-    // Declare the queue to consume from
-    channel
-        .queue_declare(
-            "sensor_data",
-            QueueDeclareOptions::default(),
+// acks or nacks a frame, a failure means the connection is gone and the consumer stream ends next
+async fn settle(delivery: &Delivery, ack: bool) {
+    let result = if ack {
+        delivery.ack(Default::default()).await
+    } else {
+        delivery.nack(Default::default()).await
+    };
+    if let Err(e) = result {
+        eprintln!("Failed to settle frame: {:?}", e);
+    }
+}
+
+// latches stops and releases addressed to this arm or to every arm,
+// on a connection of its own that is re-established whenever it drops
+async fn consume_estops(arm_id: u32, estop: Arc<EStopLatch>, shutdown: Shutdown) {
+    let mut broker = Broker::new(&format!("estop_{}", arm_id));
+    loop {
+        let (conn, channel) = tokio::select! {
+            session = broker.connect(|channel| async move { declare_estop_topology(&channel, arm_id).await }) => session,
+            _ = shutdown.wait() => return,
+        };
+        let consumer = channel
+            .basic_consume(
+                &estop_queue(arm_id),
+                &format!("estop_consumer_{}", arm_id),
+                BasicConsumeOptions {
+                    no_ack: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await;
+        match consumer {
+            Ok(consumer) => {
+                tokio::select! {
+                    _ = apply_estops(consumer, arm_id, &estop) => broker.lost("emergency stop consumer closed"),
+                    _ = shutdown.wait() => {
+                        close_session(&conn, &channel).await;
+                        return;
+                    }
+                }
+            }
+            Err(e) => broker.lost(e),
+        }
+    }
+}
+
+async fn apply_estops(mut consumer: Consumer, arm_id: u32, estop: &EStopLatch) {
+    while let Some(Ok(delivery)) = consumer.next().await {
+        let stop = match serde_json::from_slice::<EmergencyStop>(&delivery.data) {
+            Ok(stop) => stop,
+            Err(e) => {
+                eprintln!("Failed to deserialize emergency stop: {:?}", e);
+                continue;
+            }
+        };
+        if !stop.applies_to(arm_id) || !estop.apply(&stop) {
+            continue;
+        }
+        if stop.engaged {
+            println!(
+                "> EMERGENCY STOP arm {} from {}: {}, joints frozen",
+                arm_id, stop.source, stop.reason
+            );
+        } else {
+            println!("> Emergency stop released for arm {} by {}", arm_id, stop.source);
+        }
+    }
+}
+
+// registers with the controller, then sends heartbeats and feeds the watchdog with the controller's,
+// registers again whenever the controller's heartbeat no longer lists this arm
+async fn run_heartbeats(channel: Channel, arm_id: u32, watchdog: Arc<Watchdog>) {
+    let message = RegistryMessage::Register(registration(arm_id));
+    if let Err(e) = announce(&channel, &message).await {
+        eprintln!("Failed to register arm {}: {:?}", arm_id, e);
+    }
+
+    let consumer = channel
+        .basic_consume(
+            &arm_heartbeat_queue(arm_id),
+            &format!("heartbeat_consumer_{}", arm_id),
+            BasicConsumeOptions {
+                no_ack: true,
+                ..Default::default()
+            },
             FieldTable::default(),
         )
-        .await
-        .expect("Queue declaration error");
+        .await;
+    // the sensor consumer notices the lost connection and starts a new session
+    let Ok(mut consumer) = consumer else {
+        return;
+    };
 
-    channel
+    let mut interval = tokio::time::interval(heartbeat_interval());
+    let mut sequence = 0u64;
+    loop {
+        tokio::select! {
+            maybe_delivery = consumer.next() => {
+                let Some(Ok(delivery)) = maybe_delivery else {
+                    break;
+                };
+                let heartbeat = match serde_json::from_slice::<Heartbeat>(&delivery.data) {
+                    Ok(heartbeat) => heartbeat,
+                    Err(e) => {
+                        eprintln!("Failed to deserialize heartbeat: {:?}", e);
+                        continue;
+                    }
+                };
+                // heartbeats queued while this arm was down say nothing about the controller now
+                if now_micros().saturating_sub(heartbeat.timestamp) > watchdog.timeout.as_micros() {
+                    continue;
+                }
+                watchdog.feed();
+                if !heartbeat.arms.contains(&arm_id) {
+                    let message = RegistryMessage::Register(registration(arm_id));
+                    if let Err(e) = announce(&channel, &message).await {
+                        eprintln!("Failed to register arm {}: {:?}", arm_id, e);
+                    }
+                }
+            }
+            _ = interval.tick() => {
+                sequence += 1;
+                let heartbeat = Heartbeat {
+                    arm_id: Some(arm_id),
+                    sequence,
+                    timestamp: now_micros(),
+                    arms: Vec::new(),
+                };
+                if let Err(e) = send_heartbeat(&channel, &heartbeat).await {
+                    eprintln!("Failed to send heartbeat for arm {}: {:?}", arm_id, e);
+                }
+            }
+        }
+    }
 }
 
-async fn consume_sensor_data(
-    channel: Channel,
-    lat_tx: mpsc::UnboundedSender<u128>,
+// moves the arm to its safe state while the controller is silent
+async fn watch_controller(
+    arm_id: u32,
+    watchdog: Arc<Watchdog>,
+    safe_state: SafeState,
+    motion: Arc<Mutex<Motion>>,
     shoulder_tx: mpsc::UnboundedSender<ActuatorInstruction>,
     elbow_tx: mpsc::UnboundedSender<ActuatorInstruction>,
-    cycle_tx: mpsc::UnboundedSender<u128>,
 ) {
-    let mut consumer: Consumer = channel
-        .basic_consume(
-            "sensor_data",
-            "actuator_consumer",
-            BasicConsumeOptions::default(),
-            FieldTable::default(),
-        )
-        .await
-        .expect("Basic consume error");
+    let mut interval = tokio::time::interval(WATCHDOG_PERIOD);
+    loop {
+        interval.tick().await;
+        match watchdog.check() {
+            Some(WatchdogEvent::Tripped) => {
+                println!(
+                    "> Controller silent for over {:?}, arm {} entering {:?}",
+                    watchdog.timeout, arm_id, safe_state
+                );
+                if safe_state == SafeState::Park {
+                    if let Some(report) = motion.lock().unwrap().park(arm_id, &shoulder_tx, &elbow_tx) {
+                        println!("> Arm {} park {:?}: {:?}", arm_id, report.action, report.violations);
+                    }
+                }
+            }
+            Some(WatchdogEvent::Recovered) => {
+                println!("> Controller heartbeat back, arm {} resuming", arm_id);
+            }
+            None => {}
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn consume_sensor_data(
+    arm_id: u32,
+    watchdog: Arc<Watchdog>,
+    estop: Arc<EStopLatch>,
+    shutdown: Shutdown,
+    telemetry: TelemetrySender,
+    shoulder_tx: mpsc::UnboundedSender<ActuatorInstruction>,
+    elbow_tx: mpsc::UnboundedSender<ActuatorInstruction>,
+    mut feedback_link: Option<FeedbackLink>,
+    grip_model: GripModel,
+    motion: Arc<Mutex<Motion>>,
+) -> FrameCounts {
+    let mut broker = Broker::new(&format!("actuator_{}", arm_id));
 
     // let mut latencies = Vec::new();
     let mut total_msgs = 0u64;
     let mut missed_deadlines = 0u64;
-    let mut cycles = 0;
-
-    println!("> Actuator is ready to receive sensor data...");
-
-    while let Some(delivery) = consumer.next().await {
-        cycles += 1;
-        let delivery = match delivery {
-            Ok(d) => d,
+    let mut cycles = 0u64;
+    let mut dropped_in_safe_state = 0u64;
+    let mut dropped_while_stopped = 0u64;
+    // MAX_FRAME_AGE_MS and LATEST_ONLY, a backlog is skipped rather than replayed
+    let freshness = Freshness::frames();
+    // one frame at a time unless skipping to the newest
+    let prefetch = if freshness.latest_only { LATEST_PREFETCH } else { 1 };
+    // RECORD=1 keeps every frame received, for replaying later
+    let mut recorder = Recorder::from_env(&format!("actuator_{}", arm_id));
+    let mut grip_stats = GripStats::default();
+
+    loop {
+        // a new session re-declares the queues, the consumer and the heartbeats
+        let (conn, channel) = tokio::select! {
+            session = broker.connect(|channel| setup_channel(channel, arm_id, prefetch)) => session,
+            _ = shutdown.wait() => break,
+        };
+        let consumer = channel
+            .basic_consume(
+                &sensor_queue(arm_id),
+                &format!("actuator_consumer_{}", arm_id),
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+            )
+            .await;
+        let mut consumer: Consumer = match consumer {
+            Ok(consumer) => consumer,
             Err(e) => {
-                eprintln!("Consumer stream error: {:?}", e);
+                broker.lost(e);
                 continue;
             }
         };
+        let heartbeats = tokio::spawn(run_heartbeats(channel.clone(), arm_id, Arc::clone(&watchdog)));
+
+        println!("> Actuator for arm {} is ready to receive sensor data...", arm_id);
+
+        loop {
+            // the frame being handled is finished before shutdown is looked at
+            let delivery = tokio::select! {
+                delivery = consumer.next() => delivery,
+                _ = shutdown.wait() => None,
+            };
+            if shutdown.is_triggered() {
+                heartbeats.abort();
+                leave(&channel, arm_id).await;
+                // feedback still held back by the link goes out before the connection closes
+                if let Some(link) = feedback_link.take() {
+                    link.close().await;
+                }
+                close_session(&conn, &channel).await;
+                break;
+            }
+            let Some(delivery) = delivery else {
+                break;
+            };
+            cycles += 1;
+            let mut delivery = match delivery {
+                Ok(d) => d,
+                Err(e) => {
+                    eprintln!("Consumer stream error: {:?}", e);
+                    break;
+                }
+            };
+            if freshness.latest_only {
+                // frames already delivered behind this one are newer, the arm only chases the newest
+                while let Some(Some(Ok(newer))) = consumer.next().now_or_never() {
+                    if let Some(recorder) = &mut recorder {
+                        recorder.record(Stream::Sensor, arm_id, &delivery.data, now_micros());
+                    }
+                    settle(&delivery, true).await;
+                    freshness.supersede();
+                    delivery = newer;
+                }
+            }
 
-        let payload = &delivery.data;
+            let payload = &delivery.data;
 
-        let mut sensor_data: SensorArmData = match serde_json::from_slice(payload) {
-            Ok(data) => data,
-            Err(e) => {
-                eprintln!("Failed to deserialize sensor data: {:?}", e);
-                delivery
-                    .nack(Default::default())
-                    .await
-                    .expect("Failed to nack");
+            let mut sensor_data: SensorArmData = match serde_json::from_slice(payload) {
+                Ok(data) => data,
+                Err(e) => {
+                    eprintln!("Failed to deserialize sensor data: {:?}", e);
+                    settle(&delivery, false).await;
+                    continue;
+                }
+            };
+            sensor_data.trace.received = now_micros();
+            if let Some(recorder) = &mut recorder {
+                recorder.record(Stream::Sensor, sensor_data.arm_id, payload, sensor_data.trace.received);
+            }
+            if freshness.is_stale(sensor_data.timestamp) {
+                // the object has moved on, acting on this frame would chase where it used to be
+                settle(&delivery, true).await;
                 continue;
             }
-        };
-        // println!("> Received sensor data: {:?}", sensor_data);
-        let reception_latency = now_micros().saturating_sub(sensor_data.timestamp);
-        println!("> Reception Latency: {} µs\n", reception_latency);
-
-        if cycles < 500 {
-            println!("> Warming up, skipping cycle: {}", cycles);
-            delivery
-                .nack(Default::default())
-                .await
-                .expect("Failed to nack");
-            continue; // skip first 500 cycles - warm up
-        }
-
-        // lat_tx
-        //     .send(sensor_data.timestamp)
-        //     .expect("Failed to send receive time for latency calculation");
-
-        // cycle starts after receiving data is done
-        let cycle_start_time = now_micros();
-
-        total_msgs += 1;
-        println!("> Message count: {:?}", total_msgs);
-
-        let receive_time = now_micros();
+            if sensor_data.arm_id != arm_id {
+                eprintln!("Frame for arm {} routed to arm {}, dropping", sensor_data.arm_id, arm_id);
+                settle(&delivery, false).await;
+                continue;
+            }
+            if estop.is_engaged() {
+                dropped_while_stopped += 1;
+                settle(&delivery, true).await;
+                continue;
+            }
+            if watchdog.is_tripped() {
+                // the controller is gone, whatever is still queued is not acted on
+                dropped_in_safe_state += 1;
+                settle(&delivery, true).await;
+                continue;
+            }
+            // println!("> Received sensor data: {:?}", sensor_data);
+            let reception_latency = now_micros().saturating_sub(sensor_data.timestamp);
+            println!("> Reception Latency: {} µs\n", reception_latency);
+
+            if cycles < 500 {
+                println!("> Warming up, skipping cycle: {}", cycles);
+                settle(&delivery, false).await;
+                continue; // skip first 500 cycles - warm up
+            }
 
-        // Process and send response
-        control_arm(
-            &channel,
-            sensor_data,
-            receive_time,
-            &shoulder_tx,
-            &elbow_tx,
-            &cycle_tx,
-            cycle_start_time,
-        )
-        .await;
+            // from the controller publishing the frame to this actuator receiving it
+            telemetry.record("data_reception", sensor_data.trace.cycle, sensor_data.timestamp, sensor_data.trace.received);
+
+            // cycle starts after receiving data is done
+            let cycle_start_time = now_micros();
+
+            total_msgs += 1;
+            println!("> Message count: {:?}", total_msgs);
+
+            let receive_time = now_micros();
+
+            // the controller's strength is only a suggestion, the hand applies what the object needs
+            let grip = grip_model.assess(&sensor_data.object_data, sensor_data.arm_strength);
+            grip_stats.record(&grip_model, &grip);
+            sensor_data.arm_strength = grip.applied;
+
+            // Process and send response
+            let sent = control_arm(
+                &channel,
+                sensor_data,
+                receive_time,
+                &shoulder_tx,
+                &elbow_tx,
+                &telemetry,
+                cycle_start_time,
+                grip,
+                &motion,
+                feedback_link.as_mut(),
+            )
+            .await;
+            if let Err(e) = sent {
+                // the frame stays unacked and is delivered again after reconnecting
+                eprintln!("Failed to send feedback: {:?}", e);
+                break;
+            }
 
-        delivery
-            .ack(Default::default())
-            .await
-            .expect("Failed to ack");
+            settle(&delivery, true).await;
+        }
+        heartbeats.abort();
+        if shutdown.is_triggered() {
+            break;
+        }
+        broker.lost("sensor consumer closed");
+        println!(
+            "> Session ended after {} frames, dropped {} in safe state and {} under emergency stop, {:?} downtime so far",
+            total_msgs,
+            dropped_in_safe_state,
+            dropped_while_stopped,
+            broker.stats.downtime()
+        );
+    }
+    if let Some(link) = feedback_link {
+        link.close().await;
+    }
+    if let Some(recorder) = recorder {
+        recorder.finish();
+    }
+    broker.stats.print_summary(&broker.name);
+    FrameCounts {
+        handled: total_msgs,
+        dropped_in_safe_state,
+        dropped_while_stopped,
+        stale: freshness.stale(),
+        superseded: freshness.superseded(),
+        grip: grip_stats,
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn control_arm(
     channel: &Channel,
     mut data: SensorArmData,
     receive_time: u128,
     shoulder_tx: &mpsc::UnboundedSender<ActuatorInstruction>,
     elbow_tx: &mpsc::UnboundedSender<ActuatorInstruction>,
-    cycle_tx: &mpsc::UnboundedSender<u128>,
+    telemetry: &TelemetrySender,
     cycle_start_time: u128,
-) {
+    mut grip: GripAssessment,
+    motion: &Mutex<Motion>,
+    feedback_link: Option<&mut FeedbackLink>,
+) -> lapin::Result<()> {
     // println!("Executing control for sensor data: {:?}", data);
     // target never goes negative x
     let mut target_x = data.object_data.object_x;
     let mut target_y = data.object_data.object_y;
 
-    // Arm segment lengths
-    let l1 = 3.0; // shoulder to elbow
-    let l2 = 3.0; // elbow to wrist
-
-    // Distance to target from shoulder (0, 0)
-    let dist = (target_x.powi(2) + target_y.powi(2)).sqrt();
-
-    // Clamp target if beyond max reach
-    let (clamped_x, clamped_y) = if dist > l1 + l2 {
-        let scale = (l1 + l2) / dist;
-        (target_x * scale, target_y * scale)
-    } else {
-        (target_x, target_y)
-    };
-
-    // Inverse kinematics: calculate angles
-    let cos_theta2 =
-        ((clamped_x.powi(2) + clamped_y.powi(2)) - l1.powi(2) - l2.powi(2)) / (2.0 * l1 * l2);
-    let cos_theta2 = cos_theta2.clamp(-1.0, 1.0); // prevent NaNs
-    let theta2 = cos_theta2.acos(); // elbow angle
-
-    let k1 = l1 + l2 * theta2.cos();
-    let k2 = l2 * theta2.sin();
-    let theta1 = clamped_y.atan2(clamped_x) - k2.atan2(k1); // shoulder angle
-
-    // Calculate joint positions
-    let shoulder_x = 0.0;
-    let shoulder_y = 0.0;
-
-    let elbow_x = shoulder_x + l1 * theta1.cos();
-    let elbow_y = shoulder_y + l1 * theta1.sin();
-
-    let wrist_x = elbow_x + l2 * (theta1 + theta2).cos();
-    let wrist_y = elbow_y + l2 * (theta1 + theta2).sin();
-
-    // Set new joint and wrist positions
-    data.joints.shoulder_x = shoulder_x;
-    data.joints.shoulder_y = shoulder_y;
-
-    data.elbow.elbow_x = elbow_x;
-    data.elbow.elbow_y = elbow_y;
-
-    data.wrist.wrist_x = wrist_x;
-    data.wrist.wrist_y = wrist_y;
-
     // === NEW: Estimate time until object reaches ground ===
-    let object_height = data.object_data.object_height;
-    let object_velocity = data.object_data.object_velocity;
-
-    let time_to_reach = if object_velocity > 0.0 {
-        (object_height / object_velocity * 1000.00) as u64
-    } else {
+    // µs, like the timestamps it is added to
+    let time_to_reach = data.object_data.time_to_reach().unwrap_or_else(|| {
         println!(
             "[WARNING] Object velocity is zero or negative ({}). Cannot compute time to reach.",
-            object_velocity
+            data.object_data.object_velocity
         );
         0
-    };
+    });
 
     // println!("> Estimated time to reach ground: {} µs", time_to_reach);
 
     //send with time message received to measure latency from message received to actuator execution
-    let _ = shoulder_tx.send(ActuatorInstruction {
-        x: shoulder_x,
-        y: shoulder_y,
-        strength: data.arm_strength,
-        time_to_reach: time_to_reach,
-        timestamp: cycle_start_time,
-    });
-    let _ = elbow_tx.send(ActuatorInstruction {
-        x: elbow_x,
-        y: elbow_y,
-        strength: data.arm_strength,
-        time_to_reach: time_to_reach,
-        timestamp: cycle_start_time,
-    });
+    // held until the move is sent, so a watchdog park never lands in the middle of one
+    let (pose, plan, safety_report) = {
+        let mut motion = motion.lock().unwrap();
+        let Motion { planner, safety } = &mut *motion;
+
+        // Inverse kinematics, checked against the obstacles all the way from the current pose
+        let (mut pose, plan) = planner.plan((target_x, target_y));
+
+        // a rejected move leaves the joints where they are
+        let mut safety_report = None;
+        if !matches!(plan, PlanResult::Rejected { .. }) {
+            let (shoulder_angle, elbow_angle) = planner.current_angles();
+            let instruction = |(x, y): (f32, f32), angle| ActuatorInstruction {
+                arm_id: data.arm_id,
+                x,
+                y,
+                strength: data.arm_strength,
+                angle,
+                time_to_reach,
+                timestamp: cycle_start_time,
+                trace: data.trace.clone(),
+            };
+            let mut shoulder = instruction(pose.shoulder, shoulder_angle);
+            let mut elbow = instruction(pose.elbow, elbow_angle);
+            // nothing reaches the joints outside the safety envelope
+            safety_report = safety.check(&mut shoulder, &mut elbow, &planner.workspace);
+            if safety_report.is_some() {
+                // the next plan starts from where the joints were actually sent
+                planner.set_current(safety.angles());
+                pose = planner.current_pose();
+                if shoulder.strength < data.arm_strength {
+                    grip = grip.with_applied(shoulder.strength);
+                    data.arm_strength = shoulder.strength;
+                }
+            }
+            if safety_report.as_ref().is_none_or(|report| report.action == LimitAction::Clamp) {
+                let _ = shoulder_tx.send(shoulder);
+                let _ = elbow_tx.send(elbow);
+            }
+        }
+        (pose, plan, safety_report)
+    };
+
+    // Set new joint and wrist positions
+    data.joints.shoulder_x = pose.shoulder.0;
+    data.joints.shoulder_y = pose.shoulder.1;
+
+    data.elbow.elbow_x = pose.elbow.0;
+    data.elbow.elbow_y = pose.elbow.1;
+
+    data.wrist.wrist_x = pose.wrist.0;
+    data.wrist.wrist_y = pose.wrist.1;
 
     let compute_done_time = now_micros();
     let arrived_at_ground = compute_done_time + time_to_reach as u128;
+    data.trace.actuated = compute_done_time;
 
     // Internal latency: time spent from receiving to finishing computation
     // let internal_latency = compute_done_time.saturating_sub(receive_time);
@@ -311,170 +823,63 @@ async fn control_arm(
         data,
         arrived_at_ground,
         cycle_start_time,
-        &cycle_tx,
+        telemetry,
+        Some(grip),
+        Some(plan),
+        safety_report,
+        feedback_link,
     )
-    .await;
+    .await
 }
 /// Simulates sending feedback from actuator to sensor.
-pub async fn send_feedback(
+#[allow(clippy::too_many_arguments)]
+async fn send_feedback(
     channel: &Channel,
     mut data: SensorArmData,
     arrived_at_ground: u128,
     cycle_start_time: u128,
-    cycle_tx: &mpsc::UnboundedSender<u128>,
-) {
+    telemetry: &TelemetrySender,
+    grip: Option<GripAssessment>,
+    plan: Option<PlanResult>,
+    safety: Option<SafetyReport>,
+    link: Option<&mut FeedbackLink>,
+) -> lapin::Result<()> {
     // log time done  for feedback AFTER actuator processing
     data.timestamp = now_micros();
+    data.trace.feedback_sent = data.timestamp;
+    let cycle = data.trace.cycle;
 
-    let feedback = data.to_feedback(arrived_at_ground);
+    let mut feedback = data.to_feedback(arrived_at_ground);
+    feedback.grip = grip;
+    feedback.plan = plan;
+    feedback.safety = safety;
 
     let payload = serde_json::to_vec(&feedback).expect("Failed to serialize feedback");
 
-    channel
-        .basic_publish(
-            "",
-            "feedback_to_sensor", // sensor listens here
-            BasicPublishOptions::default(),
-            &payload,
-            BasicProperties::default(),
-        )
-        .await
-        .expect("Failed to publish feedback")
-        .await
-        .expect("Failed to confirm feedback delivery");
+    match link {
+        // the link delivers on its own time, a failed delivery is queued again for the next feedback
+        Some(link) => link.send(channel, (cycle, feedback.arm_id, payload))?,
+        None => publish_feedback(channel, &feedback_routing_key(feedback.arm_id), &payload).await?,
+    }
 
     // println!(
     //     "> Cycle time: {} µs",
     //     now_micros().saturating_sub(cycle_start_time)
     // );
-    cycle_tx
-        .send(cycle_start_time)
-        .expect("Failed to send cycle time for latency calculation");
+    telemetry.record("cycle_time", cycle, cycle_start_time, now_micros());
+    Ok(())
 }
 
-async fn start_latency(
-    mut lat_rx: mpsc::UnboundedReceiver<u128>,
-    mut lat_elbow_rx: mpsc::UnboundedReceiver<u128>,
-    mut lat_shoulder_rx: mpsc::UnboundedReceiver<u128>,
-    mut lat_cycle_rx: mpsc::UnboundedReceiver<u128>,
-) {
-    println!("> Starting latency calculations...");
-
-    // File writer (shared between threads)
-    let file = std::fs::OpenOptions::new()
-        .append(true)
-        .create(true)
-        .open("latency_log.csv")
-        .expect("Failed to open latency_log.csv");
-
-    let file = std::sync::Arc::new(std::sync::Mutex::new(csv::Writer::from_writer(file)));
-
-    // Write header only once
-    {
-        let mut writer = file.lock().unwrap();
-        writer
-            .write_record(&["timestamp", "latency_type", "latency_μs"])
-            .expect("Failed to write CSV header");
-        writer.flush().unwrap();
-    }
-
-    // Reception latency logging
-    {
-        let writer = file.clone();
-        std::thread::spawn(move || {
-            let rt = tokio::runtime::Runtime::new().expect("Failed to create Tokio runtime");
-            rt.block_on(async move {
-                while let Some(sent_timestamp) = lat_rx.recv().await {
-                    let now = now_micros();
-                    let latency = now.saturating_sub(sent_timestamp);
-                    println!("Data Reception Latency: {} µs", latency);
-
-                    let mut writer = writer.lock().unwrap();
-                    writer
-                        .write_record(&[
-                            now.to_string(),
-                            "Data reception latency".to_string(),
-                            latency.to_string(),
-                        ])
-                        .unwrap();
-                    writer.flush().unwrap();
-                }
-            });
-        });
-    }
-
-    // Cycle latency logging
-    {
-        let writer = file.clone();
-        std::thread::spawn(move || {
-            let rt = tokio::runtime::Runtime::new().expect("Failed to create Tokio runtime");
-            rt.block_on(async move {
-                while let Some(sent_timestamp) = lat_cycle_rx.recv().await {
-                    let now = now_micros();
-                    let latency = now.saturating_sub(sent_timestamp);
-                    println!("Cycle Time: {} µs", latency);
-
-                    let mut writer = writer.lock().unwrap();
-                    writer
-                        .write_record(&[
-                            now.to_string(),
-                            "cycle time".to_string(),
-                            latency.to_string(),
-                        ])
-                        .unwrap();
-                    writer.flush().unwrap();
-                }
-            });
-        });
-    }
-
-    // Elbow latency logging
-    {
-        let writer = file.clone();
-        std::thread::spawn(move || {
-            let rt = tokio::runtime::Runtime::new().expect("Failed to create Tokio runtime");
-            rt.block_on(async move {
-                while let Some(sent_timestamp) = lat_elbow_rx.recv().await {
-                    let now = now_micros();
-                    let latency = now.saturating_sub(sent_timestamp);
-                    println!("Actuator Elbow Latency: {} µs", latency);
-
-                    let mut writer = writer.lock().unwrap();
-                    writer
-                        .write_record(&[
-                            now.to_string(),
-                            "elbow actuator time".to_string(),
-                            latency.to_string(),
-                        ])
-                        .unwrap();
-                    writer.flush().unwrap();
-                }
-            });
-        });
-    }
-
-    // Shoulder latency logging
-    {
-        let writer = file.clone();
-        std::thread::spawn(move || {
-            let rt = tokio::runtime::Runtime::new().expect("Failed to create Tokio runtime");
-            rt.block_on(async move {
-                while let Some(sent_timestamp) = lat_shoulder_rx.recv().await {
-                    let now = now_micros();
-                    let latency = now.saturating_sub(sent_timestamp);
-                    println!("Actuator Shoulder Latency: {} µs", latency);
-
-                    let mut writer = writer.lock().unwrap();
-                    writer
-                        .write_record(&[
-                            now.to_string(),
-                            "shoulder actuator time".to_string(),
-                            latency.to_string(),
-                        ])
-                        .unwrap();
-                    writer.flush().unwrap();
-                }
-            });
-        });
-    }
+async fn publish_feedback(channel: &Channel, routing_key: &str, payload: &[u8]) -> lapin::Result<()> {
+    channel
+        .basic_publish(
+            FEEDBACK_EXCHANGE,
+            routing_key, // sensor listens here
+            BasicPublishOptions::default(),
+            payload,
+            BasicProperties::default(),
+        )
+        .await?
+        .await?;
+    Ok(())
 }
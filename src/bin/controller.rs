@@ -1,34 +1,35 @@
 use std::{
+    collections::{HashMap, VecDeque},
     sync::Arc,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use futures_util::stream::StreamExt;
-use lapin::{options::*, types::FieldTable, Connection, ConnectionProperties};
+use lapin::{options::*, types::FieldTable, Channel, Consumer};
+use Real_time_systems_repo::routing::*;
 use serde::Serialize;
-use serde_json;
-use tokio::{sync::{mpsc, Mutex, Notify}, time::Instant, };
-use fastrand;
+use tokio::sync::{mpsc, Mutex, Notify};
+use Real_time_systems_repo::allocation::*;
+use Real_time_systems_repo::backpressure::*;
+use Real_time_systems_repo::broker::*;
+use Real_time_systems_repo::catching::*;
+use Real_time_systems_repo::config::*;
 use Real_time_systems_repo::data_structure::*;
-use std::fs::OpenOptions;
-use std::io::Write;
-
-pub async fn start_csv_logger(mut rx: mpsc::Receiver<LogEntry>, file_path: &str) {
-    let mut file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(file_path)
-        .expect("Failed to open CSV log file");
-
-    // Write header if file is new
-    if file.metadata().unwrap().len() == 0 {
-        writeln!(file, "task,latency_µs").expect("Failed to write header");
-    }
-
-    while let Some(entry) = rx.recv().await {
-        writeln!(file, "{},{}", entry.task, entry.latency).expect("Failed to write to CSV");
-    }
-}
+use Real_time_systems_repo::estop::*;
+use Real_time_systems_repo::freshness::*;
+use Real_time_systems_repo::heartbeat::*;
+use Real_time_systems_repo::histogram::*;
+use Real_time_systems_repo::latency_log::*;
+use Real_time_systems_repo::netfault::*;
+use Real_time_systems_repo::obstacles::*;
+use Real_time_systems_repo::safety::*;
+use Real_time_systems_repo::recording::*;
+use Real_time_systems_repo::registry::*;
+use Real_time_systems_repo::script::busy_wait;
+use Real_time_systems_repo::sensor_model::FaultEvaluation;
+use Real_time_systems_repo::shutdown::*;
+use Real_time_systems_repo::simulation::*;
+use Real_time_systems_repo::telemetry::*;
 
 fn now_micros() -> u128 {
     SystemTime::now()
@@ -41,155 +42,31 @@ fn detect_anomaly(value: f32, lower: f32, upper: f32) -> bool {
     value < lower || value > upper
 }
 
-pub fn generate_anomalous_object_data() -> ObjectData {
-    ObjectData {
-        // velocity is very low or 0, indicating no drop or static obstruction like a hand
-        object_velocity: fastrand::f32() * 1.0, // 0–1 m/s (very slow or static)
-
-        // since mass can change, the heavier the object the more difficult it is to catch as there is more momentum thus more velocity
-        // anomaly: very heavy (hand = 400–600g vs normal 1–5g)
-        object_mass: 100.0 + fastrand::f32() * 500.0, // 100g–600g = unexpected
-
-        // assume size is variable in small range of 4-5l
-        // anomaly: either too small or much larger object
-        object_size: 10.0 + fastrand::f32() * 20.0, // 10–30L = abnormal for expected object
-
-        // distance changes due to the object being let go at different areas of the tube, where tube is a circle with diameter of 3cm
-        // anomaly: object is placed very close or far off, like a hand waving or blocking the tube
-        object_x: 7.0 + fastrand::f32() * 3.0, 
-
-        object_y: 7.0 + fastrand::f32() * 4.0,
-
-        object_height: 0.0,
-    }
-}
-
-pub fn generate_normal_object_data() -> ObjectData {
-    // Normal falling object
-    ObjectData {
-        //velocity > mass since v = u + at, where v = final velocity, u = initial velocity(at rest so 0), a = acceleration (gravity 9.8), t = time (object has to be caught at 1s)
-        // up to 11.8 m/s and the object can be heavier than 1g
-        object_velocity: 9.8 + fastrand::f32() * 2.0,
-        //since mass can change, the heavier the object the more difficult it is to catch as there is more momentum thus more velocity
-        //thus variability in velocity is needed
-        //1 - 5g
-        object_mass: 1.0 + fastrand::f32() * 4.0,
-        //assume size is variable in small range of 4-5l
-        object_size: 4.0 + fastrand::f32(),
-        //distance changes due to the object being let go at different areas of the tube, where tube is a circle with diameter of 3cm
-        //thus object is at any point within the tube(circle)
-        //need x and y to tell where the robotic arm is in relation to the object to catch it
-        //where x is front back y is left right
-        //max object distance is 3cm(diameter of tube) for x and y so 4cm is a good range if accounting for some wind 
-        //tube infront by 4cm, assume tube is 3cm in diameter
-        object_x: 4.0 + fastrand::f32() * 3.0,
-        //-5 to 5
-        object_y: (fastrand::f32() * 10.0) - 5.0,
-        //object height is the distance from the top of the tube to the object
-        //calculated later based on arm
-        object_height: 0.0,
-    }
-}
-
-
-fn branchless_clamp(val: f32, min: f32, max: f32) -> f32 {
-    val.max(min).min(max)
-}
-
 pub async fn generate_sensor_data(
+    simulator: &mut SensorSimulator,
     cycle: u64,
     shared_feedback: Arc<Mutex<Option<FeedbackData>>>,
-    log_sender: mpsc::Sender<LogEntry>,
-) -> SensorArmData {
-
-    let start = Instant::now();
-    let object_data = if cycle % 10 == 0 {
-        // Every 10th cycle, simulate an anomaly (like hand)
-        generate_anomalous_object_data()
-    } else {
-        generate_normal_object_data()
-    };
-
-    let mut sensor_data = SensorArmData::new(object_data.clone());
-    sensor_data.update_object_data(object_data);
-
-    //realistic segment lengths (upper and lower arm)
-    //l1 = shoulder to elbow (1–4cm), l2 = elbow to wrist (4–7cm)
-    let l1 = 1.0 + fastrand::f32() * 3.0; // 1cm base + 0–3cm range = 1–4cm
-    let l2 = 4.0 + fastrand::f32() * 3.0; // 4–7cm
-
-    //angle from 0 (right) to π (left), but we clamp it to [0, π/2] for safe forward-right region
-    let theta1 = fastrand::f32() * std::f32::consts::FRAC_PI_2; // [0, π/2]
-    //elbow bend ±90°, so -π/2 to π/2 range is OK
-    let theta2 = (fastrand::f32() - 0.5) * std::f32::consts::PI;
-
+    telemetry: TelemetrySender,
+) -> Option<SensorArmData> {
+    let start = now_micros();
     // Lock and clone only once
-    let feedback_opt = {
-        let guard = shared_feedback.lock().await;
-        (*guard).clone()
-    };
-
-    // Use the cloned data outside the lock
-    if let Some(feedback) = feedback_opt {
-        sensor_data.joints.shoulder_x = feedback.joints.shoulder_x;
-        sensor_data.joints.shoulder_y = feedback.joints.shoulder_y;
-        sensor_data.elbow.elbow_x = feedback.elbow.elbow_x;
-        sensor_data.elbow.elbow_y = feedback.elbow.elbow_y;
-        sensor_data.wrist.wrist_x = feedback.wrist.wrist_x;
-        sensor_data.wrist.wrist_y = feedback.wrist.wrist_y;
-    }
-    else {
-        //using forward kinematics to calculate arm positions
-        //wrist length > elbow length > shoulder length
-        //shoulder is the base of the arm, so it is the least variable
-        //shoulder length can vary from 0cm to 1cm
-        sensor_data.joints.shoulder_x = fastrand::f32() * 1.0;
-        sensor_data.joints.shoulder_y = (fastrand::f32() * 3.0) - 1.5; // y: [-1.5, 1.5]
-        //using FK to get elbow position from shoulder + angle + l1
-        //this models the upper arm segment
-        sensor_data.elbow.elbow_x = sensor_data.joints.shoulder_x + l1 * theta1.cos();
-        sensor_data.elbow.elbow_y = sensor_data.joints.shoulder_y + l1 * theta1.sin();
-        sensor_data.elbow.elbow_x = branchless_clamp(sensor_data.elbow.elbow_x, 0.0, 7.0);
-        sensor_data.elbow.elbow_y = branchless_clamp(sensor_data.elbow.elbow_y, -1.5, 1.5);
-
-        //wrist is the end of the forearm, which bends at the elbow
-        //direction is based on total angle (shoulder + elbow joint)
-        sensor_data.wrist.wrist_x = sensor_data.elbow.elbow_x + l2 * (theta1 + theta2).cos();
-        sensor_data.wrist.wrist_y = sensor_data.elbow.elbow_y + l2 * (theta1 + theta2).sin();
-
-        // clamp wrist_x to ≥ shoulder_x
-        if sensor_data.wrist.wrist_x < sensor_data.joints.shoulder_x {
-            sensor_data.wrist.wrist_x = sensor_data.joints.shoulder_x;
-        }
-
-        // clamp wrist_y to [-1.5, 1.5]
-        sensor_data.wrist.wrist_y = branchless_clamp(sensor_data.wrist.wrist_y, -1.5, 1.5);
-    }
-
-    //suggested arm velocity to catch object
-    sensor_data.arm_velocity = fastrand::f32() * 10.0;
-
-    //arm strength is a crude estimate based on F = m * a,
-    //assuming velocity is proportional to acceleration here
-    sensor_data.arm_strength = sensor_data.arm_velocity * sensor_data.object_data.object_mass;
-    sensor_data.object_data.object_height = sensor_data.joints.shoulder_y + l1 * theta1.sin() + l2 * (theta1 + theta2).sin();
-    sensor_data.timestamp = now_micros();
-    let duration = start.elapsed().as_micros();
-    if let Err(e) = log_sender.send(LogEntry {
-        task: "generate_sensor_data".to_string(),
-        latency: duration,
-            }).await {
-                eprintln!("Failed to log latency: {}", e);
-            }
-    sensor_data
+    let feedback_opt = shared_feedback.lock().await.clone();
+    // the arm is sensed once the frame is allocated
+    let sensor_data = simulator.observe(cycle, feedback_opt.as_ref());
+    // load phases of a scripted run
+    busy_wait(simulator.load());
+    let sensor_data = sensor_data?;
+    telemetry.record("generate_sensor_data", cycle, start, sensor_data.timestamp);
+    Some(sensor_data)
 }
 
 pub async fn process_sensor_data(
-    mut raw: SensorArmData, 
-    filters: &mut Filters, 
-    log_sender: mpsc::Sender<LogEntry>
+    mut raw: SensorArmData,
+    filters: &mut Filters,
+    cycle: u64,
+    telemetry: TelemetrySender,
 ) -> (SensorArmData, bool) {
-    let start = Instant::now();
+    let start = now_micros();
     // let start = now_micros();
     // let mut filtered = raw.clone();
     // destructure to reduce deep field access
@@ -266,164 +143,463 @@ pub async fn process_sensor_data(
     //     || detect_anomaly(filtered.object_data.object_velocity, 9.8, 11.8);       // non-moving object
     // let latency = now_micros() - start;
     // println!("Sensor data processed in {} µs", latency);
-    let duration = start.elapsed().as_micros();
-    if let Err(e) = log_sender.send(LogEntry {
-        task: "process_sensor_data".to_string(),
-        latency: duration,
-            }).await {
-                eprintln!("Failed to log latency: {}", e);
-            }
+    raw.trace.filtered = now_micros();
+    telemetry.record("process_sensor_data", cycle, start, raw.trace.filtered);
     (raw, anomaly)
 }
 
 async fn consume_feedback(
-    shutdown: Arc<Notify>,
-    shared_feedback: Arc<Mutex<Option<FeedbackData>>>,
+    shutdown: Shutdown,
+    registry: Arc<ArmRegistry>,
     ready_notify: Arc<Notify>,
-    log_sender: mpsc::Sender<LogEntry>,
+    run: RunInfo,
+    telemetry: TelemetrySender,
+    allocation_stats: Arc<AllocationStats>,
+    catches: Arc<Mutex<CatchEvaluator>>,
 ) {
     // let mut total_latency: u128 = 0;
     // let mut message_count: u64 = 0;
-    let conn = Connection::connect("amqp://127.0.0.1:5672/%2f", ConnectionProperties::default())
-        .await
-        .expect("Connection error");
-    let channel = conn.create_channel().await.expect("Channel creation error");
-
-    channel
-        .queue_declare(
-            "feedback_to_sensor",
-            QueueDeclareOptions::default(),
-            FieldTable::default(),
-        )
-        .await
-        .expect("Queue declaration error");
+    let mut broker = Broker::new("feedback");
+    // MAX_FEEDBACK_AGE_MS
+    let freshness = Freshness::feedback();
+    let mut traces = TraceWriter::open(format!("trace_{}.csv", run.process), &run.run_id)
+        .expect("Failed to open trace log");
+    // RECORD=1 keeps every feedback message received, for replaying later
+    let mut recorder = Recorder::from_env(&run.process);
+    // how the arms' moves fared against their obstacles
+    let mut plans = PlanStats::default();
+    // and against their joint limits
+    let mut safety = SafetyStats::default();
+    'session: loop {
+        // reconnects with backoff, queues and the consumer are declared again on every new channel
+        let (conn, channel) = tokio::select! {
+            session = broker.connect(|channel| async move { declare_controller_topology(&channel).await }) => session,
+            _ = shutdown.wait() => break 'session,
+        };
+        let mut consumer = match channel
+            .basic_consume(
+                FEEDBACK_QUEUE,
+                "feedback_consumer",
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+            )
+            .await
+        {
+            Ok(consumer) => consumer,
+            Err(e) => {
+                broker.lost(e);
+                continue;
+            }
+        };
 
-    let mut consumer = channel
-        .basic_consume(
-            "feedback_to_sensor",
-            "feedback_consumer",
-            BasicConsumeOptions::default(),
-            FieldTable::default(),
-        )
-        .await
-        .expect("Basic consume error");
-
-    println!("> Feedback consumer ready...");
-    ready_notify.notify_waiters();
-    loop {
-        tokio::select! {
-            maybe_delivery = consumer.next() => {
-                if let Some(Ok(delivery)) = maybe_delivery {
+        println!("> Feedback consumer ready...");
+        // stores a permit, so the sensor loop starts even if it was not waiting yet
+        ready_notify.notify_one();
+        loop {
+            tokio::select! {
+                maybe_delivery = consumer.next() => {
+                    let delivery = match maybe_delivery {
+                        Some(Ok(delivery)) => delivery,
+                        Some(Err(e)) => {
+                            broker.lost(e);
+                            continue 'session;
+                        }
+                        None => {
+                            broker.lost("consumer closed");
+                            continue 'session;
+                        }
+                    };
                     let payload = &delivery.data;
-                    if let Ok(feedback) = serde_json::from_slice::<FeedbackData>(payload) {
+                    if let Ok(mut feedback) = serde_json::from_slice::<FeedbackData>(payload) {
+                        feedback.trace.feedback_received = now_micros();
+                        if let Some(recorder) = &mut recorder {
+                            recorder.record(Stream::Feedback, feedback.arm_id, payload, feedback.trace.feedback_received);
+                        }
                         println!("Received feedback: {:?}", feedback);
                         //latency from feedback timestamp to now, measuring how long it took to send data and receive from controller end
-                        let latency = now_micros() - feedback.timestamp;
-                        // Send to logger
-                        if let Err(e) = log_sender.send(LogEntry {
-                            task: "consume_feedback".to_string(),
-                            latency,
-                        }).await {
-                            eprintln!("Failed to log latency: {}", e);
+                        let trace = &feedback.trace;
+                        telemetry.record("consume_feedback", trace.cycle, feedback.timestamp, trace.feedback_received);
+                        // full round trip of the frame this feedback answers
+                        if trace.generated > 0 {
+                            telemetry.record("round_trip", trace.cycle, trace.generated, trace.feedback_received);
+                            if let Err(e) = traces.write(feedback.arm_id, trace) {
+                                eprintln!("Failed to write frame trace: {}", e);
+                            }
                         }
                         // total_latency += latency;
                         // message_count += 1;
                         // println!("Reception latency: {} µs", latency);
-                        
-                        let mut shared = shared_feedback.lock().await;
-                        *shared = Some(feedback);
+
+                        allocation_stats.record_answer(feedback.arm_id);
+                        catches.lock().await.attempt(&feedback);
+                        if let Some(plan) = &feedback.plan {
+                            plans.record(plan);
+                            if let PlanResult::Rejected { obstacle } = plan {
+                                eprintln!("Arm {} rejected a move into obstacle {}", feedback.arm_id, obstacle);
+                            }
+                        }
+                        if let Some(report) = &feedback.safety {
+                            safety.record(report);
+                            if let Some(obstacle) = report.obstacle {
+                                eprintln!("Arm {} rejected a clamped move into obstacle {}", feedback.arm_id, obstacle);
+                            } else if report.action == LimitAction::Reject {
+                                eprintln!("Arm {} rejected a move past its joint limits: {:?}", feedback.arm_id, report.violations);
+                            }
+                        }
+                        registry.touch(feedback.arm_id);
+                        match registry.feedback_slot(feedback.arm_id) {
+                            // the arm has moved on since, its position would mislead the allocator
+                            Some(_) if freshness.is_stale(feedback.timestamp) => {}
+                            Some(slot) => {
+                                let mut slot = slot.lock().await;
+                                // latest value, feedback overtaken by a newer one is not applied
+                                if slot.as_ref().is_some_and(|newer| newer.timestamp > feedback.timestamp) {
+                                    freshness.supersede();
+                                } else {
+                                    *slot = Some(feedback);
+                                }
+                            }
+                            None => eprintln!("Feedback from unregistered arm {}, ignoring", feedback.arm_id),
+                        }
+                    }
+                    if let Err(e) = delivery.ack(Default::default()).await {
+                        broker.lost(e);
+                        continue 'session;
                     }
-                    delivery.ack(Default::default()).await.expect("Failed to ack");
-                } else {
-                    break;
                 }
-            }
-            
-            _ = shutdown.notified() => {
-                println!("Feedback consumer received shutdown signal.");
-                break;
+
+                _ = shutdown.wait() => {
+                    println!("Feedback consumer received shutdown signal.");
+                    close_session(&conn, &channel).await;
+                    break 'session;
+                }
             }
         }
     }
+    if let Err(e) = traces.flush() {
+        eprintln!("Failed to flush frame traces: {}", e);
+    }
+    if let Some(recorder) = recorder {
+        recorder.finish();
+    }
+    plans.print_summary("Motion plans reported by the arms");
+    safety.print_summary("Joint limit violations reported by the arms");
+    freshness.print_summary("feedback");
+    broker.stats.print_summary(&broker.name);
     // let avg_latency_ms = total_latency as f64 / message_count as f64;
     // println!(
     //     "Processed {} messages. Average reception latency: {:.3} µs",
     //      message_count, avg_latency_ms
     // );
 }
+async fn start_consumer(channel: &Channel, queue: &str, tag: &str, no_ack: bool) -> lapin::Result<Consumer> {
+    channel
+        .basic_consume(
+            queue,
+            tag,
+            BasicConsumeOptions {
+                no_ack,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await
+}
+
+//keeps the arm registry up to date: registrations, departures, heartbeats, timeouts and emergency stops
+//an arm whose heartbeats stop is dropped by the watchdog, so no more frames are allocated to it
+async fn consume_registrations(
+    shutdown: Shutdown,
+    registry: Arc<ArmRegistry>,
+    mut estop_rx: mpsc::UnboundedReceiver<EmergencyStop>,
+) {
+    let mut broker = Broker::new("registry");
+    // stops raised while the broker is unreachable, sent once it is back
+    let mut pending_stops: VecDeque<EmergencyStop> = VecDeque::new();
+    let mut expiry = tokio::time::interval(WATCHDOG_PERIOD);
+    let mut heartbeat = tokio::time::interval(heartbeat_interval());
+    let mut heartbeat_sequence = 0u64;
+    'session: loop {
+        let (conn, channel) = tokio::select! {
+            session = broker.connect(|channel| async move { declare_controller_topology(&channel).await }) => session,
+            _ = shutdown.wait() => break 'session,
+        };
+        let consumers = async {
+            // stops get their own channel so they never queue behind heartbeats
+            let estop_channel = conn.create_channel().await?;
+            let consumer = start_consumer(&channel, REGISTRY_QUEUE, "registry_consumer", false).await?;
+            let heartbeats = start_consumer(&channel, CONTROLLER_HEARTBEAT_QUEUE, "heartbeat_consumer", false).await?;
+            let stops = start_consumer(&estop_channel, CONTROLLER_ESTOP_QUEUE, "estop_consumer", true).await?;
+            Ok::<_, lapin::Error>((estop_channel, consumer, heartbeats, stops))
+        };
+        let (estop_channel, mut consumer, mut heartbeats, mut stops) = match consumers.await {
+            Ok(consumers) => consumers,
+            Err(e) => {
+                broker.lost(e);
+                continue;
+            }
+        };
+        while let Some(stop) = pending_stops.pop_front() {
+            if let Err(e) = raise(&estop_channel, &stop).await {
+                pending_stops.push_front(stop);
+                broker.lost(e);
+                continue 'session;
+            }
+        }
+
+        println!("> Registry consumer ready...");
+        loop {
+            tokio::select! {
+                maybe_delivery = consumer.next() => {
+                    let Some(Ok(delivery)) = maybe_delivery else {
+                        broker.lost("registry consumer closed");
+                        continue 'session;
+                    };
+                    match serde_json::from_slice::<RegistryMessage>(&delivery.data) {
+                        Ok(message) => registry.handle(message),
+                        Err(e) => eprintln!("Failed to deserialize registry message: {:?}", e),
+                    }
+                    if let Err(e) = delivery.ack(Default::default()).await {
+                        broker.lost(e);
+                        continue 'session;
+                    }
+                }
+                maybe_delivery = heartbeats.next() => {
+                    let Some(Ok(delivery)) = maybe_delivery else {
+                        broker.lost("heartbeat consumer closed");
+                        continue 'session;
+                    };
+                    match serde_json::from_slice::<Heartbeat>(&delivery.data) {
+                        // heartbeats queued while the controller was down say nothing about the arm now
+                        Ok(Heartbeat { arm_id: Some(arm_id), timestamp, .. })
+                            if now_micros().saturating_sub(timestamp) <= registry.timeout.as_micros() =>
+                        {
+                            if registry.touch(arm_id) {
+                                println!("> Arm {} is sending heartbeats again, commanding it", arm_id);
+                            }
+                        }
+                        Ok(_) => {}
+                        Err(e) => eprintln!("Failed to deserialize heartbeat: {:?}", e),
+                    }
+                    if let Err(e) = delivery.ack(Default::default()).await {
+                        broker.lost(e);
+                        continue 'session;
+                    }
+                }
+                maybe_delivery = stops.next() => {
+                    let Some(Ok(delivery)) = maybe_delivery else {
+                        broker.lost("emergency stop consumer closed");
+                        continue 'session;
+                    };
+                    match serde_json::from_slice::<EmergencyStop>(&delivery.data) {
+                        Ok(stop) => {
+                            registry.set_stopped(stop.arm_id, stop.engaged);
+                            println!(
+                                "> Emergency stop {} for {} from {}: {}",
+                                if stop.engaged { "engaged" } else { "released" },
+                                stop.arm_id.map_or("all arms".to_string(), |id| format!("arm {}", id)),
+                                stop.source,
+                                stop.reason
+                            );
+                        }
+                        Err(e) => eprintln!("Failed to deserialize emergency stop: {:?}", e),
+                    }
+                }
+                Some(stop) = estop_rx.recv() => {
+                    if let Err(e) = raise(&estop_channel, &stop).await {
+                        pending_stops.push_back(stop);
+                        broker.lost(e);
+                        continue 'session;
+                    }
+                }
+                _ = heartbeat.tick() => {
+                    heartbeat_sequence += 1;
+                    let message = Heartbeat {
+                        arm_id: None,
+                        sequence: heartbeat_sequence,
+                        timestamp: now_micros(),
+                        arms: registry.ids(),
+                    };
+                    if let Err(e) = send_heartbeat(&channel, &message).await {
+                        broker.lost(e);
+                        continue 'session;
+                    }
+                }
+                _ = expiry.tick() => {
+                    for arm_id in registry.expire() {
+                        println!(
+                            "> Arm {} missed its heartbeats, stopped commanding it, serving {:?}",
+                            arm_id,
+                            registry.ids()
+                        );
+                    }
+                }
+                _ = shutdown.wait() => {
+                    println!("Registry consumer received shutdown signal.");
+                    close_session(&conn, &channel).await;
+                    break 'session;
+                }
+            }
+        }
+    }
+    if !pending_stops.is_empty() {
+        eprintln!("{} emergency stop(s) never reached the broker", pending_stops.len());
+    }
+    broker.stats.print_summary(&broker.name);
+}
+
+//operator commands on stdin: `estop [arm]` and `release [arm]`, no arm means every arm
+//runs on a plain thread, a blocking stdin read in the runtime would hold up shutdown
+fn read_operator_commands(estop_tx: mpsc::UnboundedSender<EmergencyStop>) {
+    for line in std::io::stdin().lines().map_while(Result::ok) {
+        let mut words = line.split_whitespace();
+        let command = words.next();
+        let arm_id = words.next().and_then(|id| id.parse().ok());
+        let stop = match command {
+            Some("estop") => EmergencyStop::engage(arm_id, "controller", "operator"),
+            Some("release") => EmergencyStop::release(arm_id, "controller"),
+            _ => continue,
+        };
+        if estop_tx.send(stop).is_err() {
+            break;
+        }
+    }
+}
+
 //publish method to send processed sensor data to RabbitMQ
 async fn publish<T>(
     channel: &lapin::Channel,
     data: &T,
-    log_sender: mpsc::Sender<LogEntry>,
+    routing_key: &str,
+    cycle: u64,
+    telemetry: TelemetrySender,
 ) -> Result<(), Box<dyn std::error::Error>>
 where
     T: Serialize,
-{   
-    let start = Instant::now();
+{
+    let start = now_micros();
     let payload = serde_json::to_vec(data)?;
     channel
         .basic_publish(
-            "",
-            "sensor_data",
+            SENSOR_EXCHANGE,
+            routing_key,
             BasicPublishOptions::default(),
             &payload,
             Default::default(),
         )
         .await?
         .await?; // confirmation
-        let duration = start.elapsed().as_micros();
-        if let Err(e) = log_sender.send(LogEntry {
-            task: "publish_data".to_string(),
-            latency: duration,
-        }).await {
-            eprintln!("Failed to log latency: {}", e);
-            }     
+    telemetry.record("publish_data", cycle, start, now_micros());
     Ok(())
 }
 
+// caught objects count towards the arm that caught them
+fn record_catches(outcomes: &[CatchOutcome], stats: &AllocationStats) {
+    for outcome in outcomes {
+        if let (CatchResult::Caught, Some(arm_id)) = (outcome.result, outcome.arm_id) {
+            stats.record_catch(arm_id);
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
     let cycle = Arc::new(Mutex::new(1u64));
-    let max_cycles = 10000u64;
-    let shared_filters = Arc::new(Mutex::new(Filters::new()));
-    let shared_filters_clone = Arc::clone(&shared_filters);
-    let (tx_processed, mut rx_processed) = mpsc::channel::<SensorArmData>(100);
-    let tx_blocking = tx_processed.clone();
+    // SCENARIO_FILE replays a scripted run, SIM_SEED and the ANOMALY_* variables make the generated frames reproducible
+    let mut simulator = SensorSimulator::from_env();
+    let max_cycles = simulator.script().and_then(|script| script.cycles).unwrap_or(10000);
+    // arms join by registering, ARM_IDS=1,2,3 pins arms that are served without registering
+    let registry = Arc::new(ArmRegistry::from_env());
+    for arm_id in arm_ids_from_env() {
+        registry.pin(arm_id);
+    }
+    println!("> Serving arms: {:?}", registry.ids());
+    let registry_for_sensor = Arc::clone(&registry);
+    let registry_for_feedback = Arc::clone(&registry);
+    // Ctrl-C / SIGTERM, or the last cycle, stops the sensor loop, the pipeline then drains
+    let shutdown = Shutdown::new();
+    shutdown.trigger_on_signal();
+    // consumers stop after the drain, so feedback for frames still in flight is received
+    let consumers_shutdown = Shutdown::new();
+    let (estop_tx, estop_rx) = mpsc::unbounded_channel::<EmergencyStop>();
+    let registry_handle = tokio::spawn(consume_registrations(
+        consumers_shutdown.clone(),
+        Arc::clone(&registry),
+        estop_rx,
+    ));
+    let operator_estop_tx = estop_tx.clone();
+    std::thread::spawn(move || read_operator_commands(operator_estop_tx));
+    // anomaly policy, ESTOP_AFTER_ANOMALIES consecutive anomalous frames stop the arm
+    let estop_after_anomalies = anomaly_threshold_from_env();
+    // sensor -> publisher hand-off, BACKPRESSURE / PIPELINE_CAPACITY decide what a slow publisher costs
+    let (tx_processed, mut rx_processed) =
+        pipeline_from_env(|(_, frame): &(u64, SensorArmData)| frame.arm_id as u64);
+    let pipeline_policy = tx_processed.policy();
+    let pipeline_stats = tx_processed.stats();
     let cycle_clone = Arc::clone(&cycle);
-    let shutdown_notify = Arc::new(Notify::new());
-    let feedback_shutdown = Arc::new(Notify::new());
-    let feedback_shutdown_consumer = Arc::clone(&feedback_shutdown);
-    let shared_feedback = Arc::new(Mutex::new(None::<FeedbackData>));
-    let shared_feedback_for_feedback = Arc::clone(&shared_feedback);
-    let shared_feedback_for_sensor = Arc::clone(&shared_feedback);
+    let shutdown_for_sensor = shutdown.clone();
+    // picks which arm tries to catch each object, ALLOCATION=nearest|intercept
+    let allocator = Allocator::from_env();
+    let allocation_stats = Arc::clone(&allocator.stats);
     let feedback_ready_notify = Arc::new(Notify::new());
     let feedback_ready_notify_for_consumer = Arc::clone(&feedback_ready_notify);
-    let (log_tx, log_rx) = mpsc::channel::<LogEntry>(100);
-    let log_tx_feedback = log_tx.clone();
-    let log_tx_publisher = log_tx.clone();
-    // Start the CSV logger in a separate task
-    tokio::spawn(start_csv_logger(log_rx, "performance_log_normal.csv"));
-
+    let run = RunInfo::from_env("controller");
+    let histograms = Arc::new(LatencyHistograms::new(&CONTROLLER_STAGES));
+    // stage latencies go through the same telemetry pipeline as the actuator's, TELEMETRY_* variables
+    let (telemetry_tx, telemetry) = Telemetry::start(&run, Arc::clone(&histograms), TelemetryConfig::from_env());
+    let snapshot_handle = tokio::spawn(start_histogram_snapshots(
+        Arc::clone(&histograms),
+        run.clone(),
+        Duration::from_secs(1),
+    ));
+    let telemetry_feedback = telemetry_tx.clone();
+    let telemetry_publisher = telemetry_tx.clone();
+    let run_for_feedback = run.clone();
+    // decides which objects were caught, CATCH_TOLERANCE_CM / CATCH_SIZE_FACTOR / CATCH_SETTLE_MS
+    let catches = Arc::new(Mutex::new(CatchEvaluator::from_env(&run)));
+    let allocation_stats_for_feedback = Arc::clone(&allocator.stats);
+    let catches_for_feedback = Arc::clone(&catches);
+    let catches_for_sensor = Arc::clone(&catches);
+    let catches_for_publisher = Arc::clone(&catches);
+
+    let feedback_shutdown = consumers_shutdown.clone();
     let feedback_handle = tokio::spawn(async move {
         consume_feedback(
-            feedback_shutdown_consumer,
-            shared_feedback_for_feedback,
+            feedback_shutdown,
+            registry_for_feedback,
             feedback_ready_notify,
-            log_tx_feedback,
+            run_for_feedback,
+            telemetry_feedback,
+            allocation_stats_for_feedback,
+            catches_for_feedback,
         )
         .await;
     });
 
     // Wait for feedback consumer to be ready
-    feedback_ready_notify_for_consumer.notified().await;
+    tokio::select! {
+        _ = feedback_ready_notify_for_consumer.notified() => {}
+        _ = shutdown.wait() => {}
+    }
     // sensor generation task using tokio interval
     let sensor_task = tokio::spawn(async move {
         let mut interval = tokio::time::interval(Duration::from_millis(5));
+        // objects are generated without arm state, the chosen arm's feedback is applied after allocation
+        let unassigned_feedback = Arc::new(Mutex::new(None::<FeedbackData>));
+        // one filter bank per arm, dropped when the arm leaves
+        let mut filter_banks: HashMap<u32, Filters> = HashMap::new();
+        let mut anomaly_streaks: HashMap<u32, u32> = HashMap::new();
+        let mut detected_anomalies = 0u64;
+        // filters and anomaly detection scored against the faults the simulator injected
+        let mut evaluation = FaultEvaluation::default();
+        let modelled_fields = simulator.sensors().fields();
 
         // inside sensor_task
         loop {
-            interval.tick().await;
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = shutdown_for_sensor.wait() => break,
+            }
 
             let mut c = cycle_clone.lock().await;
             if *c > max_cycles {
@@ -432,68 +608,231 @@ async fn main() {
 
             let current_cycle = *c;
             *c += 1;
-            let shared_feedback_clone = Arc::clone(&shared_feedback_for_sensor);
-            let data = generate_sensor_data(current_cycle, shared_feedback_clone, log_tx.clone()).await;
-            let mut filters = shared_filters_clone.lock().await;
-            let (processed, anomaly) = process_sensor_data(data, &mut filters, log_tx.clone()).await;
+            let frame =
+                generate_sensor_data(&mut simulator, current_cycle, Arc::clone(&unassigned_feedback), telemetry_tx.clone()).await;
+
+            let arms = registry_for_sensor.active();
+            filter_banks.retain(|id, _| arms.iter().any(|(arm_id, _)| arm_id == id));
+            let mut statuses = Vec::with_capacity(arms.len());
+            for (arm_id, feedback) in &arms {
+                statuses.push(match feedback.lock().await.as_ref() {
+                    Some(feedback) => ArmStatus::from_feedback(feedback),
+                    None => ArmStatus::idle(*arm_id),
+                });
+            }
+            // ground truth from the world simulation, WORLD=1, only which objects landed
+            simulator.settle();
+            // the only verdict on catches, from the frames the controller sent and the arms' answers
+            let outcomes = catches_for_sensor.lock().await.settle(now_micros());
+            record_catches(&outcomes, &allocator.stats);
+            simulator.record_catches(&outcomes);
+            // the sensor saw nothing this cycle
+            let Some(mut data) = frame else {
+                continue;
+            };
+            let Some(arm_id) = allocator.allocate(&data.object_data, &statuses, now_micros()) else {
+                continue; // no arm registered yet
+            };
+            let (_, feedback) = arms
+                .iter()
+                .find(|(id, _)| *id == arm_id)
+                .expect("Allocator picked an unknown arm");
+            // the allocated arm is where it really is, and the sensor sees it with its faults
+            if let Some(feedback) = feedback.lock().await.as_ref() {
+                simulator.place_arm(&mut data, feedback);
+            }
+            simulator.sense_arm(current_cycle, &mut data);
+            let truth = simulator.truth().cloned().expect("a frame has its true state");
+            let faulted = simulator.faulted();
+            data.arm_id = arm_id;
+            let filters = filter_banks.entry(arm_id).or_default();
+            let (processed, anomaly) = process_sensor_data(data, filters, current_cycle, telemetry_tx.clone()).await;
+            evaluation.record(&modelled_fields, &truth, &processed, faulted, anomaly);
 
             if anomaly {
+                detected_anomalies += 1;
                 println!(
-                    "Anomaly detected in cycle {}: {:?}",
-                    current_cycle, processed
+                    "Anomaly detected in cycle {} for arm {}: {:?}",
+                    current_cycle, arm_id, processed
                 );
                 //remove extreme value
                 filters.reset();
+                let streak = anomaly_streaks.entry(arm_id).or_default();
+                *streak += 1;
+                if estop_after_anomalies > 0 && *streak == estop_after_anomalies {
+                    let reason = format!("{} consecutive anomalous frames", streak);
+                    let _ = estop_tx.send(EmergencyStop::engage(Some(arm_id), "anomaly_policy", &reason));
+                }
             } else {
+                anomaly_streaks.remove(&arm_id);
                 println!(
-                    "cycle {:03}, arm_strength: {:.2}, anomaly: {}",
-                    current_cycle, processed.arm_strength, anomaly
+                    "cycle {:03}, arm {}, arm_strength: {:.2}, anomaly: {}",
+                    current_cycle, arm_id, processed.arm_strength, anomaly
                 );
 
-                // use .send().await to wait for channel capacity instead of try_send
-                if let Err(e) = tx_blocking.send(processed).await {
-                    eprintln!("Failed to send processed data: {}", e);
+                // only waits for room under the block policy, the others drop or coalesce frames
+                if tx_processed.send((current_cycle, processed)).await.is_err() {
+                    eprintln!("Failed to send processed data: publisher is gone");
                     break; // if receiver dropped, break out
                 }
             }
         }
+        simulator.print_summary();
+        evaluation.print_summary();
+        (simulator, detected_anomalies)
     });
     //send data
+    // NET_FAULTS_FILE delays, drops, duplicates or reorders the frames on their way to the arms
+    let mut sensor_link = FaultyLink::from_env("sensor", &run);
+    // frames the link failed to deliver come back here and go through the outbox like any failed publish
+    let (failed_tx, mut failed_rx) = mpsc::unbounded_channel::<(u64, SensorArmData)>();
     let publisher_handle = tokio::spawn(async move {
-        let conn = Connection::connect("amqp://127.0.0.1:5672/%2f", ConnectionProperties::default())
-            .await
-            .expect("Connection error");
-        let channel = conn.create_channel().await.expect("Channel creation error");
-
-        channel
-            .queue_declare(
-                "sensor_data",
-                QueueDeclareOptions::default(),
-                FieldTable::default(),
-            )
-            .await
-            .expect("Queue declaration error");
-
-        while let Some(processed_data) = rx_processed.recv().await {
-            if let Err(e) = publish(&channel, &processed_data, log_tx_publisher.clone()).await {
-                eprintln!("Publish failed: {:?}", e);
+        let mut broker = Broker::new("publisher");
+        let setup = |channel: Channel| async move { declare_controller_topology(&channel).await };
+        // frames wait here while the broker is unreachable, OUTBOX_CAPACITY / OUTBOX_POLICY
+        let mut outbox = Outbox::from_env();
+        // frames that waited too long in the outbox are not worth sending, MAX_FRAME_AGE_MS
+        let freshness = Freshness::frames();
+        let mut session = broker.try_connect(&setup).await;
+
+        let mut sequence = 0u64;
+        while let Some(frame) = rx_processed.recv().await {
+            outbox.push(frame);
+            while let Some(frame) = rx_processed.try_recv() {
+                outbox.push(frame);
             }
-            // println!("Published sensor data in {} µs",duration);
+            if outbox.requeue_failed(&mut failed_rx) {
+                broker.lost("frame delivery failed");
+                session = None;
+            }
+            if session.is_none() {
+                // one attempt per frame at most, backoff permitting, so the pipeline never stalls
+                session = broker.try_connect(&setup).await;
+            }
+            let Some((_, channel)) = &session else {
+                continue;
+            };
+            let channel = channel.clone();
+            while let Some((cycle, mut processed_data)) = outbox.pop() {
+                if freshness.is_stale(processed_data.timestamp) {
+                    continue;
+                }
+                sequence += 1;
+                processed_data.trace.sequence = sequence;
+                processed_data.trace.published = now_micros();
+                let routing_key = sensor_routing_key(processed_data.arm_id);
+                // the error is not Send, it must be gone before the next await
+                {
+                    let published = match &mut sensor_link {
+                        // the link delivers on its own time, a failed delivery is handed back for the next frame to requeue
+                        Some(link) if channel.status().connected() => {
+                            let mut copy = 0;
+                            link.send(cycle, processed_data.arm_id, || {
+                                let channel = channel.clone();
+                                let data = processed_data.clone();
+                                let routing_key = routing_key.clone();
+                                let telemetry = telemetry_publisher.clone();
+                                // a duplicate that fails was never the network's to resend
+                                let failed = (copy == 0).then(|| failed_tx.clone());
+                                copy += 1;
+                                async move {
+                                    if let Err(e) = publish(&channel, &data, &routing_key, cycle, telemetry).await {
+                                        eprintln!("Failed to deliver frame {}: {}", cycle, e);
+                                        if let Some(failed) = failed {
+                                            let _ = failed.send((cycle, data));
+                                        }
+                                    }
+                                }
+                            });
+                            Ok(())
+                        }
+                        Some(_) => Err("channel closed".into()),
+                        None => publish(&channel, &processed_data, &routing_key, cycle, telemetry_publisher.clone()).await,
+                    };
+                    if let Err(e) = published {
+                        outbox.requeue((cycle, processed_data));
+                        broker.lost(e);
+                        session = None;
+                        break;
+                    }
+                }
+                // only a frame that left for its arm can be caught, not one dropped in the pipeline or the outbox
+                catches_for_publisher.lock().await.track(&processed_data);
+                // println!("Published sensor data in {} µs",duration);
+            }
+        }
+        // frames still held back by the link go out before the connection closes
+        if let Some(link) = sensor_link {
+            link.close().await;
         }
+        // what failed on the way out counts as undelivered
+        outbox.requeue_failed(&mut failed_rx);
+        if let Some((conn, channel)) = &session {
+            close_session(conn, channel).await;
+        }
+        freshness.print_summary("frames");
+        println!(
+            "> Outbox ({:?}): dropped={} peak={} undelivered={}",
+            outbox.policy,
+            outbox.dropped(),
+            outbox.peak(),
+            outbox.len()
+        );
+        broker.stats.print_summary(&broker.name);
     });
 
-    sensor_task.await.expect("Sensor task panicked");
-
-    // after sensor task finishes, close channel by dropping sender
-    drop(tx_processed);
+    let (mut simulator, detected_anomalies) = sensor_task.await.expect("Sensor task panicked");
+    shutdown.trigger();
+    let cycles_run = *cycle.lock().await - 1;
+    // the sensor task dropped its sender, so the publisher ends once the pipeline is drained
 
-    // now notify shutdown so publisher and feedback consumer can stop
-    shutdown_notify.notify_waiters();
-    feedback_shutdown.notify_waiters();
-
-    // wait for publisher and feedback consumer
+    // the publisher sends what is still queued and closes its connection
     publisher_handle.await.expect("Publisher panicked");
+    // give the arms time to answer the frames still in flight, SHUTDOWN_GRACE_MS
+    let grace = duration_from_env("SHUTDOWN_GRACE_MS", DEFAULT_SHUTDOWN_GRACE);
+    println!("> Draining feedback for {:?}...", grace);
+    tokio::time::sleep(grace).await;
+    consumers_shutdown.trigger();
     feedback_handle.await.expect("Feedback panicked");
+    registry_handle.await.expect("Registry panicked");
+
+    // every telemetry sender is gone now, the writer drains the queue and ends
+    let telemetry_stats = telemetry.finish().await;
+    snapshot_handle.abort();
+    if let Err(e) = histograms.write_snapshot(&run, snapshot_path(&run)) {
+        eprintln!("Failed to write histogram snapshot: {}", e);
+    }
+
+    println!("> Controller ran {} cycle(s), run {}", cycles_run, run.run_id);
+    telemetry_stats.print_summary();
+    histograms.print_summary();
+    let mut catches = catches.lock().await;
+    let outcomes = catches.finish();
+    record_catches(&outcomes, &allocation_stats);
+    simulator.record_catches(&outcomes);
+    catches.print_summary();
+    allocation_stats.print_summary();
+    pipeline_stats.print_summary(pipeline_policy);
+    if let Err(e) = histograms.write_distribution(&run, distribution_path(&run)) {
+        eprintln!("Failed to write latency histograms: {}", e);
+    }
+
+    // a scripted run fails the process when its expectations are not met
+    let failures = simulator.check(detected_anomalies);
+    if let Some(failures) = &failures {
+        let name = &simulator.script().expect("checked a script").name;
+        if failures.is_empty() {
+            println!("> Scenario {}: PASS", name);
+        } else {
+            println!("> Scenario {}: FAIL", name);
+            for failure in failures {
+                println!(">   {}", failure);
+            }
+        }
+    }
 
     println!("Shutdown complete. Exiting.");
+    if failures.is_some_and(|failures| !failures.is_empty()) {
+        std::process::exit(1);
+    }
 }
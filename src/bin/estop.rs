@@ -0,0 +1,65 @@
+// raises or releases an emergency stop from the command line
+// usage: cargo run --bin estop -- [--arm <id>] [--release] [--reason <text>]
+use std::env;
+use std::process;
+
+use lapin::{options::ConfirmSelectOptions, Connection, ConnectionProperties};
+use Real_time_systems_repo::broker::amqp_url;
+use Real_time_systems_repo::data_structure::EmergencyStop;
+use Real_time_systems_repo::estop::raise;
+use Real_time_systems_repo::routing::declare_estop_exchange;
+
+fn usage() -> ! {
+    eprintln!("usage: estop [--arm <id>] [--release] [--reason <text>]");
+    process::exit(2);
+}
+
+#[tokio::main]
+async fn main() {
+    let mut args = env::args().skip(1);
+    let mut arm_id = None;
+    let mut release = false;
+    let mut reason = "operator".to_string();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--arm" => {
+                arm_id = Some(
+                    args.next()
+                        .and_then(|id| id.parse().ok())
+                        .unwrap_or_else(|| usage()),
+                )
+            }
+            "--release" => release = true,
+            "--reason" => reason = args.next().unwrap_or_else(|| usage()),
+            _ => usage(),
+        }
+    }
+
+    let stop = if release {
+        EmergencyStop::release(arm_id, "cli")
+    } else {
+        EmergencyStop::engage(arm_id, "cli", &reason)
+    };
+
+    let conn = Connection::connect(&amqp_url(), ConnectionProperties::default())
+        .await
+        .expect("Connection error");
+    let channel = conn.create_channel().await.expect("Channel creation error");
+    // wait for the broker to take the stop before exiting
+    channel
+        .confirm_select(ConfirmSelectOptions::default())
+        .await
+        .expect("Failed to enable publisher confirms");
+    declare_estop_exchange(&channel)
+        .await
+        .expect("Exchange declaration error");
+    raise(&channel, &stop).await.expect("Failed to raise emergency stop");
+
+    println!(
+        "> Emergency stop {} for {}",
+        if stop.engaged { "raised" } else { "released" },
+        arm_id.map_or("all arms".to_string(), |id| format!("arm {}", id))
+    );
+    let _ = conn.close(200, "OK").await;
+}
@@ -0,0 +1,114 @@
+// replays a recording into the controller or an actuator
+// usage: cargo run --bin replay -- <recording.jsonl> --into <controller|actuator> [--arm <id>] [--pacing <original|fast>] [--keep-timestamps]
+use std::env;
+use std::process;
+
+use lapin::{
+    options::{BasicPublishOptions, ConfirmSelectOptions},
+    BasicProperties, Connection, ConnectionProperties,
+};
+use Real_time_systems_repo::broker::amqp_url;
+use Real_time_systems_repo::recording::{load_recording, replay, Pacing, Stream};
+use Real_time_systems_repo::routing::{
+    declare_controller_topology, feedback_routing_key, sensor_routing_key, FEEDBACK_EXCHANGE, SENSOR_EXCHANGE,
+};
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: replay <recording.jsonl> --into <controller|actuator> [--arm <id>] [--pacing <original|fast>] [--keep-timestamps]"
+    );
+    process::exit(2);
+}
+
+#[tokio::main]
+async fn main() {
+    let mut args = env::args().skip(1);
+    let mut path = None;
+    let mut stream = None;
+    let mut arm_id: Option<u32> = None;
+    let mut pacing = Pacing::Original;
+    let mut restamp = true;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            // the controller is fed feedback, an actuator sensor frames
+            "--into" => {
+                stream = match args.next().as_deref() {
+                    Some("controller") => Some(Stream::Feedback),
+                    Some("actuator") => Some(Stream::Sensor),
+                    _ => usage(),
+                }
+            }
+            "--arm" => {
+                arm_id = Some(
+                    args.next()
+                        .and_then(|id| id.parse().ok())
+                        .unwrap_or_else(|| usage()),
+                )
+            }
+            "--pacing" => {
+                pacing = args
+                    .next()
+                    .and_then(|pacing| pacing.parse().ok())
+                    .unwrap_or_else(|| usage())
+            }
+            "--keep-timestamps" => restamp = false,
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+            _ => usage(),
+        }
+    }
+    let (Some(path), Some(stream)) = (path, stream) else {
+        usage();
+    };
+
+    let messages: Vec<_> = load_recording(&path)
+        .unwrap_or_else(|e| panic!("Invalid recording {}", e))
+        .into_iter()
+        .filter(|message| message.stream == stream && arm_id.is_none_or(|arm_id| message.arm_id == arm_id))
+        .collect();
+    println!("> Replaying {} {:?} messages from {} ({:?})", messages.len(), stream, path, pacing);
+
+    let conn = Connection::connect(&amqp_url(), ConnectionProperties::default())
+        .await
+        .expect("Connection error");
+    let channel = conn.create_channel().await.expect("Channel creation error");
+    channel
+        .confirm_select(ConfirmSelectOptions::default())
+        .await
+        .expect("Failed to enable publisher confirms");
+    declare_controller_topology(&channel)
+        .await
+        .expect("Topology declaration error");
+
+    let stats = replay(messages, pacing, restamp, |message| {
+        let channel = channel.clone();
+        async move {
+            let (exchange, routing_key) = match message.stream {
+                Stream::Sensor => (SENSOR_EXCHANGE, sensor_routing_key(message.arm_id)),
+                Stream::Feedback => (FEEDBACK_EXCHANGE, feedback_routing_key(message.arm_id)),
+            };
+            let payload = serde_json::to_vec(&message.payload).expect("Failed to serialize payload");
+            let published = match channel
+                .basic_publish(
+                    exchange,
+                    &routing_key,
+                    BasicPublishOptions::default(),
+                    &payload,
+                    BasicProperties::default(),
+                )
+                .await
+            {
+                Ok(confirm) => confirm.await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = &published {
+                eprintln!("Failed to replay message received at {}: {}", message.received, e);
+            }
+            published.is_ok()
+        }
+    })
+    .await;
+
+    stats.print_summary(pacing);
+    let _ = conn.close(200, "OK").await;
+}
@@ -0,0 +1,90 @@
+// performance report over one or two sets of latency logs
+// usage: cargo run --bin report -- <log.csv>... [--compare <log.csv>...]
+//        [--md report.md] [--csv report.csv] [--deadline stage=µs]
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::process;
+use Real_time_systems_repo::latency_log::{read_latency_csv, LatencyRecord};
+use Real_time_systems_repo::report::*;
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: report <log.csv>... [--compare <log.csv>...] [--md out.md] [--csv out.csv] [--deadline stage=µs]"
+    );
+    process::exit(2);
+}
+
+fn load_all(paths: &[String]) -> Vec<LatencyRecord> {
+    let mut records = Vec::new();
+    for path in paths {
+        match read_latency_csv(path) {
+            Ok(mut r) => records.append(&mut r),
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", path, e);
+                process::exit(1);
+            }
+        }
+    }
+    records
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let mut run_a = Vec::new();
+    let mut run_b = Vec::new();
+    let mut comparing = false;
+    let mut md_path = None;
+    let mut csv_path = None;
+    let mut deadlines = HashMap::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--compare" => comparing = true,
+            "--md" => md_path = Some(args.next().unwrap_or_else(|| usage())),
+            "--csv" => csv_path = Some(args.next().unwrap_or_else(|| usage())),
+            "--deadline" => {
+                let spec = args.next().unwrap_or_else(|| usage());
+                let (stage, us) = spec.split_once('=').unwrap_or_else(|| usage());
+                deadlines.insert(stage.to_string(), us.parse().unwrap_or_else(|_| usage()));
+            }
+            "-h" | "--help" => usage(),
+            _ if comparing => run_b.push(arg),
+            _ => run_a.push(arg),
+        }
+    }
+    if run_a.is_empty() || (comparing && run_b.is_empty()) {
+        usage();
+    }
+
+    let stats_a = compute_stats(&load_all(&run_a), &deadlines);
+    let mut markdown = format!("# Latency report\n\nLogs: {}\n\n", run_a.join(", "));
+    markdown.push_str(&stats_markdown(&stats_a));
+
+    let mut comparison = Vec::new();
+    if comparing {
+        let stats_b = compute_stats(&load_all(&run_b), &deadlines);
+        comparison = compare(&stats_a, &stats_b);
+        markdown.push_str(&format!("\n## Run B\n\nLogs: {}\n\n", run_b.join(", ")));
+        markdown.push_str(&stats_markdown(&stats_b));
+        markdown.push_str("\n## A vs B\n\n");
+        markdown.push_str(&comparison_markdown(&comparison));
+    }
+
+    match md_path {
+        Some(path) => fs::write(&path, &markdown).expect("Failed to write markdown report"),
+        None => print!("{}", markdown),
+    }
+
+    if let Some(path) = csv_path {
+        write_csv(&stats_a, &path).expect("Failed to write csv summary");
+        if comparing {
+            // comparison goes next to the summary, e.g. report.csv -> report_compare.csv
+            let compare_path = match path.strip_suffix(".csv") {
+                Some(stem) => format!("{}_compare.csv", stem),
+                None => format!("{}_compare", path),
+            };
+            write_csv(&comparison, &compare_path).expect("Failed to write csv comparison");
+        }
+    }
+}
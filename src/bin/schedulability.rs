@@ -0,0 +1,81 @@
+// offline schedulability check of the 5ms loop from a recorded latency log
+// usage: cargo run --bin schedulability -- <latency_csv> [--tasks task_set.csv] [--load high]
+use std::env;
+use std::process;
+use Real_time_systems_repo::schedulability::*;
+
+fn usage() -> ! {
+    eprintln!("usage: schedulability <latency_csv> [--tasks task_set.csv] [--load <profile>]");
+    process::exit(2);
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let mut log_path = None;
+    let mut tasks_path = None;
+    let mut load = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--tasks" => tasks_path = Some(args.next().unwrap_or_else(|| usage())),
+            "--load" => load = Some(args.next().unwrap_or_else(|| usage())),
+            "-h" | "--help" => usage(),
+            _ if log_path.is_none() => log_path = Some(arg),
+            _ => usage(),
+        }
+    }
+    let log_path = log_path.unwrap_or_else(|| usage());
+
+    let tasks = match tasks_path {
+        Some(path) => load_task_set(&path).expect("Failed to read task set"),
+        None => default_task_set(),
+    };
+    let wcets = load_observed_wcets(&log_path, load.as_deref()).expect("Failed to read latency log");
+    let report = analyse(&tasks, &wcets);
+
+    println!(
+        "> Schedulability of {} ({} load)",
+        log_path,
+        load.as_deref().unwrap_or("any")
+    );
+    println!(
+        "{:<24} {:>4} {:>10} {:>10} {:>10} {:>12} {:>10}",
+        "task", "prio", "wcet_µs", "period_µs", "deadline", "response_µs", "margin_µs"
+    );
+    for t in &report.tasks {
+        let response = if t.schedulable() {
+            t.response_time_us.to_string()
+        } else {
+            format!("{} MISS", t.response_time_us)
+        };
+        println!(
+            "{:<24} {:>4} {:>10} {:>10} {:>10} {:>12} {:>10}",
+            t.name, t.priority, t.wcet_us, t.period_us, t.deadline_us, response, t.margin_us()
+        );
+    }
+    for name in &report.missing {
+        println!("[WARNING] No samples for task {}, assumed wcet of 0 µs", name);
+    }
+
+    let verdict = if report.overloaded() {
+        "FAILS, above 1"
+    } else if report.passes_utilization_bound() {
+        "passes"
+    } else {
+        "inconclusive"
+    };
+    println!(
+        "> Utilization: {:.4} (Liu & Layland bound {:.4}) -> {}",
+        report.utilization, report.liu_layland_bound, verdict
+    );
+    if report.schedulable() {
+        println!(
+            "> Loop of {} µs is SCHEDULABLE, minimum margin {} µs",
+            LOOP_PERIOD_US,
+            report.min_margin_us()
+        );
+    } else {
+        println!("> Loop of {} µs is NOT schedulable", LOOP_PERIOD_US);
+        process::exit(1);
+    }
+}
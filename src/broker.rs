@@ -0,0 +1,393 @@
+// supervised broker connections
+// a lost connection is retried with exponential backoff and the caller re-declares its queues
+// and consumers on the new channel, frames produced during an outage wait in a bounded outbox
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use lapin::{Channel, Connection, ConnectionProperties};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use crate::config::parse_env;
+use crate::now_micros;
+
+pub const DEFAULT_AMQP_URL: &str = "amqp://127.0.0.1:5672/%2f";
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+//overridden by OUTBOX_CAPACITY, 1s worth of 5ms frames
+pub const DEFAULT_OUTBOX_CAPACITY: usize = 200;
+
+/// Broker address from `AMQP_URL`, the local broker otherwise.
+pub fn amqp_url() -> String {
+    std::env::var("AMQP_URL").unwrap_or_else(|_| DEFAULT_AMQP_URL.to_string())
+}
+
+/// Doubles the delay after every failure, up to a cap.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Backoff {
+            initial,
+            max,
+            current: initial,
+        }
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = (self.current * 2).min(self.max);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(INITIAL_BACKOFF, MAX_BACKOFF)
+    }
+}
+
+/// Outage metrics of one connection.
+#[derive(Debug, Default)]
+pub struct BrokerStats {
+    outages: AtomicU64,
+    failed_attempts: AtomicU64,
+    reconnects: AtomicU64,
+    downtime_us: AtomicU64,
+    // 0 while connected
+    down_since: AtomicU64,
+}
+
+impl BrokerStats {
+    fn mark_down(&self) {
+        let now = now_micros() as u64;
+        if self
+            .down_since
+            .compare_exchange(0, now, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+        {
+            self.outages.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn mark_up(&self) {
+        let since = self.down_since.swap(0, Ordering::Relaxed);
+        if since > 0 {
+            self.downtime_us
+                .fetch_add((now_micros() as u64).saturating_sub(since), Ordering::Relaxed);
+            self.reconnects.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn outages(&self) -> u64 {
+        self.outages.load(Ordering::Relaxed)
+    }
+
+    pub fn reconnects(&self) -> u64 {
+        self.reconnects.load(Ordering::Relaxed)
+    }
+
+    /// Total time without a connection, including an outage still going on.
+    pub fn downtime(&self) -> Duration {
+        let since = self.down_since.load(Ordering::Relaxed);
+        let ongoing = if since > 0 {
+            (now_micros() as u64).saturating_sub(since)
+        } else {
+            0
+        };
+        Duration::from_micros(self.downtime_us.load(Ordering::Relaxed) + ongoing)
+    }
+
+    pub fn print_summary(&self, name: &str) {
+        println!(
+            "> Broker link {}: outages={} reconnects={} failed_attempts={} downtime={:?}",
+            name,
+            self.outages(),
+            self.reconnects(),
+            self.failed_attempts.load(Ordering::Relaxed),
+            self.downtime()
+        );
+    }
+}
+
+/// One supervised connection. `connect` keeps trying until the broker is back,
+/// `try_connect` makes at most one attempt so a producer can keep buffering meanwhile.
+pub struct Broker {
+    pub name: String,
+    pub stats: Arc<BrokerStats>,
+    url: String,
+    backoff: Backoff,
+    next_attempt: Option<Instant>,
+}
+
+impl Broker {
+    pub fn new(name: &str) -> Self {
+        Broker {
+            name: name.to_string(),
+            stats: Default::default(),
+            url: amqp_url(),
+            backoff: Backoff::default(),
+            next_attempt: None,
+        }
+    }
+
+    /// Opens a connection and a channel and runs `setup` (qos, declarations) on it,
+    /// None when the attempt failed or the backoff delay has not passed yet.
+    pub async fn try_connect<F, Fut>(&mut self, setup: &F) -> Option<(Connection, Channel)>
+    where
+        F: Fn(Channel) -> Fut,
+        Fut: Future<Output = lapin::Result<()>>,
+    {
+        if self.next_attempt.is_some_and(|at| Instant::now() < at) {
+            return None;
+        }
+        match self.open(setup).await {
+            Ok(session) => {
+                if self.stats.down_since.load(Ordering::Relaxed) > 0 {
+                    println!("> Broker link {} reconnected", self.name);
+                }
+                self.stats.mark_up();
+                self.backoff.reset();
+                self.next_attempt = None;
+                Some(session)
+            }
+            Err(e) => {
+                self.stats.failed_attempts.fetch_add(1, Ordering::Relaxed);
+                self.stats.mark_down();
+                let delay = self.backoff.next_delay();
+                eprintln!("Broker link {} connection failed: {}, retrying in {:?}", self.name, e, delay);
+                self.next_attempt = Some(Instant::now() + delay);
+                None
+            }
+        }
+    }
+
+    async fn open<F, Fut>(&self, setup: &F) -> lapin::Result<(Connection, Channel)>
+    where
+        F: Fn(Channel) -> Fut,
+        Fut: Future<Output = lapin::Result<()>>,
+    {
+        let conn = Connection::connect(&self.url, ConnectionProperties::default()).await?;
+        let channel = conn.create_channel().await?;
+        setup(channel.clone()).await?;
+        Ok((conn, channel))
+    }
+
+    /// Retries with backoff until connected.
+    pub async fn connect<F, Fut>(&mut self, setup: F) -> (Connection, Channel)
+    where
+        F: Fn(Channel) -> Fut,
+        Fut: Future<Output = lapin::Result<()>>,
+    {
+        loop {
+            if let Some(session) = self.try_connect(&setup).await {
+                return session;
+            }
+            if let Some(at) = self.next_attempt {
+                tokio::time::sleep_until(at).await;
+            }
+        }
+    }
+
+    /// Call when an established connection fails, starts counting downtime.
+    pub fn lost(&mut self, error: impl Debug) {
+        if self.stats.down_since.load(Ordering::Relaxed) == 0 {
+            eprintln!("Broker link {} lost: {:?}", self.name, error);
+        }
+        self.stats.mark_down();
+    }
+}
+
+/// What a full outbox gives up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutboxPolicy {
+    // drop the oldest frame to make room, the actuator gets the freshest data on reconnect
+    KeepNewest,
+    // refuse new frames, the oldest are delivered first
+    DropNewest,
+}
+
+impl FromStr for OutboxPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep-newest" => Ok(OutboxPolicy::KeepNewest),
+            "drop-newest" => Ok(OutboxPolicy::DropNewest),
+            other => Err(format!("unknown outbox policy: {}", other)),
+        }
+    }
+}
+
+/// Bounded buffer in front of a publisher.
+#[derive(Debug)]
+pub struct Outbox<T> {
+    items: VecDeque<T>,
+    capacity: usize,
+    pub policy: OutboxPolicy,
+    dropped: u64,
+    peak: usize,
+}
+
+impl<T> Outbox<T> {
+    pub fn new(capacity: usize, policy: OutboxPolicy) -> Self {
+        Outbox {
+            items: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+            policy,
+            dropped: 0,
+            peak: 0,
+        }
+    }
+
+    /// `OUTBOX_CAPACITY` and `OUTBOX_POLICY` (`keep-newest` or `drop-newest`), keep newest by default.
+    pub fn from_env() -> Self {
+        let capacity = parse_env("OUTBOX_CAPACITY").unwrap_or(DEFAULT_OUTBOX_CAPACITY);
+        let policy = parse_env("OUTBOX_POLICY").unwrap_or(OutboxPolicy::KeepNewest);
+        Self::new(capacity, policy)
+    }
+
+    /// Queues an item, returns false when something was dropped to respect the bound.
+    pub fn push(&mut self, item: T) -> bool {
+        if self.items.len() >= self.capacity {
+            self.dropped += 1;
+            match self.policy {
+                OutboxPolicy::KeepNewest => {
+                    self.items.pop_front();
+                }
+                OutboxPolicy::DropNewest => return false,
+            }
+            self.items.push_back(item);
+            return false;
+        }
+        self.items.push_back(item);
+        self.peak = self.peak.max(self.items.len());
+        true
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        self.items.pop_front()
+    }
+
+    /// Puts back an item whose delivery failed, ahead of everything else.
+    pub fn requeue(&mut self, item: T) {
+        if self.items.len() >= self.capacity {
+            self.dropped += 1;
+            if self.policy == OutboxPolicy::KeepNewest {
+                return;
+            }
+            self.items.pop_back();
+        }
+        self.items.push_front(item);
+    }
+
+    /// Requeues whatever came back undelivered from an asynchronous sender, oldest still first.
+    /// Returns whether anything had failed.
+    pub fn requeue_failed(&mut self, failed: &mut mpsc::UnboundedReceiver<T>) -> bool {
+        let mut items = Vec::new();
+        while let Ok(item) = failed.try_recv() {
+            items.push(item);
+        }
+        let any = !items.is_empty();
+        for item in items.into_iter().rev() {
+            self.requeue(item);
+        }
+        any
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    pub fn peak(&self) -> usize {
+        self.peak
+    }
+}
+
+/// Closes a session on shutdown, failures are only reported since the process is leaving anyway.
+pub async fn close_session(conn: &Connection, channel: &Channel) {
+    if channel.status().connected() {
+        if let Err(e) = channel.close(200, "shutdown").await {
+            eprintln!("Failed to close channel: {:?}", e);
+        }
+    }
+    if conn.status().connected() {
+        if let Err(e) = conn.close(200, "shutdown").await {
+            eprintln!("Failed to close connection: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(outbox: &mut Outbox<i32>) -> Vec<i32> {
+        std::iter::from_fn(|| outbox.pop()).collect()
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap_and_resets() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_millis(500));
+        let delays: Vec<u128> = (0..5).map(|_| backoff.next_delay().as_millis()).collect();
+        assert_eq!(delays, [100, 200, 400, 500, 500]);
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn full_outbox_drops_the_oldest() {
+        let mut outbox = Outbox::new(3, OutboxPolicy::KeepNewest);
+        assert!((1..=3).all(|item| outbox.push(item)));
+        assert!(!outbox.push(4));
+        assert!(!outbox.push(5));
+        assert_eq!((outbox.dropped(), outbox.peak()), (2, 3));
+        assert_eq!(drain(&mut outbox), [3, 4, 5]);
+    }
+
+    #[test]
+    fn full_outbox_can_drop_the_newest_instead() {
+        let mut outbox = Outbox::new(2, OutboxPolicy::DropNewest);
+        outbox.push(1);
+        outbox.push(2);
+        assert!(!outbox.push(3));
+        // a failed delivery is older than anything queued, it goes back ahead of the newest
+        outbox.requeue(0);
+        assert_eq!(outbox.dropped(), 2);
+        assert_eq!(drain(&mut outbox), [0, 1]);
+    }
+
+    #[test]
+    fn failed_deliveries_go_back_ahead_in_order() {
+        let mut outbox = Outbox::new(4, OutboxPolicy::KeepNewest);
+        let (failed_tx, mut failed_rx) = mpsc::unbounded_channel();
+        assert!(!outbox.requeue_failed(&mut failed_rx));
+        outbox.push(3);
+        failed_tx.send(1).unwrap();
+        failed_tx.send(2).unwrap();
+        assert!(outbox.requeue_failed(&mut failed_rx));
+        assert_eq!(drain(&mut outbox), [1, 2, 3]);
+    }
+}
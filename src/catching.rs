@@ -0,0 +1,409 @@
+// catch evaluation
+// every published frame tells where its object will land and when, every feedback tells where an arm
+// put its wrist and when it got there; once the object has landed the last attempt made in time decides
+// whether it was caught, within a tolerance that grows with the object's size, and with a grip
+// strong enough to arrest it
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::time::Duration;
+
+use crate::config::{duration_from_env, open_append_csv, parse_env};
+use crate::data_structure::{FeedbackData, GripAssessment, GripVerdict, SensorArmData};
+use crate::latency_log::RunInfo;
+use crate::world::GRAVITY;
+
+const CATCH_LOG_HEADER: [&str; 13] = [
+    "run_id",
+    "object_id",
+    "arm_id",
+    "result",
+    "landed_x",
+    "landed_y",
+    "landed_us",
+    "distance_cm",
+    "tolerance_cm",
+    "margin_us",
+    "grip",
+    "required_n",
+    "applied_n",
+];
+
+// landed objects wait this long for feedback still on its way
+pub const DEFAULT_SETTLE_GRACE: Duration = Duration::from_millis(100);
+
+/// How far (cm) the wrist may be from the object's landing point: `base_cm + size_factor * object_size`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CatchTolerance {
+    pub base_cm: f32,
+    pub size_factor: f32,
+}
+
+impl Default for CatchTolerance {
+    fn default() -> Self {
+        CatchTolerance {
+            base_cm: 0.5,
+            size_factor: 0.25,
+        }
+    }
+}
+
+impl CatchTolerance {
+    /// Defaults adjusted by `CATCH_TOLERANCE_CM` and `CATCH_SIZE_FACTOR`.
+    pub fn from_env() -> Self {
+        let default = CatchTolerance::default();
+        CatchTolerance {
+            base_cm: parse_env("CATCH_TOLERANCE_CM").unwrap_or(default.base_cm),
+            size_factor: parse_env("CATCH_SIZE_FACTOR").unwrap_or(default.size_factor),
+        }
+    }
+
+    pub fn for_size(&self, object_size: f32) -> f32 {
+        self.base_cm + self.size_factor * object_size
+    }
+}
+
+/// Seconds until an object `height` m up, falling at `velocity` m/s, reaches the catch plane.
+pub fn fall_time(height: f32, velocity: f32) -> f32 {
+    let height = height.max(0.0);
+    ((velocity * velocity + 2.0 * GRAVITY * height).sqrt() - velocity) / GRAVITY
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CatchResult {
+    Caught,
+    // no arm answered any of its frames
+    NoAttempt,
+    // the wrist got there after the object
+    Late,
+    // in time, but too far from the landing point
+    OutOfTolerance,
+    // in place, but the grip could not arrest it
+    Dropped,
+}
+
+#[derive(Debug, Clone)]
+pub struct CatchOutcome {
+    pub object_id: u64,
+    // the arm of the deciding attempt
+    pub arm_id: Option<u32>,
+    pub result: CatchResult,
+    pub landed_at: (f32, f32),
+    pub landed_us: u128,
+    pub distance_cm: f32,
+    pub tolerance_cm: f32,
+    // landing time minus the time the wrist was in place, negative when late
+    pub margin_us: i128,
+    pub grip: Option<GripAssessment>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Attempt {
+    arm_id: u32,
+    wrist: (f32, f32),
+    at: u128,
+    grip: Option<GripAssessment>,
+}
+
+#[derive(Debug, Clone)]
+struct Tracked {
+    landing_point: (f32, f32),
+    size: f32,
+    landing_us: u128,
+    attempt: Option<Attempt>,
+}
+
+pub struct CatchEvaluator {
+    pub tolerance: CatchTolerance,
+    pub grace: Duration,
+    run_id: String,
+    log: Option<csv::Writer<File>>,
+    tracked: BTreeMap<u64, Tracked>,
+    results: BTreeMap<CatchResult, u64>,
+    // caught and decided per arm
+    per_arm: BTreeMap<u32, (u64, u64)>,
+}
+
+impl CatchEvaluator {
+    /// Outcomes are appended to `catches_<process>.csv`.
+    pub fn new(tolerance: CatchTolerance, grace: Duration, run: &RunInfo) -> Self {
+        let log = open_append_csv(format!("catches_{}.csv", run.process), &CATCH_LOG_HEADER)
+            .map_err(|e| eprintln!("Failed to open catch log: {}", e))
+            .ok();
+        CatchEvaluator {
+            tolerance,
+            grace,
+            run_id: run.run_id.clone(),
+            log,
+            tracked: BTreeMap::new(),
+            results: BTreeMap::new(),
+            per_arm: BTreeMap::new(),
+        }
+    }
+
+    /// `CatchTolerance::from_env` and `CATCH_SETTLE_MS`.
+    pub fn from_env(run: &RunInfo) -> Self {
+        Self::new(
+            CatchTolerance::from_env(),
+            duration_from_env("CATCH_SETTLE_MS", DEFAULT_SETTLE_GRACE),
+            run,
+        )
+    }
+
+    /// A frame going out to an arm, the newest frame of an object gives its landing prediction.
+    pub fn track(&mut self, frame: &SensorArmData) {
+        let object = &frame.object_data;
+        let fall_us = (fall_time(object.object_height, object.object_velocity) * 1_000_000.0) as u128;
+        let landing_us = frame.trace.generated.max(frame.timestamp) + fall_us;
+        let tracked = self.tracked.entry(frame.trace.object_id).or_insert(Tracked {
+            landing_point: (0.0, 0.0),
+            size: 0.0,
+            landing_us,
+            attempt: None,
+        });
+        tracked.landing_point = (object.object_x, object.object_y);
+        tracked.size = object.object_size;
+        tracked.landing_us = landing_us;
+    }
+
+    /// An arm's answer, the last attempt in place before landing counts.
+    pub fn attempt(&mut self, feedback: &FeedbackData) {
+        let Some(tracked) = self.tracked.get_mut(&feedback.trace.object_id) else {
+            return;
+        };
+        let at = match feedback.trace.actuated {
+            0 => feedback.timestamp,
+            actuated => actuated,
+        };
+        let attempt = Attempt {
+            arm_id: feedback.arm_id,
+            wrist: (feedback.wrist.wrist_x, feedback.wrist.wrist_y),
+            at,
+            grip: feedback.grip,
+        };
+        // a late attempt only stands in when there is nothing better
+        if at <= tracked.landing_us || tracked.attempt.is_none() {
+            tracked.attempt = Some(attempt);
+        }
+    }
+
+    /// Scores the objects that landed more than the grace period before `now`.
+    pub fn settle(&mut self, now: u128) -> Vec<CatchOutcome> {
+        let cutoff = now.saturating_sub(self.grace.as_micros());
+        let landed: Vec<u64> = self
+            .tracked
+            .iter()
+            .filter(|(_, tracked)| tracked.landing_us <= cutoff)
+            .map(|(id, _)| *id)
+            .collect();
+        landed.into_iter().map(|id| self.decide(id)).collect()
+    }
+
+    /// Scores everything still tracked, at the end of the run.
+    pub fn finish(&mut self) -> Vec<CatchOutcome> {
+        let ids: Vec<u64> = self.tracked.keys().copied().collect();
+        ids.into_iter().map(|id| self.decide(id)).collect()
+    }
+
+    fn decide(&mut self, object_id: u64) -> CatchOutcome {
+        let tracked = self.tracked.remove(&object_id).expect("deciding a tracked object");
+        let tolerance_cm = self.tolerance.for_size(tracked.size);
+        let (x, y) = tracked.landing_point;
+        let (result, distance_cm, margin_us) = match tracked.attempt {
+            None => (CatchResult::NoAttempt, f32::INFINITY, 0),
+            Some(attempt) => {
+                let distance = ((attempt.wrist.0 - x).powi(2) + (attempt.wrist.1 - y).powi(2)).sqrt();
+                let margin = tracked.landing_us as i128 - attempt.at as i128;
+                let result = if margin < 0 {
+                    CatchResult::Late
+                } else if distance > tolerance_cm {
+                    CatchResult::OutOfTolerance
+                } else if attempt.grip.is_some_and(|grip| grip.verdict != GripVerdict::Holds) {
+                    CatchResult::Dropped
+                } else {
+                    CatchResult::Caught
+                };
+                (result, distance, margin)
+            }
+        };
+        let outcome = CatchOutcome {
+            object_id,
+            arm_id: tracked.attempt.map(|attempt| attempt.arm_id),
+            result,
+            landed_at: tracked.landing_point,
+            landed_us: tracked.landing_us,
+            distance_cm,
+            tolerance_cm,
+            margin_us,
+            grip: tracked.attempt.and_then(|attempt| attempt.grip),
+        };
+        *self.results.entry(result).or_default() += 1;
+        if let Some(arm_id) = outcome.arm_id {
+            let arm = self.per_arm.entry(arm_id).or_default();
+            arm.1 += 1;
+            if result == CatchResult::Caught {
+                arm.0 += 1;
+            }
+        }
+        self.write(&outcome);
+        outcome
+    }
+
+    fn write(&mut self, outcome: &CatchOutcome) {
+        let Some(log) = &mut self.log else {
+            return;
+        };
+        let row = [
+            self.run_id.clone(),
+            outcome.object_id.to_string(),
+            outcome.arm_id.map_or(String::new(), |arm_id| arm_id.to_string()),
+            format!("{:?}", outcome.result),
+            format!("{:.3}", outcome.landed_at.0),
+            format!("{:.3}", outcome.landed_at.1),
+            outcome.landed_us.to_string(),
+            format!("{:.3}", outcome.distance_cm),
+            format!("{:.3}", outcome.tolerance_cm),
+            outcome.margin_us.to_string(),
+            outcome.grip.map_or(String::new(), |grip| format!("{:?}", grip.verdict)),
+            outcome.grip.map_or(String::new(), |grip| format!("{:.3}", grip.required)),
+            outcome.grip.map_or(String::new(), |grip| format!("{:.3}", grip.applied)),
+        ];
+        if let Err(e) = log.write_record(&row).and_then(|_| log.flush().map_err(Into::into)) {
+            eprintln!("Failed to write catch log: {}", e);
+        }
+    }
+
+    pub fn decided(&self) -> u64 {
+        self.results.values().sum()
+    }
+
+    pub fn caught(&self) -> u64 {
+        self.results.get(&CatchResult::Caught).copied().unwrap_or(0)
+    }
+
+    /// Caught over decided objects, 0 before any was decided.
+    pub fn catch_rate(&self) -> f64 {
+        self.caught() as f64 / self.decided().max(1) as f64
+    }
+
+    pub fn print_summary(&self) {
+        let count = |result| self.results.get(&result).copied().unwrap_or(0);
+        println!(
+            "> Catches: objects={} caught={} late={} out_of_tolerance={} dropped={} no_attempt={} catch_rate={:.1}%",
+            self.decided(),
+            count(CatchResult::Caught),
+            count(CatchResult::Late),
+            count(CatchResult::OutOfTolerance),
+            count(CatchResult::Dropped),
+            count(CatchResult::NoAttempt),
+            self.catch_rate() * 100.0
+        );
+        for (arm_id, (caught, attempted)) in &self.per_arm {
+            println!(
+                ">   arm {}: caught {} of {} ({:.1}%)",
+                arm_id,
+                caught,
+                attempted,
+                *caught as f64 / (*attempted).max(1) as f64 * 100.0
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_structure::ObjectData;
+
+    const LANDING_US: u128 = 1_000_000;
+
+    fn evaluator() -> CatchEvaluator {
+        CatchEvaluator {
+            tolerance: CatchTolerance::default(),
+            grace: Duration::from_millis(100),
+            run_id: String::new(),
+            log: None,
+            tracked: BTreeMap::new(),
+            results: BTreeMap::new(),
+            per_arm: BTreeMap::new(),
+        }
+    }
+
+    // an object of size 4 at (1, 2) on the catch plane, landing at LANDING_US
+    fn frame(object_id: u64) -> SensorArmData {
+        let mut frame = SensorArmData::new(ObjectData {
+            object_velocity: 10.0,
+            object_mass: 2.0,
+            object_size: 4.0,
+            object_x: 1.0,
+            object_y: 2.0,
+            object_height: 0.0,
+        });
+        frame.trace.object_id = object_id;
+        frame.trace.generated = LANDING_US;
+        frame.timestamp = LANDING_US;
+        frame
+    }
+
+    fn feedback(object_id: u64, wrist: (f32, f32), actuated: u128, grip: Option<GripAssessment>) -> FeedbackData {
+        let mut feedback = frame(object_id).to_feedback(0);
+        feedback.arm_id = 1;
+        feedback.wrist.wrist_x = wrist.0;
+        feedback.wrist.wrist_y = wrist.1;
+        feedback.trace.actuated = actuated;
+        feedback.grip = grip;
+        feedback
+    }
+
+    #[test]
+    fn fall_time_matches_the_analytic_fall() {
+        assert_eq!(fall_time(0.0, 10.0), 0.0);
+        // from rest, h = g t² / 2
+        assert!((fall_time(GRAVITY / 2.0, 0.0) - 1.0).abs() < 1e-6);
+        // v t + g t² / 2 = h
+        let t = fall_time(1.5, 10.0);
+        assert!((10.0 * t + GRAVITY * t * t / 2.0 - 1.5).abs() < 1e-5);
+        // already below the plane
+        assert_eq!(fall_time(-1.0, 10.0), 0.0);
+    }
+
+    #[test]
+    fn attempts_are_scored_on_time_place_and_grip() {
+        let mut catches = evaluator();
+        for object_id in 1..=5 {
+            catches.track(&frame(object_id));
+        }
+        let slips = GripAssessment {
+            required: 10.0,
+            requested: 5.0,
+            applied: 5.0,
+            verdict: GripVerdict::Slips,
+        };
+        catches.attempt(&feedback(1, (1.5, 2.5), LANDING_US - 10, None));
+        // too late to replace the attempt made in time
+        catches.attempt(&feedback(1, (9.0, 9.0), LANDING_US + 10, None));
+        catches.attempt(&feedback(2, (1.0, 2.0), LANDING_US + 10, None));
+        // tolerance is 0.5 + 0.25 * 4 = 1.5cm
+        catches.attempt(&feedback(3, (3.0, 2.0), LANDING_US - 10, None));
+        catches.attempt(&feedback(4, (1.0, 2.0), LANDING_US - 10, Some(slips)));
+
+        // landed feedback may still be on its way
+        assert!(catches.settle(LANDING_US + 99_999).is_empty());
+        let outcomes = catches.settle(LANDING_US + 100_000);
+        let results: Vec<CatchResult> = outcomes.iter().map(|outcome| outcome.result).collect();
+        assert_eq!(
+            results,
+            [
+                CatchResult::Caught,
+                CatchResult::Late,
+                CatchResult::OutOfTolerance,
+                CatchResult::Dropped,
+                CatchResult::NoAttempt,
+            ]
+        );
+        assert_eq!(outcomes[0].margin_us, 10);
+        assert_eq!(outcomes[1].margin_us, -10);
+        assert_eq!(outcomes[4].arm_id, None);
+        assert_eq!((catches.decided(), catches.caught()), (5, 1));
+        assert!(catches.finish().is_empty());
+    }
+}
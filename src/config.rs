@@ -0,0 +1,73 @@
+// configuration helpers shared by every module
+// env vars are read once at startup through these, and the csv logs are all opened the same way
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Value of an env var, None when unset, panics naming the var when it does not parse.
+pub fn parse_env<T: FromStr>(var: &str) -> Option<T> {
+    std::env::var(var)
+        .ok()
+        .map(|v| v.parse().unwrap_or_else(|_| panic!("Invalid {}: {}", var, v)))
+}
+
+/// Duration in ms from an env var, `default` when unset, panics when it does not parse.
+pub fn duration_from_env(var: &str, default: Duration) -> Duration {
+    parse_env(var).map_or(default, Duration::from_millis)
+}
+
+/// A flag from an env var, `1`/`true`/`on`/`yes` or `0`/`false`/`off`/`no`, `default` when unset.
+pub fn bool_from_env(var: &str, default: bool) -> bool {
+    let Ok(value) = std::env::var(var) else {
+        return default;
+    };
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" | "on" | "yes" => true,
+        "0" | "false" | "off" | "no" => false,
+        _ => panic!("Invalid {}: {}, expected 1 or 0", var, value),
+    }
+}
+
+/// Opens a csv log for appending, `header` is only written when the file is new.
+pub fn open_append_csv<P: AsRef<Path>>(path: P, header: &[&str]) -> Result<csv::Writer<File>, Box<dyn Error>> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let is_new = file.metadata()?.len() == 0;
+    let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(file);
+    if is_new {
+        writer.write_record(header)?;
+    }
+    Ok(writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every test sets its own variables, the tests run in parallel
+    #[test]
+    fn flags_agree_on_on_and_off() {
+        for (value, expected) in [("1", true), ("true", true), ("ON", true), ("0", false), ("false", false), ("no", false)] {
+            std::env::set_var("CONFIG_TEST_FLAG", value);
+            assert_eq!(bool_from_env("CONFIG_TEST_FLAG", !expected), expected, "{}", value);
+        }
+        std::env::remove_var("CONFIG_TEST_FLAG");
+        assert!(bool_from_env("CONFIG_TEST_FLAG", true));
+        assert!(!bool_from_env("CONFIG_TEST_FLAG", false));
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid CONFIG_TEST_BAD_FLAG")]
+    fn unknown_flag_value_names_the_var() {
+        std::env::set_var("CONFIG_TEST_BAD_FLAG", "maybe");
+        bool_from_env("CONFIG_TEST_BAD_FLAG", false);
+    }
+
+    #[test]
+    fn durations_are_milliseconds() {
+        std::env::set_var("CONFIG_TEST_MS", "250");
+        assert_eq!(duration_from_env("CONFIG_TEST_MS", Duration::ZERO), Duration::from_millis(250));
+        assert_eq!(duration_from_env("CONFIG_TEST_UNSET_MS", Duration::from_secs(1)), Duration::from_secs(1));
+    }
+}
@@ -2,7 +2,7 @@ use std::{
     sync::{
         Arc,
     },
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 use futures_util::stream::StreamExt;
@@ -14,9 +14,10 @@ use lapin::{
 };
 use serde::Serialize;
 use serde_json;
-use tokio::sync::{mpsc, Mutex, Notify};
-use fastrand;
+use tokio::sync::{Mutex, Notify};
 use crate::data_structure::*;
+use crate::routing::*;
+use crate::simulation::SensorSimulator;
 
 
 pub fn now_micros() -> u128 {
@@ -30,145 +31,20 @@ pub fn detect_anomaly(value: f32, lower: f32, upper: f32) -> bool {
     value < lower || value > upper
 }
 
-pub fn generate_anomalous_object_data() -> ObjectData {
-    ObjectData {
-        // velocity is very low or 0, indicating no drop or static obstruction like a hand
-        object_velocity: fastrand::f32() * 1.0, // 0–1 m/s (very slow or static)
-
-        // since mass can change, the heavier the object the more difficult it is to catch as there is more momentum thus more velocity
-        // anomaly: very heavy (hand = 400–600g vs normal 1–5g)
-        object_mass: 100.0 + fastrand::f32() * 500.0, // 100g–600g = unexpected
-
-        // assume size is variable in small range of 4-5l
-        // anomaly: either too small or much larger object
-        object_size: 10.0 + fastrand::f32() * 20.0, // 10–30L = abnormal for expected object
-
-        // distance changes due to the object being let go at different areas of the tube, where tube is a circle with diameter of 3cm
-        // anomaly: object is placed very close or far off, like a hand waving or blocking the tube
-        object_x: 7.0 + fastrand::f32() * 3.0, 
-
-        object_y: 7.0 + fastrand::f32() * 4.0,
-
-        object_height: 0.0,
-    }
-}
-
-pub fn generate_normal_object_data() -> ObjectData {
-    // Normal falling object
-    ObjectData {
-        //velocity > mass since v = u + at, where v = final velocity, u = initial velocity(at rest so 0), a = acceleration (gravity 9.8), t = time (object has to be caught at 1s)
-        // up to 11.8 m/s and the object can be heavier than 1g
-        object_velocity: 9.8 + fastrand::f32() * 2.0,
-        //since mass can change, the heavier the object the more difficult it is to catch as there is more momentum thus more velocity
-        //thus variability in velocity is needed
-        //1 - 5g
-        object_mass: 1.0 + fastrand::f32() * 4.0,
-        //assume size is variable in small range of 4-5l
-        object_size: 4.0 + fastrand::f32(),
-        //distance changes due to the object being let go at different areas of the tube, where tube is a circle with diameter of 3cm
-        //thus object is at any point within the tube(circle)
-        //need x and y to tell where the robotic arm is in relation to the object to catch it
-        //where x is front back y is left right
-        //max object distance is 3cm(diameter of tube) for x and y so 4cm is a good range if accounting for some wind 
-        //tube infront by 4cm, assume tube is 3cm in diameter
-        object_x: 4.0 + fastrand::f32() * 3.0,
-        //-5 to 5
-        object_y: (fastrand::f32() * 10.0) - 5.0,
-        //object height is the distance from the top of the tube to the object
-        //calculated later based on arm
-        object_height: 0.0,
-    }
-}
-
-
-fn branchless_clamp(val: f32, min: f32, max: f32) -> f32 {
-    val.max(min).min(max)
-}
-
 pub async fn generate_sensor_data(
+    simulator: &mut SensorSimulator,
     cycle: u64,
     shared_feedback: Arc<Mutex<Option<FeedbackData>>>
-) -> SensorArmData {
-    // Use fastrand directly to generate variables below
-
-    let object_data = if cycle % 10 == 0 {
-        // Every 10th cycle, simulate an anomaly (like hand)
-        generate_anomalous_object_data()
-    } else {
-        generate_normal_object_data()
-    };
-
-    let mut sensor_data = SensorArmData::new(object_data.clone());
-    sensor_data.update_object_data(object_data);
-
-    //realistic segment lengths (upper and lower arm)
-    //l1 = shoulder to elbow (1–4cm), l2 = elbow to wrist (4–7cm)
-    let l1 = 1.0 + fastrand::f32() * 3.0; // 1cm base + 0–3cm range = 1–4cm
-    let l2 = 4.0 + fastrand::f32() * 3.0; // 4–7cm
-
-    //angle from 0 (right) to π (left), but we clamp it to [0, π/2] for safe forward-right region
-    let theta1 = fastrand::f32() * std::f32::consts::FRAC_PI_2; // [0, π/2]
-    //elbow bend ±90°, so -π/2 to π/2 range is OK
-    let theta2 = (fastrand::f32() - 0.5) * std::f32::consts::PI;
-
+) -> Option<SensorArmData> {
     // Lock and clone only once
-    let feedback_opt = {
-        let guard = shared_feedback.lock().await;
-        (*guard).clone()
-    };
-
-    // Use the cloned data outside the lock
-    if let Some(feedback) = feedback_opt {
-        sensor_data.joints.shoulder_x = feedback.joints.shoulder_x;
-        sensor_data.joints.shoulder_y = feedback.joints.shoulder_y;
-        sensor_data.elbow.elbow_x = feedback.elbow.elbow_x;
-        sensor_data.elbow.elbow_y = feedback.elbow.elbow_y;
-        sensor_data.wrist.wrist_x = feedback.wrist.wrist_x;
-        sensor_data.wrist.wrist_y = feedback.wrist.wrist_y;
-    }
-    else {
-        //using forward kinematics to calculate arm positions
-        //wrist length > elbow length > shoulder length
-        //shoulder is the base of the arm, so it is the least variable
-        //shoulder length can vary from 0cm to 1cm
-        sensor_data.joints.shoulder_x = fastrand::f32() * 1.0;
-        sensor_data.joints.shoulder_y = (fastrand::f32() * 3.0) - 1.5; // y: [-1.5, 1.5]
-        //using FK to get elbow position from shoulder + angle + l1
-        //this models the upper arm segment
-        sensor_data.elbow.elbow_x = sensor_data.joints.shoulder_x + l1 * theta1.cos();
-        sensor_data.elbow.elbow_y = sensor_data.joints.shoulder_y + l1 * theta1.sin();
-        sensor_data.elbow.elbow_x = branchless_clamp(sensor_data.elbow.elbow_x, 0.0, 7.0);
-        sensor_data.elbow.elbow_y = branchless_clamp(sensor_data.elbow.elbow_y, -1.5, 1.5);
-
-        //wrist is the end of the forearm, which bends at the elbow
-        //direction is based on total angle (shoulder + elbow joint)
-        sensor_data.wrist.wrist_x = sensor_data.elbow.elbow_x + l2 * (theta1 + theta2).cos();
-        sensor_data.wrist.wrist_y = sensor_data.elbow.elbow_y + l2 * (theta1 + theta2).sin();
-
-        // clamp wrist_x to ≥ shoulder_x
-        if sensor_data.wrist.wrist_x < sensor_data.joints.shoulder_x {
-            sensor_data.wrist.wrist_x = sensor_data.joints.shoulder_x;
-        }
-
-        // clamp wrist_y to [-1.5, 1.5]
-        sensor_data.wrist.wrist_y = branchless_clamp(sensor_data.wrist.wrist_y, -1.5, 1.5);
-    }
-
-    //suggested arm velocity to catch object
-    sensor_data.arm_velocity = fastrand::f32() * 10.0;
-
-    //arm strength is a crude estimate based on F = m * a,
-    //assuming velocity is proportional to acceleration here
-    sensor_data.arm_strength = sensor_data.arm_velocity * sensor_data.object_data.object_mass;
-    sensor_data.object_data.object_height = sensor_data.joints.shoulder_y + l1 * theta1.sin() + l2 * (theta1 + theta2).sin();
-    sensor_data.timestamp = now_micros();
-
-    sensor_data
+    let feedback_opt = shared_feedback.lock().await.clone();
+    simulator.generate(cycle, feedback_opt.as_ref())
 }
 
 pub async fn publish<T>(
     channel: &lapin::Channel,
     data: &T,
+    routing_key: &str,
 ) -> Result<(), Box<dyn std::error::Error>>
 where
     T: Serialize,
@@ -176,8 +52,8 @@ where
     let payload = serde_json::to_vec(data)?;
     channel
         .basic_publish(
-            "",
-            "sensor_data",
+            SENSOR_EXCHANGE,
+            routing_key,
             BasicPublishOptions::default(),
             &payload,
             Default::default(),
@@ -264,6 +140,7 @@ pub fn process_sensor_data(mut raw: SensorArmData, filters: &mut Filters) -> (Se
     //     || detect_anomaly(filtered.object_data.object_velocity, 9.8, 11.8);       // non-moving object
     // let latency = now_micros() - start;
     // println!("Sensor data processed in {} µs", latency);
+    raw.trace.filtered = now_micros();
     (raw, anomaly)
 }
 
@@ -273,14 +150,10 @@ pub async fn consume_feedback(shutdown: Arc<Notify>, shared_feedback: Arc<Mutex<
         .await.expect("Connection error");
     let channel = conn.create_channel().await.expect("Channel creation error");
 
-    channel.queue_declare(
-        "feedback_to_sensor",
-        QueueDeclareOptions::default(),
-        FieldTable::default(),
-    ).await.expect("Queue declaration error");
+    declare_controller_topology(&channel).await.expect("Queue declaration error");
 
     let mut consumer = channel.basic_consume(
-        "feedback_to_sensor",
+        FEEDBACK_QUEUE,
         "feedback_consumer",
         BasicConsumeOptions::default(),
         FieldTable::default(),
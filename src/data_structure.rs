@@ -1,3 +1,11 @@
+//arm served when nothing else is configured
+pub const DEFAULT_ARM_ID: u32 = 1;
+//max reach of the actuator arm, shoulder to elbow + elbow to wrist used by its IK
+pub const ARM_REACH: f32 = 6.0;
+fn default_arm_id() -> u32 {
+    DEFAULT_ARM_ID
+}
+
 pub fn now_micros() -> u128 {
     use std::time::{SystemTime, UNIX_EPOCH};
     SystemTime::now()
@@ -8,6 +16,9 @@ pub fn now_micros() -> u128 {
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 //to simulate sensor arm data
 pub struct SensorArmData {
+    //arm the frame is for, also picks the routing key
+    #[serde(default = "default_arm_id")]
+    pub arm_id: u32,
     pub object_data: ObjectData,
 
     pub wrist: WristData,
@@ -19,6 +30,40 @@ pub struct SensorArmData {
     pub arm_length: i32,
 
     pub timestamp: u128,
+    #[serde(default)]
+    pub trace: FrameTrace,
+}
+
+//correlation id and per hop timestamps of one frame, carried from generation to feedback
+//timestamps are µs since epoch, 0 means the frame has not reached that hop
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct FrameTrace {
+    pub cycle: u64,    // controller cycle the frame was generated in
+    pub sequence: u64, // published frame count, cycles with anomalies are never published
+    #[serde(default)]
+    pub object_id: u64, // object the frame saw, frames of one falling object share it
+    pub generated: u128,
+    pub filtered: u128,
+    pub published: u128,
+    pub received: u128,
+    pub actuated: u128,
+    pub feedback_sent: u128,
+    pub feedback_received: u128,
+}
+
+impl FrameTrace {
+    /// Duration of each hop of a completed round trip in µs, in the order the frame travels.
+    pub fn hops(&self) -> [(&'static str, u128); 7] {
+        [
+            ("filter", self.filtered.saturating_sub(self.generated)),
+            ("publish", self.published.saturating_sub(self.filtered)),
+            ("transport", self.received.saturating_sub(self.published)),
+            ("control", self.actuated.saturating_sub(self.received)),
+            ("feedback_prepare", self.feedback_sent.saturating_sub(self.actuated)),
+            ("feedback_transport", self.feedback_received.saturating_sub(self.feedback_sent)),
+            ("round_trip", self.feedback_received.saturating_sub(self.generated)),
+        ]
+    }
 }
 //simulate object data
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -31,6 +76,14 @@ pub struct ObjectData {
     pub object_height: f32,
 }
 
+impl ObjectData {
+    /// µs until the object reaches the ground at its current speed, height in m and velocity in m/s.
+    /// None when it is not falling.
+    pub fn time_to_reach(&self) -> Option<u64> {
+        (self.object_velocity > 0.0).then(|| (self.object_height / self.object_velocity * 1_000_000.0) as u64)
+    }
+}
+
 //stored in sensor
 //overall data struct in arm
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -70,6 +123,7 @@ impl SensorArmData {
         let arm_strength = arm_velocity * 10.0;
 
         SensorArmData {
+            arm_id: DEFAULT_ARM_ID,
             object_data,
             wrist,
             joints,
@@ -78,6 +132,7 @@ impl SensorArmData {
             arm_strength,
             arm_length: 10,
             timestamp: 0,
+            trace: FrameTrace::default(),
         }
     }
 }
@@ -86,58 +141,183 @@ impl SensorArmData {
     pub fn update_object_data(&mut self, object_data: ObjectData) {
         self.object_data = object_data;
     }
+
+    //take the joint positions the actuator last reported
+    pub fn apply_feedback(&mut self, feedback: &FeedbackData) {
+        self.joints.shoulder_x = feedback.joints.shoulder_x;
+        self.joints.shoulder_y = feedback.joints.shoulder_y;
+        self.elbow.elbow_x = feedback.elbow.elbow_x;
+        self.elbow.elbow_y = feedback.elbow.elbow_y;
+        self.wrist.wrist_x = feedback.wrist.wrist_x;
+        self.wrist.wrist_y = feedback.wrist.wrist_y;
+    }
 }
 //convert sensor arm data to feedback data
 impl SensorArmData {
     pub fn to_feedback(&self, eta: u128) -> FeedbackData {
         FeedbackData {
+            arm_id: self.arm_id,
             wrist: self.wrist.clone(),
             joints: self.joints.clone(),
             elbow: self.elbow.clone(),
             arrived_at_ground: eta,
+            reach: ARM_REACH,
+            arm_velocity: self.arm_velocity,
+            // the arm is committed to this object until it lands
+            busy_until: eta,
+            grip: None,
+            plan: None,
+            safety: None,
             timestamp: now_micros(),
+            trace: self.trace.clone(),
         }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct ActuatorInstruction {
+    pub arm_id: u32,
     pub x: f32,
     pub y: f32,
     pub strength: f32,
+    //joint angle (rad) the position was solved for, checked by the safety envelope
+    pub angle: f32,
     pub time_to_reach: u64,
     pub timestamp: u128,
+    pub trace: FrameTrace,
 }
 
 impl ActuatorInstruction {
     pub fn new(x: f32, y: f32, strength: f32, time_to_reach: u64) -> Self {
         ActuatorInstruction {
+            arm_id: DEFAULT_ARM_ID,
             x,
             y,
             strength,
+            angle: y.atan2(x),
             time_to_reach,
             timestamp: now_micros(),
+            trace: FrameTrace::default(),
         }
     }
 }
 
+//grip the actuator reports in its feedback, worked out by grip::GripModel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum GripVerdict {
+    // the applied force arrests the object and stays within the limit
+    Holds,
+    // the applied force is too weak, the object slips through
+    Slips,
+    // arresting it would take more than the force limit
+    BeyondLimit,
+}
+
+/// What the arm was asked for, what the object needs and what was applied, in N.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct GripAssessment {
+    pub required: f32,
+    pub requested: f32,
+    pub applied: f32,
+    pub verdict: GripVerdict,
+}
+
+//collision check result the actuator reports in its feedback, see obstacles::MotionPlanner
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "plan", rename_all = "kebab-case")]
+pub enum PlanResult {
+    // the first solution is collision free
+    Clear,
+    // the other elbow solution was taken
+    Replanned,
+    // both collide, the arm did not move
+    Rejected { obstacle: usize },
+}
+
+//joint limit breaches the actuator reports in its feedback, see safety::SafetyEnvelope
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LimitAction {
+    // bring the instruction back inside the limits
+    Clamp,
+    // drop the move, the joints stay where they are
+    Reject,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Joint {
+    Shoulder,
+    Elbow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Limit {
+    Angle,
+    Velocity,
+    Acceleration,
+    Torque,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Violation {
+    pub joint: Joint,
+    pub limit: Limit,
+    // what the instruction asked for, and the limit it broke
+    pub value: f32,
+    pub allowed: f32,
+}
+
+/// Sent back in the feedback when a move broke a limit.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SafetyReport {
+    pub action: LimitAction,
+    pub violations: Vec<Violation>,
+    // the clamped move would have hit this obstacle, so it was rejected instead
+    #[serde(default)]
+    pub obstacle: Option<usize>,
+}
+
 //store feedback data from actuator to sensor
 //this is the data that the sensor will use to update its state
 //does not have object data, as it is not needed for the feedback
-//as long as the arm data has been returned, the object can be deemed caught
+//whether the object was caught is decided by the controller's catch evaluator, see catching.rs
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct FeedbackData {
+    #[serde(default = "default_arm_id")]
+    pub arm_id: u32,
     pub wrist: WristData,
     pub joints: ShoulderData,
     pub elbow: ElbowData,
     pub arrived_at_ground: u128,
+    //used by the controller to allocate objects between arms
+    #[serde(default)]
+    pub reach: f32,
+    #[serde(default)]
+    pub arm_velocity: f32,
+    #[serde(default)]
+    pub busy_until: u128,
+    //how the arm meant to arrest the object, None from arms without a grip model
+    #[serde(default)]
+    pub grip: Option<GripAssessment>,
+    //whether the move was collision free, replanned or rejected, the wrist stays put when rejected
+    #[serde(default)]
+    pub plan: Option<PlanResult>,
+    //limits the move broke and whether it was clamped or rejected, None when it was within all of them
+    #[serde(default)]
+    pub safety: Option<SafetyReport>,
 
     pub timestamp: u128,
+    #[serde(default)]
+    pub trace: FrameTrace,
 }
 //function to get feedback data from sensor arm data
 impl SensorArmData {
     pub fn from_feedback(feedback: FeedbackData) -> Self {
         SensorArmData {
+            arm_id: feedback.arm_id,
             object_data: ObjectData {
                 object_velocity: 0.0,
                 object_mass: 0.0,
@@ -153,6 +333,7 @@ impl SensorArmData {
             arm_strength: 0.0,
             timestamp: feedback.timestamp,
             arm_length: 10,
+            trace: feedback.trace,
         }
     }
 }
@@ -198,6 +379,12 @@ impl MovingAverage {
     }
 }
 
+impl Default for MovingAverage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Clone)]
 pub struct Filters {
     pub wrist_x_filter: MovingAverage,
@@ -250,9 +437,52 @@ impl Filters {
         self.object_height_filter.reset();
     }
 }
-//to track latency in the system of high/normal load
-#[derive(Debug)]
-pub struct LogEntry {
-    pub task: String,
-    pub latency: u128, // microseconds
+impl Default for Filters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//sent by an actuator to join or leave the controller's registry
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ArmRegistration {
+    pub arm_id: u32,
+    pub model: String,
+    pub reach: f32,
+    //e.g. shoulder, elbow, feedback
+    pub capabilities: Vec<String>,
+    pub timestamp: u128,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind")]
+pub enum RegistryMessage {
+    //first announcement, repeated periodically so the controller knows the arm is still there
+    Register(ArmRegistration),
+    Deregister { arm_id: u32, timestamp: u128 },
+}
+
+//periodic liveness message, sent both ways
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Heartbeat {
+    //None when sent by the controller
+    pub arm_id: Option<u32>,
+    pub sequence: u64,
+    pub timestamp: u128,
+    //controller only: arms it currently serves, an arm missing from it registers again
+    #[serde(default)]
+    pub arms: Vec<u32>,
+}
+
+//emergency stop, latched by the actuator until a release reaches it
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct EmergencyStop {
+    //None addresses every arm
+    pub arm_id: Option<u32>,
+    //false releases an earlier stop
+    pub engaged: bool,
+    //controller, cli or anomaly_policy
+    pub source: String,
+    pub reason: String,
+    pub timestamp: u128,
 }
@@ -0,0 +1,17 @@
+// value ranges the simulators draw from
+// a leaf module so the sensor simulator and the world can both use it without importing each other
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize)]
+pub struct Range {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Range {
+    pub const fn new(min: f32, max: f32) -> Self {
+        Range { min, max }
+    }
+
+    pub fn sample(&self, rng: &mut fastrand::Rng) -> f32 {
+        self.min + rng.f32() * (self.max - self.min)
+    }
+}
@@ -0,0 +1,91 @@
+// emergency stop path, separate from the sensor traffic so a stop never waits behind frames
+// the actuator latches a stop: queued instructions are dropped and the joints stay frozen until released
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use lapin::{options::BasicPublishOptions, BasicProperties, Channel};
+
+use crate::config::parse_env;
+use crate::data_structure::EmergencyStop;
+use crate::now_micros;
+use crate::routing::{estop_routing_key, ESTOP_EXCHANGE};
+
+impl EmergencyStop {
+    pub fn engage(arm_id: Option<u32>, source: &str, reason: &str) -> Self {
+        EmergencyStop {
+            arm_id,
+            engaged: true,
+            source: source.to_string(),
+            reason: reason.to_string(),
+            timestamp: now_micros(),
+        }
+    }
+
+    pub fn release(arm_id: Option<u32>, source: &str) -> Self {
+        EmergencyStop {
+            engaged: false,
+            ..Self::engage(arm_id, source, "released")
+        }
+    }
+
+    pub fn applies_to(&self, arm_id: u32) -> bool {
+        self.arm_id.is_none_or(|id| id == arm_id)
+    }
+}
+
+/// Stop state of one arm, shared by the stop consumer, the frame consumer and the joint tasks.
+#[derive(Debug, Default)]
+pub struct EStopLatch {
+    engaged: AtomicBool,
+    stops: AtomicU64,
+    // instructions dropped by the joint tasks while engaged
+    cancelled: AtomicU64,
+}
+
+impl EStopLatch {
+    pub fn is_engaged(&self) -> bool {
+        self.engaged.load(Ordering::SeqCst)
+    }
+
+    /// Applies a stop or release, returns true when the state changed.
+    pub fn apply(&self, stop: &EmergencyStop) -> bool {
+        let changed = self.engaged.swap(stop.engaged, Ordering::SeqCst) != stop.engaged;
+        if changed && stop.engaged {
+            self.stops.fetch_add(1, Ordering::Relaxed);
+        }
+        changed
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stops(&self) -> u64 {
+        self.stops.load(Ordering::Relaxed)
+    }
+
+    pub fn cancelled(&self) -> u64 {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// Publishes a stop or release on the stop exchange.
+pub async fn raise(channel: &Channel, stop: &EmergencyStop) -> Result<(), Box<dyn std::error::Error>> {
+    let payload = serde_json::to_vec(stop)?;
+    channel
+        .basic_publish(
+            ESTOP_EXCHANGE,
+            &estop_routing_key(stop.arm_id),
+            BasicPublishOptions::default(),
+            &payload,
+            BasicProperties::default(),
+        )
+        .await?
+        .await?;
+    Ok(())
+}
+
+/// Anomaly policy: consecutive anomalous frames of an arm before it is stopped,
+/// from `ESTOP_AFTER_ANOMALIES`, 0 (the default) never stops.
+pub fn anomaly_threshold_from_env() -> u32 {
+    parse_env("ESTOP_AFTER_ANOMALIES").unwrap_or(0)
+}
@@ -0,0 +1,79 @@
+// stale data rejection
+// a frame or feedback older than the max age describes where the object used to be, so it is dropped,
+// and with latest-value semantics a backlog is skipped in favour of its newest entry
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::config::{bool_from_env, duration_from_env};
+use crate::now_micros;
+
+//10 loop periods, overridden by MAX_FRAME_AGE_MS
+pub const DEFAULT_MAX_FRAME_AGE: Duration = Duration::from_millis(50);
+//overridden by MAX_FEEDBACK_AGE_MS
+pub const DEFAULT_MAX_FEEDBACK_AGE: Duration = Duration::from_millis(50);
+
+#[derive(Debug)]
+pub struct Freshness {
+    pub max_age: Duration,
+    // skip to the newest of the messages already delivered
+    pub latest_only: bool,
+    stale: AtomicU64,
+    superseded: AtomicU64,
+}
+
+impl Freshness {
+    pub fn new(max_age: Duration, latest_only: bool) -> Self {
+        Freshness {
+            max_age,
+            latest_only,
+            stale: AtomicU64::new(0),
+            superseded: AtomicU64::new(0),
+        }
+    }
+
+    /// Max age from `max_age_var` (ms), latest-value semantics unless `LATEST_ONLY=0`.
+    pub fn from_env(max_age_var: &str, default: Duration) -> Self {
+        let latest_only = bool_from_env("LATEST_ONLY", true);
+        Self::new(duration_from_env(max_age_var, default), latest_only)
+    }
+
+    pub fn frames() -> Self {
+        Self::from_env("MAX_FRAME_AGE_MS", DEFAULT_MAX_FRAME_AGE)
+    }
+
+    pub fn feedback() -> Self {
+        Self::from_env("MAX_FEEDBACK_AGE_MS", DEFAULT_MAX_FEEDBACK_AGE)
+    }
+
+    /// True, and counted, when a message stamped at `timestamp` is older than the max age.
+    pub fn is_stale(&self, timestamp: u128) -> bool {
+        let stale = now_micros().saturating_sub(timestamp) > self.max_age.as_micros();
+        if stale {
+            self.stale.fetch_add(1, Ordering::Relaxed);
+        }
+        stale
+    }
+
+    /// Counts a message skipped because a newer one was already there.
+    pub fn supersede(&self) {
+        self.superseded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stale(&self) -> u64 {
+        self.stale.load(Ordering::Relaxed)
+    }
+
+    pub fn superseded(&self) -> u64 {
+        self.superseded.load(Ordering::Relaxed)
+    }
+
+    pub fn print_summary(&self, what: &str) {
+        println!(
+            "> Stale {} dropped (older than {:?}): {}, superseded by newer: {}",
+            what,
+            self.max_age,
+            self.stale(),
+            self.superseded()
+        );
+    }
+}
@@ -0,0 +1,190 @@
+// grip and impact model
+// to arrest a falling object the arm has to take its momentum over a short stopping distance and
+// then hold its weight, the force that takes is compared with the strength the arm applies, which
+// the actuator raises to what the object needs but never past the force limit
+use std::collections::BTreeMap;
+
+use crate::config::{bool_from_env, parse_env};
+use crate::data_structure::{GripAssessment, GripVerdict, ObjectData};
+use crate::world::GRAVITY;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GripModel {
+    // how far the hand gives way while arresting the object, m
+    pub stopping_distance: f32,
+    // the most force the hand may apply, N
+    pub force_limit: f32,
+    // applied force over the required one when adjusting
+    pub margin: f32,
+    // false applies the controller's strength as is, only clamped to the limit
+    pub adjust: bool,
+}
+
+impl Default for GripModel {
+    fn default() -> Self {
+        GripModel {
+            // 2cm, the hand is small next to the 6cm arm
+            stopping_distance: 0.02,
+            // a 5g object at 12 m/s from 1.5m needs about 23N
+            force_limit: 30.0,
+            margin: 1.2,
+            adjust: true,
+        }
+    }
+}
+
+/// Speed (m/s) of an object `height` m up, falling at `velocity` m/s, when it reaches the catch plane.
+pub fn impact_velocity(height: f32, velocity: f32) -> f32 {
+    (velocity * velocity + 2.0 * GRAVITY * height.max(0.0)).sqrt()
+}
+
+impl GripModel {
+    /// Defaults adjusted by `GRIP_STOPPING_DISTANCE_CM`, `GRIP_FORCE_LIMIT_N`, `GRIP_MARGIN` and `GRIP_ADJUST=0`.
+    pub fn from_env() -> Self {
+        let default = GripModel::default();
+        GripModel {
+            stopping_distance: parse_env::<f32>("GRIP_STOPPING_DISTANCE_CM")
+                .map_or(default.stopping_distance, |cm| cm / 100.0),
+            force_limit: parse_env("GRIP_FORCE_LIMIT_N").unwrap_or(default.force_limit),
+            margin: parse_env("GRIP_MARGIN").unwrap_or(default.margin),
+            adjust: bool_from_env("GRIP_ADJUST", default.adjust),
+        }
+    }
+
+    /// Force (N) to stop the object over the stopping distance and hold it, mass in g and height in m,
+    /// which every frame carries now, with or without a world.
+    pub fn required_force(&self, object: &ObjectData) -> f32 {
+        let mass = object.object_mass / 1000.0;
+        let velocity = impact_velocity(object.object_height, object.object_velocity);
+        mass * velocity * velocity / (2.0 * self.stopping_distance) + mass * GRAVITY
+    }
+
+    /// Decides the force to apply for `requested` N from the controller.
+    pub fn assess(&self, object: &ObjectData, requested: f32) -> GripAssessment {
+        let required = self.required_force(object);
+        let wanted = if self.adjust { required * self.margin } else { requested };
+        let applied = wanted.clamp(0.0, self.force_limit);
+        let verdict = if required > self.force_limit {
+            GripVerdict::BeyondLimit
+        } else if applied < required {
+            GripVerdict::Slips
+        } else {
+            GripVerdict::Holds
+        };
+        GripAssessment {
+            required,
+            requested,
+            applied,
+            verdict,
+        }
+    }
+}
+
+impl GripAssessment {
+    /// The same grip with less force applied, when the joints cannot take what was decided.
+    pub fn with_applied(self, applied: f32) -> Self {
+        let verdict = match self.verdict {
+            GripVerdict::BeyondLimit => GripVerdict::BeyondLimit,
+            _ if applied < self.required => GripVerdict::Slips,
+            _ => GripVerdict::Holds,
+        };
+        GripAssessment { applied, verdict, ..self }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct GripStats {
+    verdicts: BTreeMap<GripVerdict, u64>,
+    // the applied force differed from the controller's strength
+    pub adjusted: u64,
+    pub clamped: u64,
+    pub peak_required: f32,
+}
+
+impl GripStats {
+    pub fn record(&mut self, model: &GripModel, grip: &GripAssessment) {
+        *self.verdicts.entry(grip.verdict).or_default() += 1;
+        if (grip.applied - grip.requested).abs() > f32::EPSILON {
+            self.adjusted += 1;
+        }
+        if grip.applied >= model.force_limit {
+            self.clamped += 1;
+        }
+        self.peak_required = self.peak_required.max(grip.required);
+    }
+
+    pub fn print_summary(&self, model: &GripModel) {
+        let count = |verdict| self.verdicts.get(&verdict).copied().unwrap_or(0);
+        println!(
+            ">   grip (limit {}N): holds={} slips={} beyond_limit={} adjusted={} clamped={} peak_required={:.2}N",
+            model.force_limit,
+            count(GripVerdict::Holds),
+            count(GripVerdict::Slips),
+            count(GripVerdict::BeyondLimit),
+            self.adjusted,
+            self.clamped,
+            self.peak_required
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `mass` g falling at 10 m/s, already at the catch plane
+    fn object(mass: f32) -> ObjectData {
+        ObjectData {
+            object_velocity: 10.0,
+            object_mass: mass,
+            object_size: 4.5,
+            object_x: 1.0,
+            object_y: 2.0,
+            object_height: 0.0,
+        }
+    }
+
+    #[test]
+    fn required_force_stops_the_object_and_holds_it() {
+        let model = GripModel::default();
+        // m v² / 2d + m g
+        let expected = 0.005 * 100.0 / 0.04 + 0.005 * GRAVITY;
+        assert!((model.required_force(&object(5.0)) - expected).abs() < 1e-4);
+        assert!((impact_velocity(GRAVITY / 2.0, 0.0) - GRAVITY).abs() < 1e-4);
+    }
+
+    #[test]
+    fn grip_slips_just_below_the_required_force() {
+        let model = GripModel {
+            adjust: false,
+            ..GripModel::default()
+        };
+        let required = model.required_force(&object(5.0));
+        assert_eq!(model.assess(&object(5.0), required).verdict, GripVerdict::Holds);
+        let below = model.assess(&object(5.0), required - 0.01);
+        assert_eq!(below.verdict, GripVerdict::Slips);
+        assert_eq!(below.applied, required - 0.01);
+        // the limit still applies to the controller's strength
+        assert_eq!(model.assess(&object(5.0), 100.0).applied, model.force_limit);
+    }
+
+    #[test]
+    fn adjusted_grip_adds_the_margin_up_to_the_limit() {
+        let model = GripModel::default();
+        let grip = model.assess(&object(5.0), 1.0);
+        assert_eq!(grip.verdict, GripVerdict::Holds);
+        assert!((grip.applied - grip.required * model.margin).abs() < 1e-4);
+        // needs about 25N, the margin would take it past the 30N limit
+        let heavy = model.assess(&object(10.0), 1.0);
+        assert_eq!((heavy.verdict, heavy.applied), (GripVerdict::Holds, model.force_limit));
+        let too_heavy = model.assess(&object(20.0), 1.0);
+        assert_eq!(too_heavy.verdict, GripVerdict::BeyondLimit);
+    }
+
+    #[test]
+    fn lowering_the_applied_force_reassesses_the_grip() {
+        let grip = GripModel::default().assess(&object(5.0), 1.0);
+        assert_eq!(grip.with_applied(grip.required).verdict, GripVerdict::Holds);
+        assert_eq!(grip.with_applied(grip.required - 0.01).verdict, GripVerdict::Slips);
+    }
+}
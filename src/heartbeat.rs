@@ -0,0 +1,158 @@
+// liveness between the controller and the arms
+// both sides publish a heartbeat every interval and run a watchdog over the other side's heartbeats,
+// a tripped watchdog puts that side in its safe state until heartbeats come back
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+use lapin::{options::BasicPublishOptions, BasicProperties, Channel};
+
+use crate::config::{duration_from_env, parse_env};
+use crate::data_structure::Heartbeat;
+use crate::now_micros;
+use crate::routing::{arm_heartbeat_key, CONTROLLER_HEARTBEAT_KEY, HEARTBEAT_EXCHANGE};
+
+//overridden by HEARTBEAT_INTERVAL_MS
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_millis(250);
+//actuator side, overridden by CONTROLLER_TIMEOUT_MS
+pub const DEFAULT_CONTROLLER_TIMEOUT: Duration = Duration::from_millis(1000);
+//how often the watchdogs look at the last heartbeat
+pub const WATCHDOG_PERIOD: Duration = Duration::from_millis(50);
+
+pub fn heartbeat_interval() -> Duration {
+    duration_from_env("HEARTBEAT_INTERVAL_MS", DEFAULT_HEARTBEAT_INTERVAL)
+}
+
+/// What the actuator does with the arm once the controller goes silent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SafeState {
+    // keep the joints where they are and ignore new frames
+    Hold,
+    // move the joints back to the rest pose and ignore new frames
+    Park,
+}
+
+impl FromStr for SafeState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hold" => Ok(SafeState::Hold),
+            "park" => Ok(SafeState::Park),
+            other => Err(format!("unknown safe state: {}", other)),
+        }
+    }
+}
+
+impl SafeState {
+    /// From the `SAFE_STATE` env var (`hold` or `park`), park by default.
+    pub fn from_env() -> Self {
+        parse_env("SAFE_STATE").unwrap_or(SafeState::Park)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchdogEvent {
+    Tripped,
+    Recovered,
+}
+
+/// Trips when nothing was fed for longer than the timeout, recovers on the next feed.
+/// Starts fed, so the peer gets one timeout to show up.
+pub struct Watchdog {
+    pub timeout: Duration,
+    last_fed: AtomicU64,
+    tripped: AtomicBool,
+    trips: AtomicU64,
+}
+
+impl Watchdog {
+    pub fn new(timeout: Duration) -> Self {
+        Watchdog {
+            timeout,
+            last_fed: AtomicU64::new(now_micros() as u64),
+            tripped: AtomicBool::new(false),
+            trips: AtomicU64::new(0),
+        }
+    }
+
+    pub fn feed(&self) {
+        self.last_fed.store(now_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn is_tripped(&self) -> bool {
+        self.tripped.load(Ordering::Relaxed)
+    }
+
+    pub fn trips(&self) -> u64 {
+        self.trips.load(Ordering::Relaxed)
+    }
+
+    /// Compares the last feed against the timeout, returns the state change if there was one.
+    pub fn check(&self) -> Option<WatchdogEvent> {
+        let silent = (now_micros() as u64).saturating_sub(self.last_fed.load(Ordering::Relaxed));
+        let expired = silent as u128 > self.timeout.as_micros();
+        if expired && !self.tripped.swap(true, Ordering::Relaxed) {
+            self.trips.fetch_add(1, Ordering::Relaxed);
+            return Some(WatchdogEvent::Tripped);
+        }
+        if !expired && self.tripped.swap(false, Ordering::Relaxed) {
+            return Some(WatchdogEvent::Recovered);
+        }
+        None
+    }
+}
+
+/// Publishes a heartbeat, on `heartbeat.arm.<id>` from an arm and `heartbeat.controller` otherwise.
+pub async fn send_heartbeat(channel: &Channel, heartbeat: &Heartbeat) -> Result<(), Box<dyn std::error::Error>> {
+    let payload = serde_json::to_vec(heartbeat)?;
+    let routing_key = match heartbeat.arm_id {
+        Some(arm_id) => arm_heartbeat_key(arm_id),
+        None => CONTROLLER_HEARTBEAT_KEY.to_string(),
+    };
+    channel
+        .basic_publish(
+            HEARTBEAT_EXCHANGE,
+            &routing_key,
+            BasicPublishOptions::default(),
+            &payload,
+            BasicProperties::default(),
+        )
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // as if the last feed was `ago` before now
+    fn starve(watchdog: &Watchdog, ago: Duration) {
+        watchdog
+            .last_fed
+            .store(now_micros() as u64 - ago.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn watchdog_trips_after_the_timeout_and_clears_on_a_beat() {
+        let watchdog = Watchdog::new(Duration::from_secs(1));
+        assert_eq!(watchdog.check(), None);
+        starve(&watchdog, Duration::from_millis(900));
+        assert_eq!(watchdog.check(), None);
+
+        starve(&watchdog, Duration::from_millis(1100));
+        assert_eq!(watchdog.check(), Some(WatchdogEvent::Tripped));
+        assert!(watchdog.is_tripped());
+        // reported once per outage
+        assert_eq!(watchdog.check(), None);
+
+        watchdog.feed();
+        assert_eq!(watchdog.check(), Some(WatchdogEvent::Recovered));
+        assert!(!watchdog.is_tripped());
+        assert_eq!(watchdog.check(), None);
+
+        starve(&watchdog, Duration::from_secs(2));
+        assert_eq!(watchdog.check(), Some(WatchdogEvent::Tripped));
+        assert_eq!(watchdog.trips(), 2);
+    }
+}
@@ -0,0 +1,297 @@
+// hdr style log-linear latency histograms, one per stage
+// recording is a couple of atomic adds on a fixed array, no allocation and no lock,
+// so the hot path can record every sample and the files are written from the side
+use std::error::Error;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::open_append_csv;
+use crate::latency_log::RunInfo;
+use crate::now_micros;
+
+// each power of two range is split in 2^SUB_BUCKET_BITS linear buckets, ~3% relative error
+const SUB_BUCKET_BITS: u32 = 5;
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+// values below SUB_BUCKETS get one bucket each, then one group per remaining bit of a u64
+pub const BUCKETS: usize = SUB_BUCKETS + (64 - SUB_BUCKET_BITS as usize) * SUB_BUCKETS;
+
+fn bucket_index(value: u64) -> usize {
+    if value < SUB_BUCKETS as u64 {
+        return value as usize;
+    }
+    let msb = 63 - value.leading_zeros();
+    let group = (msb - SUB_BUCKET_BITS) as usize;
+    let sub = ((value >> group) as usize) & (SUB_BUCKETS - 1);
+    SUB_BUCKETS + group * SUB_BUCKETS + sub
+}
+
+/// Inclusive value range covered by a bucket.
+pub fn bucket_bounds(index: usize) -> (u64, u64) {
+    if index < SUB_BUCKETS {
+        return (index as u64, index as u64);
+    }
+    let group = (index - SUB_BUCKETS) / SUB_BUCKETS;
+    let sub = (index - SUB_BUCKETS) % SUB_BUCKETS;
+    let low = ((SUB_BUCKETS + sub) as u64) << group;
+    (low, low + ((1u64 << group) - 1))
+}
+
+pub struct LatencyHistogram {
+    counts: [AtomicU64; BUCKETS],
+    total: AtomicU64,
+    sum: AtomicU64,
+    min: AtomicU64,
+    max: AtomicU64,
+}
+
+impl LatencyHistogram {
+    pub fn new() -> Self {
+        LatencyHistogram {
+            counts: [const { AtomicU64::new(0) }; BUCKETS],
+            total: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+            min: AtomicU64::new(u64::MAX),
+            max: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record(&self, value_us: u64) {
+        self.counts[bucket_index(value_us)].fetch_add(1, Ordering::Relaxed);
+        self.total.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value_us, Ordering::Relaxed);
+        self.min.fetch_min(value_us, Ordering::Relaxed);
+        self.max.fetch_max(value_us, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.total.load(Ordering::Relaxed)
+    }
+
+    pub fn min(&self) -> u64 {
+        if self.count() == 0 {
+            0
+        } else {
+            self.min.load(Ordering::Relaxed)
+        }
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max.load(Ordering::Relaxed)
+    }
+
+    pub fn mean(&self) -> f64 {
+        let count = self.count();
+        if count == 0 {
+            return 0.0;
+        }
+        self.sum.load(Ordering::Relaxed) as f64 / count as f64
+    }
+
+    /// Upper bound of the bucket holding the p-th percentile, capped at the recorded max.
+    pub fn value_at_percentile(&self, p: f64) -> u64 {
+        let count = self.count();
+        if count == 0 {
+            return 0;
+        }
+        let target = ((p / 100.0) * count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, c) in self.counts.iter().enumerate() {
+            seen += c.load(Ordering::Relaxed);
+            if seen >= target {
+                return bucket_bounds(i).1.min(self.max());
+            }
+        }
+        self.max()
+    }
+
+    /// Non empty buckets as (low, high, count), allocates so keep it off the hot path.
+    pub fn buckets(&self) -> Vec<(u64, u64, u64)> {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter_map(|(i, c)| {
+                let c = c.load(Ordering::Relaxed);
+                (c > 0).then(|| {
+                    let (low, high) = bucket_bounds(i);
+                    (low, high, c)
+                })
+            })
+            .collect()
+    }
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// One histogram per stage, the stage set is fixed up front so lookups never allocate.
+pub struct LatencyHistograms {
+    stages: Vec<(&'static str, LatencyHistogram)>,
+}
+
+impl LatencyHistograms {
+    pub fn new(stages: &[&'static str]) -> Self {
+        LatencyHistograms {
+            stages: stages.iter().map(|s| (*s, LatencyHistogram::new())).collect(),
+        }
+    }
+
+    pub fn get(&self, stage: &str) -> Option<&LatencyHistogram> {
+        self.stages.iter().find(|(s, _)| *s == stage).map(|(_, h)| h)
+    }
+
+    /// Records into the stage's histogram, unknown stages are ignored.
+    pub fn record(&self, stage: &str, latency_us: u128) {
+        if let Some(h) = self.get(stage) {
+            h.record(latency_us.min(u64::MAX as u128) as u64);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &LatencyHistogram)> {
+        self.stages.iter().map(|(s, h)| (*s, h))
+    }
+
+    /// Appends one summary row per stage, used for the periodic snapshots.
+    pub fn write_snapshot<P: AsRef<Path>>(&self, run: &RunInfo, path: P) -> Result<(), Box<dyn Error>> {
+        let mut writer = open_append_csv(
+            path,
+            &[
+                "timestamp_us", "run_id", "process", "stage", "load",
+                "count", "mean_us", "min_us", "p50_us", "p90_us", "p99_us", "p999_us", "max_us",
+            ],
+        )?;
+        let now = now_micros().to_string();
+        for (stage, h) in self.iter() {
+            writer.write_record([
+                now.clone(),
+                run.run_id.clone(),
+                run.process.clone(),
+                stage.to_string(),
+                run.load.clone(),
+                h.count().to_string(),
+                format!("{:.2}", h.mean()),
+                h.min().to_string(),
+                h.value_at_percentile(50.0).to_string(),
+                h.value_at_percentile(90.0).to_string(),
+                h.value_at_percentile(99.0).to_string(),
+                h.value_at_percentile(99.9).to_string(),
+                h.max().to_string(),
+            ])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes every non empty bucket of every stage, the full distribution at shutdown.
+    pub fn write_distribution<P: AsRef<Path>>(&self, run: &RunInfo, path: P) -> Result<(), Box<dyn Error>> {
+        let mut writer = csv::Writer::from_path(path)?;
+        writer.write_record(["run_id", "process", "stage", "load", "low_us", "high_us", "count"])?;
+        for (stage, h) in self.iter() {
+            for (low, high, count) in h.buckets() {
+                writer.write_record([
+                    run.run_id.clone(),
+                    run.process.clone(),
+                    stage.to_string(),
+                    run.load.clone(),
+                    low.to_string(),
+                    high.to_string(),
+                    count.to_string(),
+                ])?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn print_summary(&self) {
+        for (stage, h) in self.iter() {
+            println!(
+                "> {:<22} n={:<7} mean={:.1}µs p50={}µs p99={}µs max={}µs",
+                stage,
+                h.count(),
+                h.mean(),
+                h.value_at_percentile(50.0),
+                h.value_at_percentile(99.0),
+                h.max()
+            );
+        }
+    }
+}
+
+/// Periodically appends a snapshot of every stage to `histogram_<process>_snapshots.csv`.
+pub async fn start_histogram_snapshots(histograms: Arc<LatencyHistograms>, run: RunInfo, every: Duration) {
+    let path = snapshot_path(&run);
+    let mut interval = tokio::time::interval(every);
+    interval.tick().await; // first tick fires immediately, nothing recorded yet
+    loop {
+        interval.tick().await;
+        if let Err(e) = histograms.write_snapshot(&run, &path) {
+            eprintln!("Failed to write histogram snapshot: {}", e);
+        }
+    }
+}
+
+pub fn snapshot_path(run: &RunInfo) -> String {
+    format!("histogram_{}_snapshots.csv", run.process)
+}
+
+/// Final distribution of every stage, `histogram_<process>.csv`.
+pub fn distribution_path(run: &RunInfo) -> String {
+    format!("histogram_{}.csv", run.process)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_split_at_each_power_of_two() {
+        for bit in SUB_BUCKET_BITS..64 {
+            let edge = 1u64 << bit;
+            assert_eq!(bucket_index(edge), bucket_index(edge - 1) + 1, "2^{}", bit);
+            assert_eq!(bucket_bounds(bucket_index(edge)).0, edge);
+            assert_eq!(bucket_bounds(bucket_index(edge - 1)).1, edge - 1);
+        }
+        // exact below the first power of two that is split
+        assert_eq!(bucket_bounds(bucket_index(17)), (17, 17));
+        assert_eq!(bucket_index(u64::MAX), BUCKETS - 1);
+    }
+
+    #[test]
+    fn value_falls_inside_its_bucket() {
+        let values = (0..10_000).chain((0..64).map(|bit| 1u64 << bit)).chain([123_456_789, u64::MAX]);
+        for value in values {
+            let (low, high) = bucket_bounds(bucket_index(value));
+            assert!(low <= value && value <= high, "{} outside {}..={}", value, low, high);
+            // ~3% relative error
+            assert!((high - low) as f64 <= value as f64 / SUB_BUCKETS as f64, "{}", value);
+        }
+    }
+
+    #[test]
+    fn percentiles_of_a_known_sample() {
+        let histogram = LatencyHistogram::new();
+        for value in 1..=100 {
+            histogram.record(value);
+        }
+        assert_eq!(histogram.count(), 100);
+        assert_eq!(histogram.value_at_percentile(50.0), 50);
+        assert_eq!(histogram.value_at_percentile(99.0), 99);
+        // 100 shares a bucket with 101, capped at the max
+        assert_eq!(histogram.value_at_percentile(100.0), 100);
+        assert_eq!((histogram.min(), histogram.max()), (1, 100));
+        assert_eq!(histogram.mean(), 50.5);
+    }
+
+    #[test]
+    fn empty_histogram_reports_zeros() {
+        let histogram = LatencyHistogram::new();
+        assert_eq!(histogram.value_at_percentile(99.0), 0);
+        assert_eq!((histogram.min(), histogram.max(), histogram.mean()), (0, 0, 0.0));
+        assert!(histogram.buckets().is_empty());
+    }
+}
@@ -0,0 +1,287 @@
+// one latency log schema shared by the controller and the actuator
+// every sample is a LatencyRecord, written as a csv row by LatencyWriter
+use std::env;
+use std::error::Error;
+use std::fs::File;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::config::{bool_from_env, open_append_csv};
+use crate::data_structure::FrameTrace;
+use crate::now_micros;
+
+//stage names each binary logs under
+pub const CONTROLLER_STAGES: [&str; 5] = [
+    "generate_sensor_data",
+    "process_sensor_data",
+    "publish_data",
+    "consume_feedback",
+    "round_trip",
+];
+pub const ACTUATOR_STAGES: [&str; 4] = [
+    "data_reception",
+    "cycle_time",
+    "shoulder_actuation",
+    "elbow_actuation",
+];
+
+//csv columns of a LatencyRecord, in field order
+const LATENCY_RECORD_HEADER: [&str; 9] = [
+    "run_id", "process", "stage", "cycle", "sequence", "start_us", "end_us", "latency_us", "load",
+];
+
+//one latency sample, all timestamps are µs since the unix epoch
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct LatencyRecord {
+    pub run_id: String,
+    pub process: String, // "controller" or "actuator"
+    pub stage: String,   // e.g. generate_sensor_data, cycle_time
+    pub cycle: u64,
+    pub sequence: u64, // per process record counter, keeps ordering when timestamps tie
+    pub start_us: u128,
+    pub end_us: u128,
+    pub latency_us: u128,
+    pub load: String, // load profile the run was started under, e.g. normal/high
+}
+
+/// Describes the run a process is logging for and hands out record sequence numbers.
+/// Clones share the same sequence counter.
+#[derive(Debug, Clone)]
+pub struct RunInfo {
+    pub run_id: String,
+    pub process: String,
+    pub load: String,
+    // write every sample to the csv, otherwise only the histograms see them
+    pub per_sample: bool,
+    sequence: Arc<AtomicU64>,
+}
+
+impl RunInfo {
+    pub fn new(run_id: &str, process: &str, load: &str) -> Self {
+        RunInfo {
+            run_id: run_id.to_string(),
+            process: process.to_string(),
+            load: load.to_string(),
+            per_sample: false,
+            sequence: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Reads `RUN_ID`, `LOAD_PROFILE` and `LATENCY_SAMPLES` from the environment.
+    /// Start both binaries with the same `RUN_ID` so their logs can be joined,
+    /// otherwise the start time is used as the id. `LATENCY_SAMPLES=1` turns on the per sample csv,
+    /// by default only the histograms see the samples so the hot path does no file io.
+    pub fn from_env(process: &str) -> Self {
+        let run_id = env::var("RUN_ID").unwrap_or_else(|_| now_micros().to_string());
+        let load = env::var("LOAD_PROFILE").unwrap_or_else(|_| "normal".to_string());
+        let mut run = Self::new(&run_id, process, &load);
+        run.per_sample = bool_from_env("LATENCY_SAMPLES", false);
+        run
+    }
+
+    /// Default log file of this process, `latency_<process>.csv`.
+    pub fn log_path(&self) -> String {
+        format!("latency_{}.csv", self.process)
+    }
+
+    pub fn record(&self, stage: &str, cycle: u64, start_us: u128, end_us: u128) -> LatencyRecord {
+        LatencyRecord {
+            run_id: self.run_id.clone(),
+            process: self.process.clone(),
+            stage: stage.to_string(),
+            cycle,
+            sequence: self.sequence.fetch_add(1, Ordering::Relaxed),
+            start_us,
+            end_us,
+            latency_us: end_us.saturating_sub(start_us),
+            load: self.load.clone(),
+        }
+    }
+}
+
+/// Appends latency records to a csv file, the header is only written when the file is empty.
+pub struct LatencyWriter {
+    writer: csv::Writer<File>,
+}
+
+impl LatencyWriter {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let writer = open_append_csv(path, &LATENCY_RECORD_HEADER)?;
+        Ok(LatencyWriter { writer })
+    }
+
+    pub fn write(&mut self, record: &LatencyRecord) -> Result<(), Box<dyn Error>> {
+        self.writer.serialize(record)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes a batch of records with a single flush.
+    pub fn write_all(&mut self, records: &[LatencyRecord]) -> Result<(), Box<dyn Error>> {
+        for record in records {
+            self.writer.serialize(record)?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Appends completed frame traces, one row per round trip with every hop timestamp and duration.
+pub struct TraceWriter {
+    run_id: String,
+    writer: csv::Writer<File>,
+}
+
+impl TraceWriter {
+    pub fn open<P: AsRef<Path>>(path: P, run_id: &str) -> Result<Self, Box<dyn Error>> {
+        let mut header = vec![
+            "run_id", "arm_id", "cycle", "sequence", "generated", "filtered", "published", "received",
+            "actuated", "feedback_sent", "feedback_received",
+        ];
+        header.extend(FrameTrace::default().hops().iter().map(|(name, _)| *name));
+        let writer = open_append_csv(path, &header)?;
+        Ok(TraceWriter {
+            run_id: run_id.to_string(),
+            writer,
+        })
+    }
+
+    pub fn write(&mut self, arm_id: u32, trace: &FrameTrace) -> Result<(), Box<dyn Error>> {
+        let mut row = vec![
+            self.run_id.clone(),
+            arm_id.to_string(),
+            trace.cycle.to_string(),
+            trace.sequence.to_string(),
+            trace.generated.to_string(),
+            trace.filtered.to_string(),
+            trace.published.to_string(),
+            trace.received.to_string(),
+            trace.actuated.to_string(),
+            trace.feedback_sent.to_string(),
+            trace.feedback_received.to_string(),
+        ];
+        row.extend(trace.hops().iter().map(|(_, us)| us.to_string()));
+        self.writer.write_record(&row)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Reads a latency log back into records.
+/// Besides the current schema this accepts the older hand made formats:
+/// `task,latency[,load]` from the controller and `timestamp,latency_type,latency_μs` from the actuator.
+pub fn read_latency_csv<P: AsRef<Path>>(path: P) -> Result<Vec<LatencyRecord>, Box<dyn Error>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_path(path)?;
+    let headers = reader.headers()?.clone();
+
+    if headers.iter().any(|h| h == "stage") {
+        let mut records = Vec::new();
+        for record in reader.deserialize() {
+            records.push(record?);
+        }
+        return Ok(records);
+    }
+
+    // legacy layouts only have a stage name and a latency
+    let actuator_layout = headers.get(1) == Some("latency_type");
+    let mut records = Vec::new();
+    for (sequence, row) in reader.records().enumerate() {
+        let row = row?;
+        let (stage, latency, end_us, load) = if actuator_layout {
+            (row.get(1), row.get(2), row.get(0), None)
+        } else {
+            (row.get(0), row.get(1), None, row.get(2))
+        };
+        // skip junk rows instead of failing the whole log
+        let (Some(stage), Some(Ok(latency_us))) = (stage, latency.map(str::parse::<u128>)) else {
+            continue;
+        };
+        let end_us: u128 = end_us.and_then(|t| t.parse().ok()).unwrap_or(0);
+        records.push(LatencyRecord {
+            run_id: String::new(),
+            process: if actuator_layout { "actuator" } else { "controller" }.to_string(),
+            stage: stage.to_string(),
+            cycle: 0,
+            sequence: sequence as u64,
+            start_us: end_us.saturating_sub(latency_us),
+            end_us,
+            latency_us,
+            load: load.unwrap_or("").to_string(),
+        });
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // a fresh file per test under the temp dir, the tests run in parallel
+    fn log_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("latency_log_test_{}_{}.csv", name, std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn stages(records: &[LatencyRecord]) -> Vec<(&str, u128)> {
+        records.iter().map(|r| (r.stage.as_str(), r.latency_us)).collect()
+    }
+
+    #[test]
+    fn reads_back_what_the_writer_wrote() {
+        let path = log_file("current", "");
+        let run = RunInfo::new("run", "controller", "high");
+        let written = [run.record("publish_data", 7, 100, 350), run.record("round_trip", 7, 0, 900)];
+        LatencyWriter::open(&path).unwrap().write_all(&written).unwrap();
+        let records = read_latency_csv(&path).unwrap();
+        assert_eq!(stages(&records), [("publish_data", 250), ("round_trip", 900)]);
+        assert_eq!((records[0].cycle, records[1].sequence), (7, 1));
+        assert_eq!((records[0].run_id.as_str(), records[0].load.as_str()), ("run", "high"));
+    }
+
+    #[test]
+    fn reads_the_old_controller_layout() {
+        let path = log_file("controller", "task,latency\ngenerate_sensor_data,12\npublish_data,80\n");
+        let records = read_latency_csv(&path).unwrap();
+        assert_eq!(stages(&records), [("generate_sensor_data", 12), ("publish_data", 80)]);
+        assert_eq!(records[0].process, "controller");
+        assert_eq!(records[1].load, "");
+    }
+
+    #[test]
+    fn reads_the_old_controller_layout_with_load() {
+        let path = log_file("controller_load", "task,latency,load\npublish_data,80,high\n");
+        let records = read_latency_csv(&path).unwrap();
+        assert_eq!(stages(&records), [("publish_data", 80)]);
+        assert_eq!(records[0].load, "high");
+    }
+
+    #[test]
+    fn reads_the_old_actuator_layout() {
+        let path = log_file("actuator", "timestamp,latency_type,latency_μs\n5000,cycle_time,300\n");
+        let records = read_latency_csv(&path).unwrap();
+        assert_eq!(stages(&records), [("cycle_time", 300)]);
+        assert_eq!(records[0].process, "actuator");
+        assert_eq!((records[0].start_us, records[0].end_us), (4700, 5000));
+    }
+
+    #[test]
+    fn malformed_rows() {
+        // the old layouts skip what does not parse
+        let path = log_file("junk", "task,latency\npublish_data,fast\n\npublish_data,80\ntruncated\n");
+        assert_eq!(stages(&read_latency_csv(&path).unwrap()), [("publish_data", 80)]);
+        // the current schema is written by this crate, a bad row fails the log
+        let header = LATENCY_RECORD_HEADER.join(",");
+        let path = log_file("bad", &format!("{}\nrun,controller,publish_data,x,0,0,0,0,normal\n", header));
+        assert!(read_latency_csv(&path).is_err());
+    }
+}
@@ -1,6 +1,33 @@
+#![allow(non_snake_case)]
+pub mod config;
 pub mod data_structure;
 pub mod actuator_lib;
 pub mod controller_lib;
+pub mod schedulability;
+pub mod latency_log;
+pub mod report;
+pub mod histogram;
+pub mod routing;
+pub mod allocation;
+pub mod registry;
+pub mod heartbeat;
+pub mod estop;
+pub mod broker;
+pub mod shutdown;
+pub mod freshness;
+pub mod backpressure;
+pub mod distributions;
+pub mod simulation;
+pub mod world;
+pub mod script;
+pub mod sensor_model;
+pub mod netfault;
+pub mod recording;
+pub mod catching;
+pub mod grip;
+pub mod obstacles;
+pub mod safety;
+pub mod telemetry;
 pub fn now_micros() -> u128 {
     use std::time::{SystemTime, UNIX_EPOCH};
     SystemTime::now()
@@ -0,0 +1,255 @@
+// network fault injection
+// a link sits in front of whatever actually sends a message: it decides whether the network loses,
+// duplicates, delays or reorders it, then runs the caller's send future once its delay is up,
+// and writes every decision to a csv so it can be lined up with the frame traces after the run,
+// buffered off the send path and flushed when the link closes
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::Instant;
+
+use crate::config::{open_append_csv, parse_env};
+use crate::latency_log::RunInfo;
+use crate::now_micros;
+use crate::sensor_model::gaussian;
+
+const NETFAULT_LOG_HEADER: [&str; 9] = [
+    "run_id", "link", "cycle", "arm_id", "copy", "action", "reordered", "delay_us", "sent_us",
+];
+
+/// Delay distribution, in ms.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(tag = "dist", rename_all = "kebab-case")]
+pub enum Distribution {
+    Fixed { ms: f32 },
+    Uniform { min_ms: f32, max_ms: f32 },
+    Normal { mean_ms: f32, std_ms: f32 },
+    Exponential { mean_ms: f32 },
+}
+
+impl Distribution {
+    /// One sample in µs, can be negative for jitter.
+    pub fn sample_us(&self, rng: &mut fastrand::Rng) -> f32 {
+        let ms = match *self {
+            Distribution::Fixed { ms } => ms,
+            Distribution::Uniform { min_ms, max_ms } => min_ms + rng.f32() * (max_ms - min_ms),
+            Distribution::Normal { mean_ms, std_ms } => mean_ms + std_ms * gaussian(rng),
+            Distribution::Exponential { mean_ms } => -mean_ms * (1.0 - rng.f32()).ln(),
+        };
+        ms * 1000.0
+    }
+}
+
+/// What the network does to one link, nothing unless given.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct LinkFaults {
+    pub latency: Option<Distribution>,
+    // added on top of the latency, the total is never below zero
+    pub jitter: Option<Distribution>,
+    pub loss_rate: f32,
+    pub duplicate_rate: f32,
+    // a reordered message is held back by reorder_ms so later ones overtake it
+    pub reorder_rate: f32,
+    pub reorder_ms: f32,
+}
+
+/// The file named by `NET_FAULTS_FILE`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct NetFaults {
+    // SIM_SEED otherwise
+    pub seed: Option<u64>,
+    // controller -> actuator frames
+    pub sensor: Option<LinkFaults>,
+    // actuator -> controller feedback
+    pub feedback: Option<LinkFaults>,
+}
+
+impl NetFaults {
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var("NET_FAULTS_FILE").ok()?;
+        let text = std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("Failed to read {}: {}", path, e));
+        Some(serde_json::from_str(&text).unwrap_or_else(|e| panic!("Invalid network faults in {}: {}", path, e)))
+    }
+}
+
+type Delivery = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+#[derive(Debug, Default)]
+pub struct LinkStats {
+    pub sent: u64,
+    pub lost: u64,
+    pub duplicated: u64,
+    pub reordered: u64,
+    pub delay_us: u64,
+}
+
+pub struct FaultyLink {
+    pub name: String,
+    pub faults: LinkFaults,
+    pub stats: LinkStats,
+    rng: fastrand::Rng,
+    run_id: String,
+    // buffered, flushed on close and by the writer's own drop otherwise
+    log: Option<csv::Writer<File>>,
+    queue: mpsc::UnboundedSender<(Instant, Delivery)>,
+    deliveries: JoinHandle<()>,
+}
+
+impl FaultyLink {
+    /// Decisions are appended to `netfaults_<process>.csv`.
+    pub fn new(name: &str, faults: LinkFaults, seed: u64, run: &RunInfo) -> Self {
+        let log = open_append_csv(format!("netfaults_{}.csv", run.process), &NETFAULT_LOG_HEADER)
+            .map_err(|e| eprintln!("Failed to open network fault log: {}", e))
+            .ok();
+        let (queue, rx) = mpsc::unbounded_channel();
+        FaultyLink {
+            name: name.to_string(),
+            faults,
+            stats: LinkStats::default(),
+            rng: fastrand::Rng::with_seed(seed),
+            run_id: run.run_id.clone(),
+            log,
+            queue,
+            deliveries: tokio::spawn(deliver(rx)),
+        }
+    }
+
+    /// The `sensor` or `feedback` link of `NET_FAULTS_FILE`, None when it has no faults.
+    pub fn from_env(link: &str, run: &RunInfo) -> Option<Self> {
+        let config = NetFaults::from_env()?;
+        let faults = match link {
+            "sensor" => config.sensor,
+            "feedback" => config.feedback,
+            other => panic!("unknown link {}", other),
+        }?;
+        let seed = config
+            .seed
+            .or_else(|| parse_env("SIM_SEED"))
+            .unwrap_or_else(|| fastrand::u64(..));
+        println!("> Injecting network faults on the {} link (seed {}): {:?}", link, seed, faults);
+        // each link its own stream
+        let seed = seed.wrapping_add(if link == "sensor" { 3 } else { 4 });
+        Some(Self::new(link, faults, seed, run))
+    }
+
+    /// Sends one message over the faulty network, `send` builds the future that really delivers a copy.
+    /// `cycle` and `arm_id` only label the log rows.
+    pub fn send<F, Fut>(&mut self, cycle: u64, arm_id: u32, mut send: F)
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let now = now_micros();
+        self.stats.sent += 1;
+        if self.rng.f32() < self.faults.loss_rate {
+            self.stats.lost += 1;
+            self.record(cycle, arm_id, 0, "lost", false, 0, now);
+            return;
+        }
+        let copies = if self.rng.f32() < self.faults.duplicate_rate {
+            self.stats.duplicated += 1;
+            2
+        } else {
+            1
+        };
+        for copy in 0..copies {
+            let rng = &mut self.rng;
+            let mut delay_us = self.faults.latency.map_or(0.0, |d| d.sample_us(rng))
+                + self.faults.jitter.map_or(0.0, |d| d.sample_us(rng));
+            let reordered = rng.f32() < self.faults.reorder_rate;
+            if reordered {
+                self.stats.reordered += 1;
+                delay_us += self.faults.reorder_ms * 1000.0;
+            }
+            let delay_us = delay_us.max(0.0) as u64;
+            self.stats.delay_us += delay_us;
+            let action = if copy == 0 { "delivered" } else { "duplicate" };
+            self.record(cycle, arm_id, copy, action, reordered, delay_us, now);
+            let due = Instant::now() + Duration::from_micros(delay_us);
+            if self.queue.send((due, Box::pin(send()))).is_err() {
+                eprintln!("Network fault link {} has stopped delivering", self.name);
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn record(&mut self, cycle: u64, arm_id: u32, copy: u32, action: &str, reordered: bool, delay_us: u64, sent_us: u128) {
+        let Some(log) = &mut self.log else {
+            return;
+        };
+        let row = [
+            self.run_id.clone(),
+            self.name.clone(),
+            cycle.to_string(),
+            arm_id.to_string(),
+            copy.to_string(),
+            action.to_string(),
+            reordered.to_string(),
+            delay_us.to_string(),
+            sent_us.to_string(),
+        ];
+        if let Err(e) = log.write_record(&row) {
+            eprintln!("Failed to write network fault log: {}", e);
+        }
+    }
+
+    /// Delivers what is still in flight, then stops.
+    pub async fn close(mut self) {
+        self.print_summary();
+        if let Some(Err(e)) = self.log.as_mut().map(|log| log.flush()) {
+            eprintln!("Failed to write network fault log: {}", e);
+        }
+        drop(self.queue);
+        if let Err(e) = self.deliveries.await {
+            eprintln!("Network fault link {} failed: {:?}", self.name, e);
+        }
+    }
+
+    pub fn print_summary(&self) {
+        let stats = &self.stats;
+        println!(
+            "> Network faults on {}: sent={} lost={} duplicated={} reordered={} mean_delay={}µs",
+            self.name,
+            stats.sent,
+            stats.lost,
+            stats.duplicated,
+            stats.reordered,
+            stats.delay_us / (stats.sent - stats.lost).max(1)
+        );
+    }
+}
+
+// starts each delivery at its due time, equal due times keep their send order
+// deliveries run as their own tasks, so a slow publish never holds back the ones due after it
+async fn deliver(mut rx: mpsc::UnboundedReceiver<(Instant, Delivery)>) {
+    let mut pending: BTreeMap<(Instant, u64), Delivery> = BTreeMap::new();
+    let mut in_flight = JoinSet::new();
+    let mut order = 0u64;
+    let mut open = true;
+    while open || !pending.is_empty() {
+        let next_due = pending.keys().next().map(|(due, _)| *due);
+        tokio::select! {
+            message = rx.recv(), if open => match message {
+                Some((due, delivery)) => {
+                    pending.insert((due, order), delivery);
+                    order += 1;
+                }
+                None => open = false,
+            },
+            _ = tokio::time::sleep_until(next_due.unwrap_or_else(Instant::now)), if next_due.is_some() => {
+                let (_, delivery) = pending.pop_first().expect("a delivery was due");
+                in_flight.spawn(delivery);
+            }
+            Some(_) = in_flight.join_next(), if !in_flight.is_empty() => {}
+        }
+    }
+    while in_flight.join_next().await.is_some() {}
+}
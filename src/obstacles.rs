@@ -0,0 +1,351 @@
+// workspace obstacles and collision checking
+// static obstacles in the arm's plane (circles and boxes, cm), every IK solution is checked link by link
+// along the whole joint motion from the current pose, the other elbow solution is tried when the first
+// one collides and the move is rejected when both do, the arm then stays where it is
+use std::f32::consts::PI;
+
+use serde::Deserialize;
+
+use crate::data_structure::PlanResult;
+
+// the arm rests folded up, elbow above the shoulder, see park_instructions in the actuator
+pub const REST_ANGLES: (f32, f32) = (std::f32::consts::FRAC_PI_2, 0.0);
+//poses checked along each joint motion unless the obstacles file sets `steps`
+pub const DEFAULT_SWEEP_STEPS: u32 = 10;
+
+/// Angle in (-π, π].
+pub fn wrap(angle: f32) -> f32 {
+    let wrapped = (angle + PI).rem_euclid(2.0 * PI) - PI;
+    if wrapped == -PI {
+        PI
+    } else {
+        wrapped
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "shape", rename_all = "kebab-case")]
+pub enum Obstacle {
+    Circle { center: (f32, f32), radius: f32 },
+    Box { min: (f32, f32), max: (f32, f32) },
+}
+
+fn distance_to_segment(p: (f32, f32), a: (f32, f32), b: (f32, f32)) -> f32 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length = dx * dx + dy * dy;
+    let t = if length > 0.0 {
+        (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / length).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    ((a.0 + t * dx - p.0).powi(2) + (a.1 + t * dy - p.1).powi(2)).sqrt()
+}
+
+impl Obstacle {
+    /// Whether the segment `a`-`b` comes within `clearance` of the obstacle.
+    pub fn hits_segment(&self, a: (f32, f32), b: (f32, f32), clearance: f32) -> bool {
+        match *self {
+            Obstacle::Circle { center, radius } => distance_to_segment(center, a, b) <= radius + clearance,
+            Obstacle::Box { min, max } => {
+                // liang-barsky clip against the box grown by the clearance
+                let (min, max) = ((min.0 - clearance, min.1 - clearance), (max.0 + clearance, max.1 + clearance));
+                let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+                let (mut enter, mut exit) = (0.0f32, 1.0f32);
+                for (p, q) in [(-dx, a.0 - min.0), (dx, max.0 - a.0), (-dy, a.1 - min.1), (dy, max.1 - a.1)] {
+                    if p == 0.0 {
+                        if q < 0.0 {
+                            return false;
+                        }
+                    } else if p < 0.0 {
+                        enter = enter.max(q / p);
+                    } else {
+                        exit = exit.min(q / p);
+                    }
+                }
+                enter <= exit
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ArmPose {
+    pub shoulder: (f32, f32),
+    pub elbow: (f32, f32),
+    pub wrist: (f32, f32),
+}
+
+/// Joint positions for shoulder and elbow angles, shoulder at the origin.
+pub fn forward(angles: (f32, f32), l1: f32, l2: f32) -> ArmPose {
+    let (theta1, theta2) = angles;
+    let elbow = (l1 * theta1.cos(), l1 * theta1.sin());
+    ArmPose {
+        shoulder: (0.0, 0.0),
+        elbow,
+        wrist: (elbow.0 + l2 * (theta1 + theta2).cos(), elbow.1 + l2 * (theta1 + theta2).sin()),
+    }
+}
+
+/// Both elbow solutions for a target, clamped to the arm's reach, the positive elbow angle first.
+pub fn inverse(target: (f32, f32), l1: f32, l2: f32) -> [(f32, f32); 2] {
+    let dist = (target.0.powi(2) + target.1.powi(2)).sqrt();
+    let (x, y) = if dist > l1 + l2 {
+        let scale = (l1 + l2) / dist;
+        (target.0 * scale, target.1 * scale)
+    } else {
+        target
+    };
+    let cos_theta2 = ((x.powi(2) + y.powi(2) - l1.powi(2) - l2.powi(2)) / (2.0 * l1 * l2)).clamp(-1.0, 1.0);
+    let solution = |theta2: f32| {
+        let k1 = l1 + l2 * theta2.cos();
+        let k2 = l2 * theta2.sin();
+        (y.atan2(x) - k2.atan2(k1), theta2)
+    };
+    let theta2 = cos_theta2.acos();
+    [solution(theta2), solution(-theta2)]
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Workspace {
+    pub obstacles: Vec<Obstacle>,
+    // extra distance kept from every obstacle, cm
+    pub clearance: f32,
+    // poses checked along each joint motion, the final one included
+    pub steps: u32,
+}
+
+impl Default for Workspace {
+    fn default() -> Self {
+        Workspace {
+            obstacles: Vec::new(),
+            clearance: 0.0,
+            steps: DEFAULT_SWEEP_STEPS,
+        }
+    }
+}
+
+impl Workspace {
+    /// The workspace in `OBSTACLES_FILE`, empty otherwise.
+    pub fn from_env() -> Self {
+        let Ok(path) = std::env::var("OBSTACLES_FILE") else {
+            return Workspace::default();
+        };
+        let text = std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("Failed to read {}: {}", path, e));
+        let workspace: Workspace =
+            serde_json::from_str(&text).unwrap_or_else(|e| panic!("Invalid obstacles in {}: {}", path, e));
+        println!("> {} obstacles from {}", workspace.obstacles.len(), path);
+        workspace
+    }
+
+    /// The first obstacle either link of the pose hits.
+    pub fn collides(&self, pose: &ArmPose) -> Option<usize> {
+        self.obstacles.iter().position(|obstacle| {
+            obstacle.hits_segment(pose.shoulder, pose.elbow, self.clearance)
+                || obstacle.hits_segment(pose.elbow, pose.wrist, self.clearance)
+        })
+    }
+
+    /// The first obstacle hit while the joints move linearly from `from` to `to`,
+    /// each joint turning the short way round.
+    pub fn sweep(&self, from: (f32, f32), to: (f32, f32), l1: f32, l2: f32) -> Option<usize> {
+        if self.obstacles.is_empty() {
+            return None;
+        }
+        let steps = self.steps.max(1);
+        let turn = (wrap(to.0 - from.0), wrap(to.1 - from.1));
+        (1..=steps).find_map(|step| {
+            let t = step as f32 / steps as f32;
+            let angles = (from.0 + turn.0 * t, from.1 + turn.1 * t);
+            self.collides(&forward(angles, l1, l2))
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PlanStats {
+    pub clear: u64,
+    pub replanned: u64,
+    pub rejected: u64,
+}
+
+impl PlanStats {
+    pub fn record(&mut self, result: &PlanResult) {
+        match result {
+            PlanResult::Clear => self.clear += 1,
+            PlanResult::Replanned => self.replanned += 1,
+            PlanResult::Rejected { .. } => self.rejected += 1,
+        }
+    }
+
+    pub fn print_summary(&self, label: &str) {
+        println!(
+            "> {}: clear={} replanned={} rejected={}",
+            label, self.clear, self.replanned, self.rejected
+        );
+    }
+}
+
+/// Plans each move of one arm against the workspace, from the pose it was last sent to.
+pub struct MotionPlanner {
+    pub workspace: Workspace,
+    pub l1: f32,
+    pub l2: f32,
+    current: (f32, f32),
+    pub stats: PlanStats,
+}
+
+impl MotionPlanner {
+    pub fn new(workspace: Workspace, l1: f32, l2: f32) -> Self {
+        MotionPlanner {
+            workspace,
+            l1,
+            l2,
+            current: REST_ANGLES,
+            stats: PlanStats::default(),
+        }
+    }
+
+    pub fn current_pose(&self) -> ArmPose {
+        forward(self.current, self.l1, self.l2)
+    }
+
+    /// Shoulder and elbow angles of the pose the arm was last sent to.
+    pub fn current_angles(&self) -> (f32, f32) {
+        self.current
+    }
+
+    /// Moves on from `angles` when the joints ended up elsewhere than planned.
+    pub fn set_current(&mut self, angles: (f32, f32)) {
+        self.current = angles;
+    }
+
+    /// The pose to move to for `target`, the current one when the move is rejected.
+    pub fn plan(&mut self, target: (f32, f32)) -> (ArmPose, PlanResult) {
+        let candidates = inverse(target, self.l1, self.l2);
+        let mut first_hit = None;
+        for (index, angles) in candidates.into_iter().enumerate() {
+            match self.workspace.sweep(self.current, angles, self.l1, self.l2) {
+                None => {
+                    self.current = angles;
+                    let result = if index == 0 { PlanResult::Clear } else { PlanResult::Replanned };
+                    self.stats.record(&result);
+                    return (self.current_pose(), result);
+                }
+                Some(obstacle) => {
+                    first_hit.get_or_insert(obstacle);
+                }
+            }
+        }
+        let result = PlanResult::Rejected {
+            obstacle: first_hit.expect("a rejected move hit something"),
+        };
+        self.stats.record(&result);
+        (self.current_pose(), result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::{FRAC_PI_2, PI};
+
+    fn square(min: (f32, f32), max: (f32, f32)) -> Obstacle {
+        Obstacle::Box { min, max }
+    }
+
+    fn workspace(obstacles: Vec<Obstacle>) -> Workspace {
+        Workspace {
+            obstacles,
+            ..Workspace::default()
+        }
+    }
+
+    #[test]
+    fn segment_through_a_box_hits_it() {
+        let obstacle = square((1.0, -1.0), (2.0, 1.0));
+        assert!(obstacle.hits_segment((0.0, 0.0), (3.0, 0.0), 0.0));
+        // ends inside
+        assert!(obstacle.hits_segment((0.0, 0.0), (1.5, 0.5), 0.0));
+        // diagonal across a corner
+        assert!(obstacle.hits_segment((0.5, 0.0), (1.5, 1.5), 0.0));
+    }
+
+    #[test]
+    fn segment_beside_a_box_misses_it() {
+        let obstacle = square((1.0, -1.0), (2.0, 1.0));
+        // stops short
+        assert!(!obstacle.hits_segment((0.0, 0.0), (0.9, 0.0), 0.0));
+        // passes above
+        assert!(!obstacle.hits_segment((0.0, 2.0), (3.0, 2.0), 0.0));
+        // parallel to a side, outside it
+        assert!(!obstacle.hits_segment((0.5, -2.0), (0.5, 2.0), 0.0));
+        // misses the corner
+        assert!(!obstacle.hits_segment((0.0, 1.5), (1.5, 3.0), 0.0));
+    }
+
+    #[test]
+    fn clearance_grows_the_box() {
+        let obstacle = square((1.0, -1.0), (2.0, 1.0));
+        assert!(!obstacle.hits_segment((0.0, 1.4), (3.0, 1.4), 0.3));
+        assert!(obstacle.hits_segment((0.0, 1.4), (3.0, 1.4), 0.5));
+    }
+
+    #[test]
+    fn circle_hits_within_radius_and_clearance() {
+        let obstacle = Obstacle::Circle {
+            center: (2.0, 1.0),
+            radius: 0.5,
+        };
+        assert!(obstacle.hits_segment((0.0, 0.8), (4.0, 0.8), 0.0));
+        assert!(!obstacle.hits_segment((0.0, 0.0), (4.0, 0.0), 0.0));
+        assert!(obstacle.hits_segment((0.0, 0.0), (4.0, 0.0), 0.6));
+        // closest to the end point, not the infinite line
+        assert!(!obstacle.hits_segment((3.0, 1.0), (5.0, 1.0), 0.0));
+    }
+
+    #[test]
+    fn default_sweep_checks_poses_between_the_ends() {
+        // straight up is in the way of a half turn, neither end pose touches it
+        let workspace = workspace(vec![square((-0.5, 5.0), (0.5, 7.0))]);
+        assert_eq!(workspace.steps, DEFAULT_SWEEP_STEPS);
+        assert_eq!(workspace.collides(&forward((0.0, 0.0), 3.0, 3.0)), None);
+        assert_eq!(workspace.collides(&forward((PI, 0.0), 3.0, 3.0)), None);
+        assert_eq!(workspace.sweep((0.0, 0.0), (PI, 0.0), 3.0, 3.0), Some(0));
+    }
+
+    #[test]
+    fn sweep_turns_the_short_way_round() {
+        // from 3 rad to -3 rad is 0.28 rad through π, not 6 rad back through 0 where the box is
+        let workspace = workspace(vec![square((4.0, -1.0), (7.0, 1.0))]);
+        assert_eq!(workspace.sweep((3.0, 0.0), (-3.0, 0.0), 3.0, 3.0), None);
+        assert_eq!(workspace.sweep((FRAC_PI_2, 0.0), (-FRAC_PI_2, 0.0), 3.0, 3.0), None);
+        assert_eq!(workspace.sweep((1.0, 0.0), (-1.0, 0.0), 3.0, 3.0), Some(0));
+    }
+
+    #[test]
+    fn planner_takes_the_other_elbow_or_stays_put() {
+        // a post under the positive elbow solution for a target straight ahead
+        let target = (4.0, 0.0);
+        let [first, _] = inverse(target, 3.0, 3.0);
+        let elbow = forward(first, 3.0, 3.0).elbow;
+        let post = Obstacle::Circle {
+            center: elbow,
+            radius: 0.2,
+        };
+        let mut planner = MotionPlanner::new(workspace(vec![post.clone()]), 3.0, 3.0);
+        planner.set_current((0.0, 0.0));
+        let (pose, result) = planner.plan(target);
+        assert_eq!(result, PlanResult::Replanned);
+        assert!((pose.wrist.0 - 4.0).abs() < 1e-3 && pose.wrist.1.abs() < 1e-3);
+
+        // a wall across the whole reach blocks both
+        let wall = square((3.5, -6.0), (3.7, 6.0));
+        let mut planner = MotionPlanner::new(workspace(vec![wall]), 3.0, 3.0);
+        planner.set_current((0.0, 0.0));
+        let before = planner.current_angles();
+        let (_, result) = planner.plan((6.0, 0.0));
+        assert_eq!(result, PlanResult::Rejected { obstacle: 0 });
+        assert_eq!(planner.current_angles(), before);
+    }
+}
@@ -0,0 +1,274 @@
+// message recording and replay
+// with RECORD=1 every sensor frame an actuator receives and every feedback message the controller
+// receives is written as one JSON line with its receive time, a recording can then be replayed into
+// the controller or an actuator at its original timing or as fast as possible
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::bool_from_env;
+use crate::now_micros;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Stream {
+    // controller -> actuator frames
+    Sensor,
+    // actuator -> controller feedback
+    Feedback,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedMessage {
+    // µs since the epoch, when the message was received
+    pub received: u128,
+    pub stream: Stream,
+    pub arm_id: u32,
+    // the message as it came off the wire
+    pub payload: serde_json::Value,
+}
+
+// µs stamps a payload may carry besides `timestamp`, at the top level and in its `trace`
+const PAYLOAD_STAMPS: [&str; 2] = ["arrived_at_ground", "busy_until"];
+const TRACE_STAMPS: [&str; 7] = [
+    "generated",
+    "filtered",
+    "published",
+    "received",
+    "actuated",
+    "feedback_sent",
+    "feedback_received",
+];
+
+// moves a stamp that is set, 0 means the message never reached that point
+fn shift_stamp(object: &mut serde_json::Value, field: &str, shift: u64) {
+    if let Some(stamp) = object.get_mut(field) {
+        if let Some(value) = stamp.as_u64().filter(|value| *value > 0) {
+            *stamp = (value + shift).into();
+        }
+    }
+}
+
+impl RecordedMessage {
+    /// Moves every stamp in the payload, its `timestamp`, its deadlines and each hop of its `trace`,
+    /// by the time passed since it was recorded, so a replayed message is as old on arrival as the
+    /// original was and the hops between its stamps are unchanged.
+    pub fn restamp(&mut self, now: u128) {
+        let shift = now.saturating_sub(self.received) as u64;
+        for field in std::iter::once("timestamp").chain(PAYLOAD_STAMPS) {
+            shift_stamp(&mut self.payload, field, shift);
+        }
+        if let Some(trace) = self.payload.get_mut("trace") {
+            for field in TRACE_STAMPS {
+                shift_stamp(trace, field, shift);
+            }
+        }
+        self.received = now;
+    }
+}
+
+pub struct Recorder {
+    pub path: String,
+    writer: BufWriter<File>,
+    recorded: u64,
+}
+
+impl Recorder {
+    /// A new recording, replacing an older one at `path`.
+    pub fn create(path: &str) -> std::io::Result<Self> {
+        Ok(Recorder {
+            path: path.to_string(),
+            writer: BufWriter::new(File::create(path)?),
+            recorded: 0,
+        })
+    }
+
+    /// `recording_<process>.jsonl` when `RECORD=1`.
+    pub fn from_env(process: &str) -> Option<Self> {
+        if !bool_from_env("RECORD", false) {
+            return None;
+        }
+        let path = format!("recording_{}.jsonl", process);
+        match Self::create(&path) {
+            Ok(recorder) => {
+                println!("> Recording messages to {}", path);
+                Some(recorder)
+            }
+            Err(e) => {
+                eprintln!("Failed to create recording {}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    /// Records one message as received, payloads that are not JSON are skipped.
+    pub fn record(&mut self, stream: Stream, arm_id: u32, payload: &[u8], received: u128) {
+        let payload = match serde_json::from_slice(payload) {
+            Ok(payload) => payload,
+            Err(e) => {
+                eprintln!("Not recording a {:?} message that is not JSON: {}", stream, e);
+                return;
+            }
+        };
+        let message = RecordedMessage {
+            received,
+            stream,
+            arm_id,
+            payload,
+        };
+        let line = serde_json::to_string(&message).expect("Failed to serialize recorded message");
+        if let Err(e) = writeln!(self.writer, "{}", line) {
+            eprintln!("Failed to write recording {}: {}", self.path, e);
+            return;
+        }
+        self.recorded += 1;
+    }
+
+    pub fn finish(mut self) {
+        if let Err(e) = self.writer.flush() {
+            eprintln!("Failed to write recording {}: {}", self.path, e);
+        }
+        println!("> Recorded {} messages to {}", self.recorded, self.path);
+    }
+}
+
+/// Every message of a recording, in receive order.
+pub fn load_recording(path: &str) -> Result<Vec<RecordedMessage>, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut messages = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| format!("{}: {}", path, e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let message = serde_json::from_str(&line).map_err(|e| format!("{}:{}: {}", path, number + 1, e))?;
+        messages.push(message);
+    }
+    messages.sort_by_key(|message: &RecordedMessage| message.received);
+    Ok(messages)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pacing {
+    // the recorded gaps between messages
+    Original,
+    // back to back
+    Fast,
+}
+
+impl FromStr for Pacing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "original" => Ok(Pacing::Original),
+            "fast" => Ok(Pacing::Fast),
+            _ => Err(format!("unknown pacing {}, expected original or fast", s)),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ReplayStats {
+    pub replayed: u64,
+    pub failed: u64,
+    // how far behind the recorded timing a message went out, original pacing only
+    pub max_late_us: u128,
+    pub elapsed: Duration,
+}
+
+impl ReplayStats {
+    pub fn print_summary(&self, pacing: Pacing) {
+        println!(
+            "> Replay ({:?}): replayed={} failed={} in {:?}, max_late={}µs",
+            pacing, self.replayed, self.failed, self.elapsed, self.max_late_us
+        );
+    }
+}
+
+/// Hands every message to `send` at its pacing, `send` returns false when the message could not go out.
+pub async fn replay<F, Fut>(messages: Vec<RecordedMessage>, pacing: Pacing, restamp: bool, mut send: F) -> ReplayStats
+where
+    F: FnMut(RecordedMessage) -> Fut,
+    Fut: std::future::Future<Output = bool>,
+{
+    let mut stats = ReplayStats::default();
+    let started = tokio::time::Instant::now();
+    let start_us = now_micros();
+    let Some(first) = messages.first().map(|message| message.received) else {
+        return stats;
+    };
+    for mut message in messages {
+        if pacing == Pacing::Original {
+            let offset = Duration::from_micros((message.received - first) as u64);
+            tokio::time::sleep_until(started + offset).await;
+            let late = now_micros().saturating_sub(start_us + offset.as_micros());
+            stats.max_late_us = stats.max_late_us.max(late);
+        }
+        if restamp {
+            message.restamp(now_micros());
+        }
+        if send(message).await {
+            stats.replayed += 1;
+        } else {
+            stats.failed += 1;
+        }
+    }
+    stats.elapsed = started.elapsed();
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_structure::{FeedbackData, FrameTrace, ObjectData, SensorArmData};
+
+    #[test]
+    fn restamped_feedback_keeps_its_hops() {
+        let mut frame = SensorArmData::new(ObjectData {
+            object_velocity: 10.0,
+            object_mass: 3.0,
+            object_size: 4.5,
+            object_x: 5.5,
+            object_y: 0.0,
+            object_height: 1.5,
+        });
+        frame.trace = FrameTrace {
+            cycle: 42,
+            sequence: 40,
+            object_id: 7,
+            generated: 1_000,
+            filtered: 1_050,
+            published: 1_200,
+            received: 1_500,
+            actuated: 1_700,
+            feedback_sent: 1_800,
+            // set by the controller on arrival
+            feedback_received: 0,
+        };
+        let mut feedback = frame.to_feedback(151_700);
+        feedback.timestamp = 1_800;
+        let mut message = RecordedMessage {
+            received: 2_000,
+            stream: Stream::Feedback,
+            arm_id: 1,
+            payload: serde_json::to_value(&feedback).unwrap(),
+        };
+
+        let shift = 5_000_000;
+        message.restamp(2_000 + shift);
+        assert_eq!(message.received, 2_000 + shift);
+        let replayed: FeedbackData = serde_json::from_value(message.payload).unwrap();
+        assert_eq!(replayed.timestamp, feedback.timestamp + shift);
+        assert_eq!(replayed.busy_until, feedback.busy_until + shift);
+        assert_eq!(replayed.arrived_at_ground, feedback.arrived_at_ground + shift);
+        assert_eq!(replayed.trace.generated, 1_000 + shift);
+        assert_eq!(replayed.trace.feedback_sent, 1_800 + shift);
+        assert_eq!(replayed.trace.feedback_received, 0);
+        assert_eq!(replayed.trace.hops(), feedback.trace.hops());
+        assert_eq!((replayed.trace.cycle, replayed.trace.sequence, replayed.trace.object_id), (42, 40, 7));
+    }
+}
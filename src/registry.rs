@@ -0,0 +1,264 @@
+// live registry of the arms the controller serves
+// actuators register on start and keep sending heartbeats, they leave by deregistering
+// or by going quiet for longer than the timeout, which acts as the controller's watchdog
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use lapin::{options::BasicPublishOptions, BasicProperties, Channel};
+use tokio::sync::Mutex as AsyncMutex;
+
+use crate::config::duration_from_env;
+use crate::data_structure::*;
+use crate::now_micros;
+use crate::routing::REGISTRY_QUEUE;
+
+//arms not heard from for this long are dropped, overridden by ARM_TIMEOUT_MS
+pub const DEFAULT_ARM_TIMEOUT: Duration = Duration::from_millis(1000);
+
+pub type FeedbackSlot = Arc<AsyncMutex<Option<FeedbackData>>>;
+
+#[derive(Debug, Clone)]
+pub struct RegisteredArm {
+    pub info: ArmRegistration,
+    //latest feedback of this arm, read by the sensor loop
+    pub feedback: FeedbackSlot,
+    pub last_seen: u128,
+    //configured up front through ARM_IDS, never removed
+    pub pinned: bool,
+    //under an emergency stop, kept registered but given no objects
+    pub stopped: bool,
+    //pinned arm that missed its heartbeats, given no objects until it is heard from again
+    pub silent: bool,
+}
+
+pub struct ArmRegistry {
+    arms: Mutex<BTreeMap<u32, RegisteredArm>>,
+    pub timeout: Duration,
+}
+
+impl ArmRegistry {
+    pub fn new(timeout: Duration) -> Self {
+        ArmRegistry {
+            arms: Mutex::new(BTreeMap::new()),
+            timeout,
+        }
+    }
+
+    /// Timeout from `ARM_TIMEOUT_MS`, `DEFAULT_ARM_TIMEOUT` otherwise.
+    pub fn from_env() -> Self {
+        Self::new(duration_from_env("ARM_TIMEOUT_MS", DEFAULT_ARM_TIMEOUT))
+    }
+
+    /// Adds an arm that is served without ever registering.
+    pub fn pin(&self, arm_id: u32) {
+        let info = ArmRegistration {
+            arm_id,
+            model: "static".to_string(),
+            reach: ARM_REACH,
+            capabilities: Vec::new(),
+            timestamp: now_micros(),
+        };
+        self.insert(info, true);
+    }
+
+    /// Adds or refreshes an arm, returns true when it was not registered before.
+    pub fn register(&self, info: ArmRegistration) -> bool {
+        self.insert(info, false)
+    }
+
+    fn insert(&self, info: ArmRegistration, pinned: bool) -> bool {
+        let mut arms = self.arms.lock().unwrap();
+        let now = now_micros();
+        match arms.get_mut(&info.arm_id) {
+            Some(arm) => {
+                arm.info = info;
+                arm.last_seen = now;
+                arm.silent = false;
+                false
+            }
+            None => {
+                arms.insert(
+                    info.arm_id,
+                    RegisteredArm {
+                        info,
+                        feedback: Arc::new(AsyncMutex::new(None)),
+                        last_seen: now,
+                        pinned,
+                        stopped: false,
+                        silent: false,
+                    },
+                );
+                true
+            }
+        }
+    }
+
+    pub fn deregister(&self, arm_id: u32) -> Option<RegisteredArm> {
+        self.arms.lock().unwrap().remove(&arm_id)
+    }
+
+    /// Marks the arm as alive, any message from it counts.
+    /// Returns true when a silent pinned arm came back.
+    pub fn touch(&self, arm_id: u32) -> bool {
+        match self.arms.lock().unwrap().get_mut(&arm_id) {
+            Some(arm) => {
+                arm.last_seen = now_micros();
+                std::mem::replace(&mut arm.silent, false)
+            }
+            None => false,
+        }
+    }
+
+    pub fn feedback_slot(&self, arm_id: u32) -> Option<FeedbackSlot> {
+        self.arms
+            .lock()
+            .unwrap()
+            .get(&arm_id)
+            .map(|arm| Arc::clone(&arm.feedback))
+    }
+
+    /// Marks the addressed arms (every arm for None) as stopped or released.
+    pub fn set_stopped(&self, arm_id: Option<u32>, stopped: bool) {
+        for (id, arm) in self.arms.lock().unwrap().iter_mut() {
+            if arm_id.is_none_or(|target| target == *id) {
+                arm.stopped = stopped;
+            }
+        }
+    }
+
+    /// Ids and feedback slots of the arms that can be given objects right now.
+    pub fn active(&self) -> Vec<(u32, FeedbackSlot)> {
+        self.arms
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|arm| !arm.stopped && !arm.silent)
+            .map(|arm| (arm.info.arm_id, Arc::clone(&arm.feedback)))
+            .collect()
+    }
+
+    pub fn ids(&self) -> Vec<u32> {
+        self.arms.lock().unwrap().keys().copied().collect()
+    }
+
+    /// Takes every arm silent for longer than the timeout out of service and returns their ids.
+    /// Unpinned arms are removed, pinned arms are kept as silent and served again once heard from.
+    pub fn expire(&self) -> Vec<u32> {
+        let now = now_micros();
+        let timeout = self.timeout.as_micros();
+        let mut expired = Vec::new();
+        self.arms.lock().unwrap().retain(|id, arm| {
+            if arm.silent || now.saturating_sub(arm.last_seen) <= timeout {
+                return true;
+            }
+            expired.push(*id);
+            arm.silent = arm.pinned;
+            arm.pinned
+        });
+        expired
+    }
+
+    /// Applies one message from the registry queue.
+    pub fn handle(&self, message: RegistryMessage) {
+        match message {
+            RegistryMessage::Register(info) => {
+                let arm_id = info.arm_id;
+                let model = info.model.clone();
+                if self.register(info) {
+                    println!("> Arm {} ({}) registered, serving {:?}", arm_id, model, self.ids());
+                }
+            }
+            RegistryMessage::Deregister { arm_id, .. } => {
+                if self.deregister(arm_id).is_some() {
+                    println!("> Arm {} deregistered, serving {:?}", arm_id, self.ids());
+                }
+            }
+        }
+    }
+}
+
+/// Actuator side: publishes a registry message to the controller.
+pub async fn announce(channel: &Channel, message: &RegistryMessage) -> Result<(), Box<dyn std::error::Error>> {
+    let payload = serde_json::to_vec(message)?;
+    channel
+        .basic_publish(
+            "",
+            REGISTRY_QUEUE,
+            BasicPublishOptions::default(),
+            &payload,
+            BasicProperties::default(),
+        )
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registration(arm_id: u32) -> ArmRegistration {
+        ArmRegistration {
+            arm_id,
+            model: "two_link_planar".to_string(),
+            reach: ARM_REACH,
+            capabilities: Vec::new(),
+            timestamp: now_micros(),
+        }
+    }
+
+    // as if the arm was last heard from `ago` before now
+    fn silence(registry: &ArmRegistry, arm_id: u32, ago: Duration) {
+        let mut arms = registry.arms.lock().unwrap();
+        arms.get_mut(&arm_id).unwrap().last_seen = now_micros() - ago.as_micros();
+    }
+
+    fn active_ids(registry: &ArmRegistry) -> Vec<u32> {
+        registry.active().into_iter().map(|(arm_id, _)| arm_id).collect()
+    }
+
+    #[test]
+    fn arms_expire_after_the_timeout() {
+        let registry = ArmRegistry::new(Duration::from_secs(1));
+        assert!(registry.register(registration(1)));
+        assert!(registry.register(registration(2)));
+        assert!(!registry.register(registration(2)), "already registered");
+
+        // 900ms is still within the 1s timeout
+        silence(&registry, 1, Duration::from_millis(900));
+        assert!(registry.expire().is_empty());
+        silence(&registry, 1, Duration::from_millis(1100));
+        assert_eq!(registry.expire(), [1]);
+        assert_eq!(registry.ids(), [2]);
+        assert!(registry.feedback_slot(1).is_none());
+    }
+
+    #[test]
+    fn pinned_arms_go_silent_until_heard_from() {
+        let registry = ArmRegistry::new(Duration::from_secs(1));
+        registry.pin(3);
+        silence(&registry, 3, Duration::from_secs(2));
+        assert_eq!(registry.expire(), [3]);
+        assert_eq!(registry.ids(), [3]);
+        assert!(active_ids(&registry).is_empty());
+        // expired once, not again on every check
+        assert!(registry.expire().is_empty());
+
+        assert!(registry.touch(3), "came back");
+        assert_eq!(active_ids(&registry), [3]);
+        assert!(!registry.touch(3));
+    }
+
+    #[test]
+    fn stopped_arms_stay_registered_without_objects() {
+        let registry = ArmRegistry::new(Duration::from_secs(1));
+        registry.register(registration(1));
+        registry.register(registration(2));
+        registry.set_stopped(Some(1), true);
+        assert_eq!(active_ids(&registry), [2]);
+        registry.set_stopped(None, false);
+        assert_eq!(active_ids(&registry), [1, 2]);
+        assert!(registry.deregister(1).is_some());
+        assert_eq!(registry.ids(), [2]);
+    }
+}
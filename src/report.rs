@@ -0,0 +1,213 @@
+// performance report over latency logs, replaces pivoting the csvs in a spreadsheet
+// stats are grouped by (load, stage) and can be rendered as markdown or csv
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt::Write as _;
+use std::path::Path;
+
+use crate::latency_log::LatencyRecord;
+
+/// Deadline a stage is held to, in µs, taken from the thresholds in the README.
+/// Stages not listed fall back to the 5 ms loop period.
+pub fn default_deadline_us(stage: &str) -> u128 {
+    match stage {
+        // data reception within 1ms of transmission, transmission within 1ms of processing,
+        // feedback within 1ms of actuation
+        "process_sensor_data" | "publish_data" | "consume_feedback" | "data_reception" => 1_000,
+        // actuators respond within 1-2ms
+        "shoulder_actuation" | "elbow_actuation" => 2_000,
+        _ => 5_000,
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct StageStats {
+    pub load: String,
+    pub stage: String,
+    pub count: usize,
+    pub mean_us: f64,
+    pub p50_us: u128,
+    pub p90_us: u128,
+    pub p99_us: u128,
+    pub max_us: u128,
+    pub deadline_us: u128,
+    pub misses: usize,
+    pub miss_ratio: f64,
+}
+
+/// Nearest rank percentile of an already sorted slice.
+pub fn percentile(sorted: &[u128], p: f64) -> u128 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Per (load, stage) statistics, `deadlines` overrides `default_deadline_us` per stage.
+pub fn compute_stats(records: &[LatencyRecord], deadlines: &HashMap<String, u128>) -> Vec<StageStats> {
+    let mut groups: BTreeMap<(String, String), Vec<u128>> = BTreeMap::new();
+    for r in records {
+        // legacy logs carry no load profile
+        let load = if r.load.is_empty() { "unknown" } else { r.load.as_str() };
+        groups
+            .entry((load.to_string(), r.stage.clone()))
+            .or_default()
+            .push(r.latency_us);
+    }
+
+    groups
+        .into_iter()
+        .map(|((load, stage), mut latencies)| {
+            latencies.sort_unstable();
+            let deadline_us = deadlines
+                .get(&stage)
+                .copied()
+                .unwrap_or_else(|| default_deadline_us(&stage));
+            let count = latencies.len();
+            let misses = latencies.iter().filter(|l| **l > deadline_us).count();
+            StageStats {
+                load,
+                stage,
+                count,
+                mean_us: latencies.iter().sum::<u128>() as f64 / count as f64,
+                p50_us: percentile(&latencies, 50.0),
+                p90_us: percentile(&latencies, 90.0),
+                p99_us: percentile(&latencies, 99.0),
+                max_us: *latencies.last().unwrap_or(&0),
+                deadline_us,
+                misses,
+                miss_ratio: misses as f64 / count as f64,
+            }
+        })
+        .collect()
+}
+
+//difference between two runs for one (load, stage), candidate minus baseline
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct StageComparison {
+    pub load: String,
+    pub stage: String,
+    pub baseline_mean_us: f64,
+    pub candidate_mean_us: f64,
+    pub delta_mean_us: f64,
+    pub baseline_p99_us: u128,
+    pub candidate_p99_us: u128,
+    pub delta_p99_us: i128,
+    pub baseline_miss_ratio: f64,
+    pub candidate_miss_ratio: f64,
+}
+
+/// Matches the two stat sets on (load, stage), groups only present in one run are left out.
+pub fn compare(baseline: &[StageStats], candidate: &[StageStats]) -> Vec<StageComparison> {
+    baseline
+        .iter()
+        .filter_map(|b| {
+            let c = candidate
+                .iter()
+                .find(|c| c.load == b.load && c.stage == b.stage)?;
+            Some(StageComparison {
+                load: b.load.clone(),
+                stage: b.stage.clone(),
+                baseline_mean_us: b.mean_us,
+                candidate_mean_us: c.mean_us,
+                delta_mean_us: c.mean_us - b.mean_us,
+                baseline_p99_us: b.p99_us,
+                candidate_p99_us: c.p99_us,
+                delta_p99_us: c.p99_us as i128 - b.p99_us as i128,
+                baseline_miss_ratio: b.miss_ratio,
+                candidate_miss_ratio: c.miss_ratio,
+            })
+        })
+        .collect()
+}
+
+pub fn stats_markdown(stats: &[StageStats]) -> String {
+    let mut out = String::new();
+    out.push_str("| load | stage | count | mean µs | p50 µs | p90 µs | p99 µs | max µs | deadline µs | miss ratio |\n");
+    out.push_str("|---|---|---:|---:|---:|---:|---:|---:|---:|---:|\n");
+    for s in stats {
+        let _ = writeln!(
+            out,
+            "| {} | {} | {} | {:.1} | {} | {} | {} | {} | {} | {:.2}% |",
+            s.load, s.stage, s.count, s.mean_us, s.p50_us, s.p90_us, s.p99_us, s.max_us,
+            s.deadline_us, s.miss_ratio * 100.0
+        );
+    }
+    out
+}
+
+pub fn comparison_markdown(rows: &[StageComparison]) -> String {
+    let mut out = String::new();
+    out.push_str("| load | stage | mean µs (A → B) | Δ mean µs | p99 µs (A → B) | Δ p99 µs | miss ratio (A → B) |\n");
+    out.push_str("|---|---|---:|---:|---:|---:|---:|\n");
+    for r in rows {
+        let _ = writeln!(
+            out,
+            "| {} | {} | {:.1} → {:.1} | {:+.1} | {} → {} | {:+} | {:.2}% → {:.2}% |",
+            r.load, r.stage, r.baseline_mean_us, r.candidate_mean_us, r.delta_mean_us,
+            r.baseline_p99_us, r.candidate_p99_us, r.delta_p99_us,
+            r.baseline_miss_ratio * 100.0, r.candidate_miss_ratio * 100.0
+        );
+    }
+    out
+}
+
+/// Writes any of the summary rows above as a csv with a header.
+pub fn write_csv<T: serde::Serialize, P: AsRef<Path>>(rows: &[T], path: P) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_path(path)?;
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::latency_log::RunInfo;
+
+    fn records(run: &RunInfo, stage: &str, latencies: impl IntoIterator<Item = u128>) -> Vec<LatencyRecord> {
+        latencies
+            .into_iter()
+            .enumerate()
+            .map(|(cycle, latency)| run.record(stage, cycle as u64, 1_000, 1_000 + latency))
+            .collect()
+    }
+
+    #[test]
+    fn nearest_rank_percentiles() {
+        let sorted: Vec<u128> = (1..=100).collect();
+        assert_eq!(percentile(&sorted, 50.0), 50);
+        assert_eq!(percentile(&sorted, 99.0), 99);
+        assert_eq!(percentile(&sorted, 100.0), 100);
+        assert_eq!(percentile(&sorted, 0.0), 1);
+        assert_eq!(percentile(&[7], 99.0), 7);
+    }
+
+    #[test]
+    fn empty_input_gives_nothing() {
+        assert_eq!(percentile(&[], 50.0), 0);
+        assert!(compute_stats(&[], &HashMap::new()).is_empty());
+    }
+
+    #[test]
+    fn stats_per_load_and_stage() {
+        let run = RunInfo::new("run", "controller", "high");
+        // 10µs steps, the last 5 past publish_data's 1ms deadline
+        let mut log = records(&run, "publish_data", (1..=105).map(|n| n * 10));
+        log.extend(records(&RunInfo::new("run", "controller", ""), "publish_data", [1]));
+        let stats = compute_stats(&log, &HashMap::new());
+        assert_eq!(stats.len(), 2);
+        let high = stats.iter().find(|s| s.load == "high").unwrap();
+        assert_eq!(high.count, 105);
+        assert_eq!((high.p50_us, high.p99_us, high.max_us), (530, 1040, 1050));
+        assert_eq!(high.mean_us, 530.0);
+        assert_eq!((high.deadline_us, high.misses), (1_000, 5));
+        assert!(stats.iter().any(|s| s.load == "unknown" && s.count == 1));
+
+        let deadlines = HashMap::from([("publish_data".to_string(), 2_000)]);
+        assert_eq!(compute_stats(&log, &deadlines)[0].misses, 0);
+    }
+}
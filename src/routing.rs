@@ -0,0 +1,130 @@
+// per arm message routing over two topic exchanges
+// sensor frames go out on `sensor.arm.<id>` and every actuator binds its own queue to its key,
+// feedback comes back on `feedback.arm.<id>` into the one controller queue bound to `feedback.arm.*`
+use lapin::{
+    options::{ExchangeDeclareOptions, QueueBindOptions, QueueDeclareOptions},
+    types::FieldTable,
+    Channel, ExchangeKind,
+};
+
+pub const SENSOR_EXCHANGE: &str = "arm_sensor";
+pub const FEEDBACK_EXCHANGE: &str = "arm_feedback";
+//the controller consumes feedback of every arm from this queue
+pub const FEEDBACK_QUEUE: &str = "feedback_to_sensor";
+//actuators announce themselves here through the default exchange
+pub const REGISTRY_QUEUE: &str = "arm_registry";
+//heartbeats both ways, the controller sends on `heartbeat.controller` and arms on `heartbeat.arm.<id>`
+pub const HEARTBEAT_EXCHANGE: &str = "arm_heartbeat";
+pub const CONTROLLER_HEARTBEAT_KEY: &str = "heartbeat.controller";
+pub const CONTROLLER_HEARTBEAT_QUEUE: &str = "controller_heartbeat";
+//emergency stops, `estop.all` or `estop.arm.<id>`, kept apart from the sensor traffic
+pub const ESTOP_EXCHANGE: &str = "arm_estop";
+pub const ESTOP_ALL_KEY: &str = "estop.all";
+//the controller follows every stop so it stops allocating to stopped arms
+pub const CONTROLLER_ESTOP_QUEUE: &str = "controller_estop";
+
+pub fn sensor_routing_key(arm_id: u32) -> String {
+    format!("sensor.arm.{}", arm_id)
+}
+
+pub fn feedback_routing_key(arm_id: u32) -> String {
+    format!("feedback.arm.{}", arm_id)
+}
+
+pub fn sensor_queue(arm_id: u32) -> String {
+    format!("sensor_data.arm.{}", arm_id)
+}
+
+pub fn arm_heartbeat_key(arm_id: u32) -> String {
+    format!("heartbeat.arm.{}", arm_id)
+}
+
+//where an arm receives the controller's heartbeats
+pub fn arm_heartbeat_queue(arm_id: u32) -> String {
+    format!("controller_heartbeat.arm.{}", arm_id)
+}
+
+pub fn estop_routing_key(arm_id: Option<u32>) -> String {
+    match arm_id {
+        Some(arm_id) => format!("estop.arm.{}", arm_id),
+        None => ESTOP_ALL_KEY.to_string(),
+    }
+}
+
+pub fn estop_queue(arm_id: u32) -> String {
+    format!("estop.arm.{}", arm_id)
+}
+
+async fn declare_exchange(channel: &Channel, exchange: &str) -> lapin::Result<()> {
+    channel
+        .exchange_declare(
+            exchange,
+            ExchangeKind::Topic,
+            ExchangeDeclareOptions::default(),
+            FieldTable::default(),
+        )
+        .await
+}
+
+async fn declare_bound_queue(channel: &Channel, queue: &str, exchange: &str, key: &str) -> lapin::Result<()> {
+    channel
+        .queue_declare(queue, QueueDeclareOptions::default(), FieldTable::default())
+        .await?;
+    channel
+        .queue_bind(queue, exchange, key, QueueBindOptions::default(), FieldTable::default())
+        .await
+}
+
+async fn declare_registry_queue(channel: &Channel) -> lapin::Result<()> {
+    channel
+        .queue_declare(REGISTRY_QUEUE, QueueDeclareOptions::default(), FieldTable::default())
+        .await
+        .map(|_| ())
+}
+
+/// Controller side: the exchanges, the registry queue, the shared feedback queue
+/// and the queue every arm's heartbeats arrive on.
+pub async fn declare_controller_topology(channel: &Channel) -> lapin::Result<()> {
+    declare_exchange(channel, SENSOR_EXCHANGE).await?;
+    declare_exchange(channel, FEEDBACK_EXCHANGE).await?;
+    declare_exchange(channel, HEARTBEAT_EXCHANGE).await?;
+    declare_registry_queue(channel).await?;
+    declare_bound_queue(channel, FEEDBACK_QUEUE, FEEDBACK_EXCHANGE, "feedback.arm.*").await?;
+    declare_bound_queue(channel, CONTROLLER_HEARTBEAT_QUEUE, HEARTBEAT_EXCHANGE, "heartbeat.arm.*").await?;
+    declare_exchange(channel, ESTOP_EXCHANGE).await?;
+    declare_bound_queue(channel, CONTROLLER_ESTOP_QUEUE, ESTOP_EXCHANGE, "estop.#").await
+}
+
+/// Actuator side: the exchanges, the registry queue, this arm's sensor queue bound to its routing key
+/// and its queue for the controller's heartbeats.
+pub async fn declare_actuator_topology(channel: &Channel, arm_id: u32) -> lapin::Result<()> {
+    declare_exchange(channel, SENSOR_EXCHANGE).await?;
+    declare_exchange(channel, FEEDBACK_EXCHANGE).await?;
+    declare_exchange(channel, HEARTBEAT_EXCHANGE).await?;
+    declare_registry_queue(channel).await?;
+    declare_bound_queue(channel, &sensor_queue(arm_id), SENSOR_EXCHANGE, &sensor_routing_key(arm_id)).await?;
+    declare_bound_queue(channel, &arm_heartbeat_queue(arm_id), HEARTBEAT_EXCHANGE, CONTROLLER_HEARTBEAT_KEY).await
+}
+
+/// Anyone raising a stop only needs the exchange.
+pub async fn declare_estop_exchange(channel: &Channel) -> lapin::Result<()> {
+    declare_exchange(channel, ESTOP_EXCHANGE).await
+}
+
+/// Actuator side, on its own channel: this arm's stop queue, bound to its own key and to `estop.all`.
+pub async fn declare_estop_topology(channel: &Channel, arm_id: u32) -> lapin::Result<()> {
+    declare_exchange(channel, ESTOP_EXCHANGE).await?;
+    let queue = estop_queue(arm_id);
+    declare_bound_queue(channel, &queue, ESTOP_EXCHANGE, &estop_routing_key(Some(arm_id))).await?;
+    declare_bound_queue(channel, &queue, ESTOP_EXCHANGE, ESTOP_ALL_KEY).await
+}
+
+/// Arms the controller serves from the start, from `ARM_IDS` (comma separated).
+/// Empty when unset, arms then join by registering.
+pub fn arm_ids_from_env() -> Vec<u32> {
+    std::env::var("ARM_IDS")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|id| id.trim().parse().ok())
+        .collect()
+}
@@ -0,0 +1,450 @@
+// actuator safety envelope
+// every shoulder / elbow instruction pair is checked against the joint limits before it reaches the
+// joints: angle range, angular velocity and acceleration from the previous command, and the torque the
+// applied strength puts on each joint; a violation is clamped away or the whole move rejected, and a
+// clamped move is only sent when its own sweep from the previous pose stays clear of the obstacles
+use std::collections::BTreeMap;
+use std::f32::consts::PI;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::data_structure::{ActuatorInstruction, Joint, Limit, LimitAction, SafetyReport, Violation};
+use crate::obstacles::{forward, wrap, Workspace, REST_ANGLES};
+use crate::schedulability::LOOP_PERIOD_US;
+
+impl FromStr for LimitAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clamp" => Ok(LimitAction::Clamp),
+            "reject" => Ok(LimitAction::Reject),
+            _ => Err(format!("unknown limit action {}, expected clamp or reject", s)),
+        }
+    }
+}
+
+/// Limits of one joint, angles in rad, velocity and acceleration unchecked unless given.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct JointLimits {
+    pub min_angle: f32,
+    pub max_angle: f32,
+    // rad/s
+    pub max_velocity: Option<f32>,
+    // rad/s²
+    pub max_acceleration: Option<f32>,
+    // N·m
+    pub max_torque: Option<f32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SafetyConfig {
+    pub action: LimitAction,
+    pub shoulder: JointLimits,
+    pub elbow: JointLimits,
+}
+
+impl Default for SafetyConfig {
+    fn default() -> Self {
+        SafetyConfig {
+            action: LimitAction::Clamp,
+            // the arm works in front of the shoulder, with some room either side
+            shoulder: JointLimits {
+                min_angle: -0.75 * PI,
+                max_angle: 0.75 * PI,
+                max_velocity: None,
+                max_acceleration: None,
+                max_torque: None,
+            },
+            // the elbow never folds flat onto the upper arm
+            elbow: JointLimits {
+                min_angle: -150f32.to_radians(),
+                max_angle: 150f32.to_radians(),
+                max_velocity: None,
+                max_acceleration: None,
+                max_torque: None,
+            },
+        }
+    }
+}
+
+impl SafetyConfig {
+    /// The limits in `SAFETY_FILE`, defaults otherwise, `SAFETY_ACTION=clamp|reject` overrides the action.
+    pub fn from_env() -> Self {
+        let mut config = match std::env::var("SAFETY_FILE") {
+            Ok(path) => {
+                let text = std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("Failed to read {}: {}", path, e));
+                serde_json::from_str(&text).unwrap_or_else(|e| panic!("Invalid safety limits in {}: {}", path, e))
+            }
+            Err(_) => SafetyConfig::default(),
+        };
+        if let Ok(action) = std::env::var("SAFETY_ACTION") {
+            config.action = action.parse().unwrap_or_else(|e| panic!("Invalid SAFETY_ACTION: {}", e));
+        }
+        config
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct JointState {
+    angle: f32,
+    velocity: f32,
+}
+
+pub struct SafetyEnvelope {
+    pub config: SafetyConfig,
+    pub l1: f32,
+    pub l2: f32,
+    shoulder: JointState,
+    elbow: JointState,
+    last_command: Option<u128>,
+    pub stats: SafetyStats,
+}
+
+impl SafetyEnvelope {
+    pub fn new(config: SafetyConfig, l1: f32, l2: f32) -> Self {
+        let rest = |angle| JointState { angle, velocity: 0.0 };
+        SafetyEnvelope {
+            config,
+            l1,
+            l2,
+            shoulder: rest(REST_ANGLES.0),
+            elbow: rest(REST_ANGLES.1),
+            last_command: None,
+            stats: SafetyStats::default(),
+        }
+    }
+
+    /// The angles the joints were last commanded to.
+    pub fn angles(&self) -> (f32, f32) {
+        (self.shoulder.angle, self.elbow.angle)
+    }
+
+    /// Checks one move, clamping the instructions in place. None when the move is within every limit,
+    /// the joints must not be sent a rejected move. A clamped move that would hit an obstacle in
+    /// `workspace` on its way is rejected.
+    pub fn check(
+        &mut self,
+        shoulder: &mut ActuatorInstruction,
+        elbow: &mut ActuatorInstruction,
+        workspace: &Workspace,
+    ) -> Option<SafetyReport> {
+        let now = shoulder.timestamp;
+        let dt = match self.last_command {
+            Some(last) if now > last => (now - last) as f32 / 1_000_000.0,
+            _ => LOOP_PERIOD_US as f32 / 1_000_000.0,
+        };
+        let mut violations = Vec::new();
+        let mut angles = [wrap(shoulder.angle), wrap(elbow.angle)];
+        let mut velocities = [0.0; 2];
+        let joints = [
+            (Joint::Shoulder, &self.config.shoulder, self.shoulder),
+            (Joint::Elbow, &self.config.elbow, self.elbow),
+        ];
+        for (index, (joint, limits, state)) in joints.into_iter().enumerate() {
+            let mut violate = |limit, value: f32, allowed: f32| {
+                violations.push(Violation {
+                    joint,
+                    limit,
+                    value,
+                    allowed,
+                })
+            };
+            let angle = &mut angles[index];
+            if *angle < limits.min_angle || *angle > limits.max_angle {
+                let allowed = angle.clamp(limits.min_angle, limits.max_angle);
+                violate(Limit::Angle, *angle, allowed);
+                *angle = allowed;
+            }
+            let mut velocity = wrap(*angle - state.angle) / dt;
+            if let Some(max) = limits.max_velocity.filter(|max| velocity.abs() > *max) {
+                violate(Limit::Velocity, velocity, max);
+                velocity = velocity.clamp(-max, max);
+            }
+            if let Some(max) = limits.max_acceleration {
+                let acceleration = (velocity - state.velocity) / dt;
+                if acceleration.abs() > max {
+                    violate(Limit::Acceleration, acceleration, max);
+                    velocity = state.velocity + acceleration.clamp(-max, max) * dt;
+                }
+            }
+            // braking within the acceleration limit may carry the joint past the target
+            *angle = wrap(state.angle + velocity * dt).clamp(limits.min_angle, limits.max_angle);
+            velocities[index] = wrap(*angle - state.angle) / dt;
+        }
+
+        // the strength acts at the wrist, cm to m for the lever arms
+        let pose = forward((angles[0], angles[1]), self.l1, self.l2);
+        let levers = [
+            (pose.wrist.0.powi(2) + pose.wrist.1.powi(2)).sqrt() / 100.0,
+            self.l2 / 100.0,
+        ];
+        let mut strength = shoulder.strength;
+        for (index, (joint, limits)) in [(Joint::Shoulder, &self.config.shoulder), (Joint::Elbow, &self.config.elbow)]
+            .into_iter()
+            .enumerate()
+        {
+            let torque = strength * levers[index];
+            if let Some(max) = limits.max_torque.filter(|max| torque > *max) {
+                violations.push(Violation {
+                    joint,
+                    limit: Limit::Torque,
+                    value: torque,
+                    allowed: max,
+                });
+                strength = max / levers[index];
+            }
+        }
+
+        if violations.is_empty() {
+            self.commit(angles, velocities, now);
+            return None;
+        }
+        // the planner cleared the move it planned, not the one clamping made of it
+        let obstacle = match self.config.action {
+            LimitAction::Clamp => workspace.sweep(self.angles(), (angles[0], angles[1]), self.l1, self.l2),
+            LimitAction::Reject => None,
+        };
+        let action = match obstacle {
+            Some(_) => LimitAction::Reject,
+            None => self.config.action,
+        };
+        match action {
+            LimitAction::Reject => {
+                // holding still, the joints come to rest
+                self.shoulder.velocity = 0.0;
+                self.elbow.velocity = 0.0;
+                self.last_command = Some(now);
+            }
+            LimitAction::Clamp => {
+                let pose = forward((angles[0], angles[1]), self.l1, self.l2);
+                for (instruction, (position, angle)) in [shoulder, elbow]
+                    .into_iter()
+                    .zip([(pose.shoulder, angles[0]), (pose.elbow, angles[1])])
+                {
+                    instruction.x = position.0;
+                    instruction.y = position.1;
+                    instruction.angle = angle;
+                    instruction.strength = strength;
+                }
+                self.commit(angles, velocities, now);
+            }
+        }
+        let report = SafetyReport {
+            action,
+            violations,
+            obstacle,
+        };
+        self.stats.record(&report);
+        Some(report)
+    }
+
+    fn commit(&mut self, angles: [f32; 2], velocities: [f32; 2], at: u128) {
+        self.shoulder = JointState {
+            angle: angles[0],
+            velocity: velocities[0],
+        };
+        self.elbow = JointState {
+            angle: angles[1],
+            velocity: velocities[1],
+        };
+        self.last_command = Some(at);
+    }
+
+    pub fn print_summary(&self) {
+        self.stats.print_summary(&format!("  safety envelope ({:?})", self.config.action));
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SafetyStats {
+    pub clamped: u64,
+    pub rejected: u64,
+    // rejected because the clamped move collided
+    pub collisions: u64,
+    violations: BTreeMap<(Joint, Limit), u64>,
+}
+
+impl SafetyStats {
+    pub fn record(&mut self, report: &SafetyReport) {
+        match report.action {
+            LimitAction::Clamp => self.clamped += 1,
+            LimitAction::Reject => self.rejected += 1,
+        }
+        if report.obstacle.is_some() {
+            self.collisions += 1;
+        }
+        for violation in &report.violations {
+            *self.violations.entry((violation.joint, violation.limit)).or_default() += 1;
+        }
+    }
+
+    pub fn print_summary(&self, label: &str) {
+        println!(
+            "> {}: clamped={} rejected={} (into an obstacle {})",
+            label, self.clamped, self.rejected, self.collisions
+        );
+        for ((joint, limit), count) in &self.violations {
+            println!(">     {:?} {:?} limit broken {} time(s)", joint, limit, count);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obstacles::Obstacle;
+
+    const REACH: f32 = 3.0;
+
+    fn envelope(config: SafetyConfig) -> SafetyEnvelope {
+        SafetyEnvelope::new(config, REACH, REACH)
+    }
+
+    fn instructions(angles: (f32, f32), strength: f32) -> (ActuatorInstruction, ActuatorInstruction) {
+        let instruction = |angle| ActuatorInstruction {
+            strength,
+            angle,
+            ..ActuatorInstruction::new(0.0, 0.0, strength, 0)
+        };
+        (instruction(angles.0), instruction(angles.1))
+    }
+
+    fn check(
+        safety: &mut SafetyEnvelope,
+        angles: (f32, f32),
+        strength: f32,
+    ) -> (Option<SafetyReport>, ActuatorInstruction, ActuatorInstruction) {
+        let (mut shoulder, mut elbow) = instructions(angles, strength);
+        let report = safety.check(&mut shoulder, &mut elbow, &Workspace::default());
+        (report, shoulder, elbow)
+    }
+
+    fn assert_close(angles: (f32, f32), expected: (f32, f32)) {
+        assert!(
+            (angles.0 - expected.0).abs() < 1e-5 && (angles.1 - expected.1).abs() < 1e-5,
+            "{:?} != {:?}",
+            angles,
+            expected
+        );
+    }
+
+    fn limits(report: &SafetyReport) -> Vec<(Joint, Limit)> {
+        report.violations.iter().map(|v| (v.joint, v.limit)).collect()
+    }
+
+    #[test]
+    fn move_within_limits_passes_unchanged() {
+        let mut safety = envelope(SafetyConfig::default());
+        let (report, shoulder, elbow) = check(&mut safety, (1.0, 0.5), 5.0);
+        assert_eq!(report, None);
+        assert_close((shoulder.angle, elbow.angle), (1.0, 0.5));
+        assert_close(safety.angles(), (1.0, 0.5));
+    }
+
+    #[test]
+    fn angles_are_clamped_into_range() {
+        let config = SafetyConfig::default();
+        let (shoulder_max, elbow_min) = (config.shoulder.max_angle, config.elbow.min_angle);
+        let mut safety = envelope(config);
+        let (report, shoulder, elbow) = check(&mut safety, (3.0, -3.0), 5.0);
+        let report = report.unwrap();
+        assert_eq!(report.action, LimitAction::Clamp);
+        assert_eq!(
+            limits(&report),
+            [(Joint::Shoulder, Limit::Angle), (Joint::Elbow, Limit::Angle)]
+        );
+        assert_eq!((shoulder.angle, elbow.angle), (shoulder_max, elbow_min));
+        assert_eq!(safety.angles(), (shoulder_max, elbow_min));
+        // the positions follow the clamped angles
+        let pose = forward((shoulder_max, elbow_min), REACH, REACH);
+        assert_eq!((elbow.x, elbow.y), pose.elbow);
+    }
+
+    #[test]
+    fn velocity_is_clamped_to_the_limit() {
+        let mut config = SafetyConfig::default();
+        config.shoulder.max_velocity = Some(1.0);
+        let mut safety = envelope(config);
+        // half a radian in one 5ms period is 100 rad/s
+        let (report, shoulder, _) = check(&mut safety, (REST_ANGLES.0 + 0.5, REST_ANGLES.1), 5.0);
+        let report = report.unwrap();
+        assert_eq!(limits(&report), [(Joint::Shoulder, Limit::Velocity)]);
+        assert_eq!(report.violations[0].allowed, 1.0);
+        let step = LOOP_PERIOD_US as f32 / 1_000_000.0;
+        assert!((shoulder.angle - (REST_ANGLES.0 + step)).abs() < 1e-5);
+        assert_eq!(safety.angles().0, shoulder.angle);
+    }
+
+    #[test]
+    fn acceleration_is_clamped_to_the_limit() {
+        let mut config = SafetyConfig::default();
+        config.elbow.max_acceleration = Some(100.0);
+        let mut safety = envelope(config);
+        let (report, _, elbow) = check(&mut safety, (REST_ANGLES.0, 0.5), 5.0);
+        let report = report.unwrap();
+        assert_eq!(limits(&report), [(Joint::Elbow, Limit::Acceleration)]);
+        // from rest, 100 rad/s² for 5ms reaches 0.5 rad/s and 2.5 mrad
+        let step = LOOP_PERIOD_US as f32 / 1_000_000.0;
+        assert!((elbow.angle - 100.0 * step * step).abs() < 1e-5);
+    }
+
+    #[test]
+    fn torque_lowers_the_strength() {
+        let mut config = SafetyConfig::default();
+        config.elbow.max_torque = Some(0.3);
+        let mut safety = envelope(config);
+        // the forearm is a 3cm lever, 20N puts 0.6 N·m on the elbow
+        let (report, shoulder, elbow) = check(&mut safety, REST_ANGLES, 20.0);
+        let report = report.unwrap();
+        assert_eq!(limits(&report), [(Joint::Elbow, Limit::Torque)]);
+        assert!((shoulder.strength - 10.0).abs() < 1e-4);
+        assert_eq!(elbow.strength, shoulder.strength);
+        assert_close(safety.angles(), REST_ANGLES);
+    }
+
+    #[test]
+    fn rejected_move_leaves_the_joints_where_they_were() {
+        let config = SafetyConfig {
+            action: LimitAction::Reject,
+            ..SafetyConfig::default()
+        };
+        let mut safety = envelope(config);
+        check(&mut safety, (1.0, 0.5), 5.0);
+        let before = safety.angles();
+        let (report, shoulder, _) = check(&mut safety, (3.0, 0.5), 5.0);
+        let report = report.unwrap();
+        assert_eq!(report.action, LimitAction::Reject);
+        assert_eq!(limits(&report), [(Joint::Shoulder, Limit::Angle)]);
+        // the instruction is not corrected, it must not be sent
+        assert_eq!(shoulder.angle, 3.0);
+        assert_eq!(safety.angles(), before);
+        assert_eq!(safety.stats.rejected, 1);
+    }
+
+    #[test]
+    fn clamped_move_into_an_obstacle_is_rejected() {
+        // around the wrist once the shoulder is clamped to 135°, clear of the arm at rest
+        let workspace = Workspace {
+            obstacles: vec![Obstacle::Box {
+                min: (-5.0, 3.5),
+                max: (-3.5, 5.0),
+            }],
+            ..Workspace::default()
+        };
+        let mut safety = envelope(SafetyConfig::default());
+        let (mut shoulder, mut elbow) = instructions((3.0, 0.0), 5.0);
+        let report = safety.check(&mut shoulder, &mut elbow, &workspace).unwrap();
+        assert_eq!(report.action, LimitAction::Reject);
+        assert_eq!(report.obstacle, Some(0));
+        assert_eq!(safety.angles(), REST_ANGLES);
+        assert_eq!(safety.stats.collisions, 1);
+
+        // the same clamp with nothing in the way goes through
+        let (report, _, _) = check(&mut safety, (3.0, 0.0), 5.0);
+        assert_eq!(report.unwrap().obstacle, None);
+        assert_ne!(safety.angles(), REST_ANGLES);
+    }
+}
@@ -0,0 +1,264 @@
+// offline schedulability analysis over recorded latency logs
+// takes a task set (period, deadline, priority) and the observed worst case
+// execution times from a csv like merged_performance.csv, then runs
+// utilization bound checks and response time analysis on it
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+
+use crate::latency_log::read_latency_csv;
+
+//the controller loop runs every 5ms
+pub const LOOP_PERIOD_US: u128 = 5_000;
+
+//one periodic task in the set, lower priority number = higher priority
+#[derive(Debug, Clone)]
+pub struct TaskSpec {
+    pub name: String,
+    pub period_us: u128,
+    pub deadline_us: u128,
+    pub priority: u32,
+}
+
+impl TaskSpec {
+    pub fn new(name: &str, period_us: u128, deadline_us: u128, priority: u32) -> Self {
+        TaskSpec {
+            name: name.to_string(),
+            period_us,
+            deadline_us,
+            priority,
+        }
+    }
+}
+
+/// Default task set of the controller, every stage is released once per 5 ms cycle.
+/// Priorities follow the order the stages run in the loop.
+pub fn default_task_set() -> Vec<TaskSpec> {
+    vec![
+        TaskSpec::new("generate_sensor_data", LOOP_PERIOD_US, LOOP_PERIOD_US, 1),
+        TaskSpec::new("process_sensor_data", LOOP_PERIOD_US, LOOP_PERIOD_US, 2),
+        TaskSpec::new("publish_data", LOOP_PERIOD_US, LOOP_PERIOD_US, 3),
+        TaskSpec::new("consume_feedback", LOOP_PERIOD_US, LOOP_PERIOD_US, 4),
+    ]
+}
+
+/// Reads a task set from a csv with the columns `task,period_us,deadline_us,priority`.
+pub fn load_task_set<P: AsRef<Path>>(path: P) -> Result<Vec<TaskSpec>, Box<dyn Error>> {
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_path(path)?;
+    let mut tasks = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        if record.len() < 4 {
+            return Err(format!("task set row has {} columns, expected 4", record.len()).into());
+        }
+        tasks.push(TaskSpec {
+            name: record[0].to_string(),
+            period_us: record[1].parse()?,
+            deadline_us: record[2].parse()?,
+            priority: record[3].parse()?,
+        });
+    }
+    Ok(tasks)
+}
+
+/// Observed worst case execution time per task from a latency log,
+/// any layout `read_latency_csv` understands works here.
+/// Records whose load profile does not match `load` (when given) are skipped.
+pub fn load_observed_wcets<P: AsRef<Path>>(
+    path: P,
+    load: Option<&str>,
+) -> Result<HashMap<String, u128>, Box<dyn Error>> {
+    let mut wcets: HashMap<String, u128> = HashMap::new();
+    for record in read_latency_csv(path)? {
+        if load.is_some_and(|load| record.load != load) {
+            continue;
+        }
+        let wcet = wcets.entry(record.stage).or_insert(0);
+        *wcet = (*wcet).max(record.latency_us);
+    }
+    Ok(wcets)
+}
+
+//result of the analysis for one task
+#[derive(Debug, Clone)]
+pub struct TaskResult {
+    pub name: String,
+    pub wcet_us: u128,
+    pub period_us: u128,
+    pub deadline_us: u128,
+    pub priority: u32,
+    // when past the deadline this is the first iteration that crossed it, not a fixed point
+    pub response_time_us: u128,
+}
+
+impl TaskResult {
+    pub fn schedulable(&self) -> bool {
+        self.response_time_us <= self.deadline_us
+    }
+
+    /// Slack between the worst case response time and the deadline, negative when missed.
+    pub fn margin_us(&self) -> i128 {
+        self.deadline_us as i128 - self.response_time_us as i128
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SchedulabilityReport {
+    pub tasks: Vec<TaskResult>,
+    pub utilization: f64,
+    pub liu_layland_bound: f64,
+    // tasks in the set with no samples in the log, analysed with a wcet of 0
+    pub missing: Vec<String>,
+}
+
+impl SchedulabilityReport {
+    pub fn schedulable(&self) -> bool {
+        !self.overloaded() && self.tasks.iter().all(|t| t.schedulable())
+    }
+
+    /// Necessary test, a processor demand above 1 can never be met.
+    pub fn overloaded(&self) -> bool {
+        self.utilization > 1.0
+    }
+
+    /// Sufficient (not necessary) test, passing it means RTA will pass too.
+    pub fn passes_utilization_bound(&self) -> bool {
+        self.utilization <= self.liu_layland_bound
+    }
+
+    /// Smallest deadline margin over all tasks.
+    pub fn min_margin_us(&self) -> i128 {
+        self.tasks.iter().map(|t| t.margin_us()).min().unwrap_or(0)
+    }
+}
+
+/// Liu & Layland bound for rate monotonic scheduling, n(2^(1/n) - 1).
+pub fn liu_layland_bound(n: usize) -> f64 {
+    if n == 0 {
+        return 1.0;
+    }
+    let n = n as f64;
+    n * (2f64.powf(1.0 / n) - 1.0)
+}
+
+pub fn utilization(tasks: &[TaskSpec], wcets: &HashMap<String, u128>) -> f64 {
+    tasks
+        .iter()
+        .map(|t| *wcets.get(&t.name).unwrap_or(&0) as f64 / t.period_us as f64)
+        .sum()
+}
+
+/// Fixed priority response time analysis:
+/// R = C_i + sum over higher priority j of ceil(R / T_j) * C_j, iterated to a fixed point.
+/// Stops early and returns the first R past the deadline, so the caller can still see by how much it missed.
+pub fn response_time(task: &TaskSpec, tasks: &[TaskSpec], wcets: &HashMap<String, u128>) -> u128 {
+    let wcet = |t: &TaskSpec| *wcets.get(&t.name).unwrap_or(&0);
+    let higher: Vec<&TaskSpec> = tasks
+        .iter()
+        .filter(|t| t.priority < task.priority && t.name != task.name)
+        .collect();
+
+    let own = wcet(task);
+    let mut r = own;
+    loop {
+        let interference: u128 = higher
+            .iter()
+            .map(|t| r.div_ceil(t.period_us.max(1)) * wcet(t))
+            .sum();
+        let next = own + interference;
+        if next > task.deadline_us || next == r {
+            return next;
+        }
+        r = next;
+    }
+}
+
+pub fn analyse(tasks: &[TaskSpec], wcets: &HashMap<String, u128>) -> SchedulabilityReport {
+    let mut sorted = tasks.to_vec();
+    sorted.sort_by_key(|t| t.priority);
+
+    let results = sorted
+        .iter()
+        .map(|t| TaskResult {
+            name: t.name.clone(),
+            wcet_us: *wcets.get(&t.name).unwrap_or(&0),
+            period_us: t.period_us,
+            deadline_us: t.deadline_us,
+            priority: t.priority,
+            response_time_us: response_time(t, &sorted, wcets),
+        })
+        .collect();
+
+    SchedulabilityReport {
+        tasks: results,
+        utilization: utilization(&sorted, wcets),
+        liu_layland_bound: liu_layland_bound(sorted.len()),
+        missing: sorted
+            .iter()
+            .filter(|t| !wcets.contains_key(&t.name))
+            .map(|t| t.name.clone())
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wcets(tasks: &[(&str, u128)]) -> HashMap<String, u128> {
+        tasks.iter().map(|(name, wcet)| (name.to_string(), *wcet)).collect()
+    }
+
+    fn response_times(report: &SchedulabilityReport) -> Vec<u128> {
+        report.tasks.iter().map(|t| t.response_time_us).collect()
+    }
+
+    #[test]
+    fn textbook_set_response_times() {
+        // Burns & Wellings: rate monotonic, deadlines equal to periods
+        let tasks = [
+            TaskSpec::new("a", 7, 7, 1),
+            TaskSpec::new("b", 12, 12, 2),
+            TaskSpec::new("c", 20, 20, 3),
+        ];
+        let report = analyse(&tasks, &wcets(&[("a", 3), ("b", 3), ("c", 5)]));
+        assert_eq!(response_times(&report), [3, 6, 20]);
+        assert!(report.schedulable());
+        assert_eq!(report.min_margin_us(), 0);
+        // U = 0.93 is over the bound for 3 tasks, which is only sufficient
+        assert!(!report.passes_utilization_bound());
+        assert!((report.liu_layland_bound - 0.7798).abs() < 1e-4);
+    }
+
+    #[test]
+    fn bound_passes_but_response_time_analysis_fails() {
+        // the bound assumes deadlines equal to periods, b's is shorter and a runs first
+        let tasks = [TaskSpec::new("a", 10, 10, 1), TaskSpec::new("b", 4, 1, 2)];
+        let report = analyse(&tasks, &wcets(&[("a", 1), ("b", 1)]));
+        assert!((report.utilization - 0.35).abs() < 1e-9);
+        assert!(report.passes_utilization_bound());
+        assert!(!report.schedulable());
+        assert_eq!(response_times(&report), [1, 2]);
+        assert_eq!(report.min_margin_us(), -1);
+    }
+
+    #[test]
+    fn overloaded_set_is_unschedulable() {
+        let tasks = [TaskSpec::new("a", 5, 5, 1), TaskSpec::new("b", 5, 5, 2)];
+        let report = analyse(&tasks, &wcets(&[("a", 3), ("b", 3)]));
+        assert!(report.overloaded());
+        assert!(!report.schedulable());
+        // stops at the first iteration past the deadline
+        assert_eq!(response_times(&report), [3, 6]);
+    }
+
+    #[test]
+    fn tasks_without_samples_are_reported_missing() {
+        let tasks = [TaskSpec::new("a", 5, 5, 1), TaskSpec::new("b", 5, 5, 2)];
+        let report = analyse(&tasks, &wcets(&[("a", 1)]));
+        assert_eq!(report.missing, ["b"]);
+        // nothing of its own to run, so nothing to be interfered with
+        assert_eq!(response_times(&report), [1, 0]);
+        assert_eq!(liu_layland_bound(1), 1.0);
+    }
+}
@@ -0,0 +1,294 @@
+// scripted runs
+// a JSON scenario file lists object drops, injected anomalies, load phases and the outcomes the run
+// must reach, the simulator replays it tick by tick and the controller checks the expectations at the end
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::catching::CatchResult;
+use crate::schedulability::LOOP_PERIOD_US;
+use crate::sensor_model::{FieldModel, SensorField};
+use crate::simulation::AnomalyKind;
+use crate::world::{Landing, WorldConfig};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScenarioScript {
+    #[serde(default)]
+    pub name: String,
+    // wins over SIM_SEED
+    pub seed: Option<u64>,
+    // run length, the controller's default otherwise
+    pub cycles: Option<u64>,
+    #[serde(default)]
+    pub world: WorldConfig,
+    #[serde(default)]
+    pub drops: Vec<ScriptedDrop>,
+    #[serde(default)]
+    pub injections: Vec<Injection>,
+    #[serde(default)]
+    pub load: Vec<LoadPhase>,
+    // sensor models for the whole run
+    #[serde(default)]
+    pub sensors: Vec<FieldModel>,
+    #[serde(default)]
+    pub expect: Expectations,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Expected {
+    Caught,
+    Missed,
+}
+
+/// One object released into the world.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScriptedDrop {
+    pub at_ms: u64,
+    pub x: f32,
+    pub y: f32,
+    // downward, m/s
+    pub velocity: f32,
+    pub mass: f32,
+    #[serde(default = "default_size")]
+    pub size: f32,
+    // the world's drop height otherwise
+    pub height: Option<f32>,
+    pub expect: Option<Expected>,
+}
+
+fn default_size() -> f32 {
+    4.5
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Fault {
+    // the observed object replaced like the random anomalies of the same name
+    Hand,
+    Static,
+    Heavy,
+    Oversized,
+    OffCourse,
+    // no frame at all
+    Dropout,
+    // one property reads this value
+    Spike { field: SensorField, value: f32 },
+}
+
+impl Fault {
+    pub fn anomaly(&self) -> Option<AnomalyKind> {
+        match self {
+            Fault::Hand => Some(AnomalyKind::Hand),
+            Fault::Static => Some(AnomalyKind::Static),
+            Fault::Heavy => Some(AnomalyKind::Heavy),
+            Fault::Oversized => Some(AnomalyKind::Oversized),
+            Fault::OffCourse => Some(AnomalyKind::OffCourse),
+            Fault::Dropout | Fault::Spike { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Injection {
+    pub at_ms: u64,
+    // one tick unless given
+    #[serde(default)]
+    pub duration_ms: u64,
+    #[serde(flatten)]
+    pub fault: Fault,
+}
+
+/// Extra work in the generator, to see how the loop copes under load.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LoadPhase {
+    pub from_ms: u64,
+    pub to_ms: u64,
+    // busy time added to every cycle in the phase
+    pub busy_us: u64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Expectations {
+    pub min_caught: Option<u64>,
+    pub max_missed: Option<u64>,
+    pub min_detected_anomalies: Option<u64>,
+    pub max_detected_anomalies: Option<u64>,
+}
+
+/// Tick a script time falls on, tick 1 is the first frame.
+pub fn tick_at(ms: u64) -> u64 {
+    (ms as u128 * 1000 / LOOP_PERIOD_US) as u64 + 1
+}
+
+impl ScenarioScript {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let mut script: ScenarioScript = serde_json::from_str(&text).map_err(|e| format!("{}: {}", path, e))?;
+        // object ids are handed out in release order, which is what `expect` on a drop refers to
+        script.drops.sort_by_key(|drop| drop.at_ms);
+        if script.name.is_empty() {
+            script.name = path.to_string();
+        }
+        Ok(script)
+    }
+
+    /// The file named by `SCENARIO_FILE`, if any.
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var("SCENARIO_FILE").ok()?;
+        Some(Self::load(&path).unwrap_or_else(|e| panic!("Invalid scenario file {}", e)))
+    }
+
+    pub fn drops_at(&self, tick: u64) -> impl Iterator<Item = &ScriptedDrop> {
+        self.drops.iter().filter(move |drop| tick_at(drop.at_ms) == tick)
+    }
+
+    pub fn faults_at(&self, tick: u64) -> impl Iterator<Item = Fault> + '_ {
+        self.injections
+            .iter()
+            .filter(move |injection| {
+                let from = tick_at(injection.at_ms);
+                let to = tick_at(injection.at_ms + injection.duration_ms).max(from + 1);
+                (from..to).contains(&tick)
+            })
+            .map(|injection| injection.fault)
+    }
+
+    pub fn load_at(&self, tick: u64) -> Duration {
+        let busy: u64 = self
+            .load
+            .iter()
+            .filter(|phase| (tick_at(phase.from_ms)..tick_at(phase.to_ms)).contains(&tick))
+            .map(|phase| phase.busy_us)
+            .sum();
+        Duration::from_micros(busy)
+    }
+
+    /// Failed expectations, empty when the run passed.
+    /// The world says which objects landed, the catch evaluator whether they were caught;
+    /// a landed object the evaluator never saw sent to an arm counts as missed.
+    pub fn check(
+        &self,
+        landings: &[Landing],
+        catches: &BTreeMap<u64, CatchResult>,
+        detected_anomalies: u64,
+    ) -> Vec<String> {
+        let verdict = |object_id: u64| catches.get(&object_id).copied().unwrap_or(CatchResult::NoAttempt);
+        let mut failures = Vec::new();
+        for (index, drop) in self.drops.iter().enumerate() {
+            let Some(expected) = drop.expect else {
+                continue;
+            };
+            let object_id = index as u64 + 1;
+            if !landings.iter().any(|landing| landing.object_id == object_id) {
+                failures.push(format!("drop {} at {}ms never landed", index, drop.at_ms));
+                continue;
+            }
+            let result = verdict(object_id);
+            let actual = if result == CatchResult::Caught {
+                Expected::Caught
+            } else {
+                Expected::Missed
+            };
+            if actual != expected {
+                failures.push(format!(
+                    "drop {} at {}ms: expected {:?}, was {:?} ({:?})",
+                    index, drop.at_ms, expected, actual, result
+                ));
+            }
+        }
+        let caught = landings
+            .iter()
+            .filter(|landing| verdict(landing.object_id) == CatchResult::Caught)
+            .count() as u64;
+        let missed = landings.len() as u64 - caught;
+        let expect = &self.expect;
+        if let Some(min) = expect.min_caught.filter(|min| caught < *min) {
+            failures.push(format!("caught {}, expected at least {}", caught, min));
+        }
+        if let Some(max) = expect.max_missed.filter(|max| missed > *max) {
+            failures.push(format!("missed {}, expected at most {}", missed, max));
+        }
+        if let Some(min) = expect.min_detected_anomalies.filter(|min| detected_anomalies < *min) {
+            failures.push(format!("detected {} anomalies, expected at least {}", detected_anomalies, min));
+        }
+        if let Some(max) = expect.max_detected_anomalies.filter(|max| detected_anomalies > *max) {
+            failures.push(format!("detected {} anomalies, expected at most {}", detected_anomalies, max));
+        }
+        failures
+    }
+}
+
+/// Burns CPU for `duration`, a sleep would hand the time back to the runtime.
+pub fn busy_wait(duration: Duration) {
+    let until = Instant::now() + duration;
+    while Instant::now() < until {
+        std::hint::spin_loop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn script(json: &str) -> ScenarioScript {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn landing(object_id: u64) -> Landing {
+        Landing {
+            object_id,
+            released_tick: 1,
+            landed_tick: 40,
+            landed_at: (1.0, 2.0),
+        }
+    }
+
+    #[test]
+    fn script_times_fall_on_loop_ticks() {
+        assert_eq!(tick_at(0), 1);
+        assert_eq!(tick_at(4), 1);
+        assert_eq!(tick_at(5), 2);
+        assert_eq!(tick_at(9), 2);
+        assert_eq!(tick_at(10), 3);
+        assert_eq!(tick_at(1000), 201);
+    }
+
+    #[test]
+    fn expected_catch_without_a_verdict_is_missed() {
+        let script = script(
+            r#"{"drops": [
+                {"at_ms": 0, "x": 1.0, "y": 2.0, "velocity": 10.0, "mass": 1.0, "expect": "caught"},
+                {"at_ms": 50, "x": 1.0, "y": 2.0, "velocity": 10.0, "mass": 1.0, "expect": "missed"}
+            ]}"#,
+        );
+        let landings = [landing(1), landing(2)];
+        // the evaluator never saw either object sent to an arm
+        let failures = script.check(&landings, &BTreeMap::new(), 0);
+        assert_eq!(failures, ["drop 0 at 0ms: expected Caught, was Missed (NoAttempt)"]);
+
+        let catches = BTreeMap::from([(1, CatchResult::Caught), (2, CatchResult::Late)]);
+        assert!(script.check(&landings, &catches, 0).is_empty());
+    }
+
+    #[test]
+    fn drop_that_never_landed_fails() {
+        let script = script(r#"{"drops": [{"at_ms": 20, "x": 1.0, "y": 2.0, "velocity": 10.0, "mass": 1.0, "expect": "missed"}]}"#);
+        assert_eq!(script.check(&[], &BTreeMap::new(), 0), ["drop 0 at 20ms never landed"]);
+    }
+
+    #[test]
+    fn run_wide_expectations() {
+        let script = script(r#"{"expect": {"min_caught": 2, "max_missed": 0, "max_detected_anomalies": 1}}"#);
+        let catches = BTreeMap::from([(1, CatchResult::Caught), (2, CatchResult::OutOfTolerance)]);
+        assert_eq!(
+            script.check(&[landing(1), landing(2)], &catches, 3),
+            [
+                "caught 1, expected at least 2",
+                "missed 1, expected at most 0",
+                "detected 3 anomalies, expected at most 1",
+            ]
+        );
+    }
+}
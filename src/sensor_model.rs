@@ -0,0 +1,313 @@
+// sensor models
+// the simulator produces the true object and arm state, these models turn it into what a real sensor
+// would report (noise, drifting bias, quantization, stuck readings, dropouts, spikes, late samples),
+// and the evaluation compares the filtered frames and the anomaly detector against the injected faults
+use std::collections::{BTreeMap, VecDeque};
+
+use serde::Deserialize;
+
+use crate::data_structure::SensorArmData;
+use crate::schedulability::LOOP_PERIOD_US;
+use crate::script::tick_at;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SensorField {
+    Velocity,
+    Mass,
+    Size,
+    X,
+    Y,
+    Height,
+    WristX,
+    WristY,
+    ShoulderX,
+    ShoulderY,
+    ElbowX,
+    ElbowY,
+    ArmVelocity,
+}
+
+impl SensorField {
+    pub fn get(&self, frame: &SensorArmData) -> f32 {
+        match self {
+            SensorField::Velocity => frame.object_data.object_velocity,
+            SensorField::Mass => frame.object_data.object_mass,
+            SensorField::Size => frame.object_data.object_size,
+            SensorField::X => frame.object_data.object_x,
+            SensorField::Y => frame.object_data.object_y,
+            SensorField::Height => frame.object_data.object_height,
+            SensorField::WristX => frame.wrist.wrist_x,
+            SensorField::WristY => frame.wrist.wrist_y,
+            SensorField::ShoulderX => frame.joints.shoulder_x,
+            SensorField::ShoulderY => frame.joints.shoulder_y,
+            SensorField::ElbowX => frame.elbow.elbow_x,
+            SensorField::ElbowY => frame.elbow.elbow_y,
+            SensorField::ArmVelocity => frame.arm_velocity,
+        }
+    }
+
+    /// The arm's fields, as opposed to the object's.
+    pub fn is_arm(&self) -> bool {
+        !matches!(
+            self,
+            SensorField::Velocity | SensorField::Mass | SensorField::Size | SensorField::X | SensorField::Y | SensorField::Height
+        )
+    }
+
+    pub fn set(&self, frame: &mut SensorArmData, value: f32) {
+        let slot = match self {
+            SensorField::Velocity => &mut frame.object_data.object_velocity,
+            SensorField::Mass => &mut frame.object_data.object_mass,
+            SensorField::Size => &mut frame.object_data.object_size,
+            SensorField::X => &mut frame.object_data.object_x,
+            SensorField::Y => &mut frame.object_data.object_y,
+            SensorField::Height => &mut frame.object_data.object_height,
+            SensorField::WristX => &mut frame.wrist.wrist_x,
+            SensorField::WristY => &mut frame.wrist.wrist_y,
+            SensorField::ShoulderX => &mut frame.joints.shoulder_x,
+            SensorField::ShoulderY => &mut frame.joints.shoulder_y,
+            SensorField::ElbowX => &mut frame.elbow.elbow_x,
+            SensorField::ElbowY => &mut frame.elbow.elbow_y,
+            SensorField::ArmVelocity => &mut frame.arm_velocity,
+        };
+        *slot = value;
+    }
+}
+
+/// A reading frozen for a while, at `value` or at whatever it read when it got stuck.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct StuckAt {
+    pub at_ms: u64,
+    // stuck for the rest of the run unless given
+    pub duration_ms: Option<u64>,
+    pub value: Option<f32>,
+}
+
+/// How one field is sensed, every part is off unless given.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct FieldModel {
+    pub field: SensorField,
+    // gaussian noise, standard deviation
+    #[serde(default)]
+    pub noise: f32,
+    // bias growing by this much per second
+    #[serde(default)]
+    pub drift: f32,
+    // resolution of the reading, 0 for none
+    #[serde(default)]
+    pub quantum: f32,
+    pub stuck: Option<StuckAt>,
+    // probability a sample is lost, the previous reading is repeated
+    #[serde(default)]
+    pub dropout_rate: f32,
+    // probability of a spike, and its size either way
+    #[serde(default)]
+    pub spike_rate: f32,
+    #[serde(default)]
+    pub spike: f32,
+    // readings arrive this late
+    #[serde(default)]
+    pub delay_ms: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FieldStats {
+    pub samples: u64,
+    pub spikes: u64,
+    pub stuck: u64,
+    pub dropouts: u64,
+    // |reported - true| summed, for the mean error of the raw readings
+    pub abs_error: f64,
+}
+
+struct FieldState {
+    model: FieldModel,
+    field: SensorField,
+    bias: f32,
+    history: VecDeque<f32>,
+    last: Option<f32>,
+    stuck_value: Option<f32>,
+    stats: FieldStats,
+}
+
+/// The models of all configured fields, with their own RNG stream.
+pub struct SensorModels {
+    rng: fastrand::Rng,
+    fields: Vec<FieldState>,
+}
+
+/// Standard normal sample, Box-Muller.
+pub(crate) fn gaussian(rng: &mut fastrand::Rng) -> f32 {
+    let u1 = rng.f32().max(f32::MIN_POSITIVE);
+    let u2 = rng.f32();
+    (-2.0 * u1.ln()).sqrt() * (std::f32::consts::TAU * u2).cos()
+}
+
+impl SensorModels {
+    pub fn new(models: Vec<FieldModel>, seed: u64) -> Self {
+        let fields = models
+            .into_iter()
+            .map(|model| FieldState {
+                field: model.field,
+                model,
+                bias: 0.0,
+                history: VecDeque::new(),
+                last: None,
+                stuck_value: None,
+                stats: FieldStats::default(),
+            })
+            .collect();
+        SensorModels {
+            rng: fastrand::Rng::with_seed(seed),
+            fields,
+        }
+    }
+
+    /// Models from the JSON array in `SENSOR_MODEL_FILE`, none otherwise.
+    pub fn from_env() -> Vec<FieldModel> {
+        let Ok(path) = std::env::var("SENSOR_MODEL_FILE") else {
+            return Vec::new();
+        };
+        let text = std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("Failed to read {}: {}", path, e));
+        serde_json::from_str(&text).unwrap_or_else(|e| panic!("Invalid sensor models in {}: {}", path, e))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    pub fn fields(&self) -> Vec<SensorField> {
+        self.fields.iter().map(|state| state.field).collect()
+    }
+
+    /// Turns the true frame into the reported one, true when a fault (spike, stuck, dropout) was injected.
+    pub fn sense(&mut self, tick: u64, frame: &mut SensorArmData) -> bool {
+        self.sense_fields(tick, frame, |_| true)
+    }
+
+    /// `sense` for the fields `which` picks only, each field is sensed at most once per tick.
+    pub fn sense_fields(&mut self, tick: u64, frame: &mut SensorArmData, which: impl Fn(&SensorField) -> bool) -> bool {
+        let dt = LOOP_PERIOD_US as f32 / 1_000_000.0;
+        let rng = &mut self.rng;
+        let mut faulted = false;
+        for state in self.fields.iter_mut().filter(|state| which(&state.field)) {
+            let model = &state.model;
+            let truth = state.field.get(frame);
+            state.stats.samples += 1;
+
+            // late readings, the true value of delay_ms ago
+            let delay_ticks = (model.delay_ms as u128 * 1000 / LOOP_PERIOD_US) as usize;
+            state.history.push_back(truth);
+            while state.history.len() > delay_ticks + 1 {
+                state.history.pop_front();
+            }
+            let mut value = state.history[0];
+
+            state.bias += model.drift * dt;
+            value += state.bias;
+            if model.noise > 0.0 {
+                value += model.noise * gaussian(rng);
+            }
+            if model.spike_rate > 0.0 && rng.f32() < model.spike_rate {
+                value += if rng.bool() { model.spike } else { -model.spike };
+                state.stats.spikes += 1;
+                faulted = true;
+            }
+            let stuck = model.stuck.as_ref().filter(|stuck| {
+                let from = tick_at(stuck.at_ms);
+                tick >= from && stuck.duration_ms.is_none_or(|duration| tick < tick_at(stuck.at_ms + duration))
+            });
+            match stuck {
+                Some(stuck) => {
+                    let held = *state
+                        .stuck_value
+                        .get_or_insert(stuck.value.or(state.last).unwrap_or(value));
+                    value = held;
+                    state.stats.stuck += 1;
+                    faulted = true;
+                }
+                None => state.stuck_value = None,
+            }
+            if model.dropout_rate > 0.0 && rng.f32() < model.dropout_rate {
+                if let Some(last) = state.last {
+                    value = last;
+                    state.stats.dropouts += 1;
+                    faulted = true;
+                }
+            }
+            if model.quantum > 0.0 {
+                value = (value / model.quantum).round() * model.quantum;
+            }
+            state.last = Some(value);
+            state.stats.abs_error += (value - truth).abs() as f64;
+            state.field.set(frame, value);
+        }
+        faulted
+    }
+
+    pub fn print_summary(&self) {
+        for state in &self.fields {
+            let stats = &state.stats;
+            println!(
+                "> Sensor {:?}: samples={} spikes={} stuck={} dropouts={} mean_error={:.4}",
+                state.field,
+                stats.samples,
+                stats.spikes,
+                stats.stuck,
+                stats.dropouts,
+                stats.abs_error / stats.samples.max(1) as f64
+            );
+        }
+    }
+}
+
+/// How the filters and the anomaly detector did against the faults that were actually injected.
+#[derive(Debug, Default)]
+pub struct FaultEvaluation {
+    // faulted frames flagged as anomalous, and not
+    pub detected: u64,
+    pub missed: u64,
+    // clean frames flagged anyway
+    pub false_alarms: u64,
+    pub clean: u64,
+    // |filtered - true| summed per field, and the frames counted
+    filtered_error: BTreeMap<SensorField, f64>,
+    frames: u64,
+}
+
+impl FaultEvaluation {
+    pub fn record(
+        &mut self,
+        fields: &[SensorField],
+        truth: &SensorArmData,
+        filtered: &SensorArmData,
+        faulted: bool,
+        flagged: bool,
+    ) {
+        match (faulted, flagged) {
+            (true, true) => self.detected += 1,
+            (true, false) => self.missed += 1,
+            (false, true) => self.false_alarms += 1,
+            (false, false) => self.clean += 1,
+        }
+        self.frames += 1;
+        for field in fields {
+            *self.filtered_error.entry(*field).or_default() += (field.get(filtered) - field.get(truth)).abs() as f64;
+        }
+    }
+
+    pub fn print_summary(&self) {
+        println!(
+            "> Fault detection: detected={} missed={} false_alarms={} clean={}",
+            self.detected, self.missed, self.false_alarms, self.clean
+        );
+        for (field, error) in &self.filtered_error {
+            println!(
+                "> Filtered {:?}: mean_error={:.4}",
+                field,
+                error / self.frames.max(1) as f64
+            );
+        }
+    }
+}
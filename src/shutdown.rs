@@ -0,0 +1,71 @@
+// process wide shutdown, triggered by Ctrl-C / SIGTERM or when the run is over
+// unlike Notify::notify_waiters the state sticks, a task that starts waiting late still sees it
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+//how long the controller keeps consuming feedback after the last frame, overridden by SHUTDOWN_GRACE_MS
+pub const DEFAULT_SHUTDOWN_GRACE: Duration = Duration::from_millis(250);
+
+#[derive(Clone)]
+pub struct Shutdown {
+    tx: Arc<watch::Sender<bool>>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Shutdown {
+            tx: Arc::new(watch::channel(false).0),
+        }
+    }
+
+    pub fn trigger(&self) {
+        self.tx.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.tx.borrow()
+    }
+
+    /// Resolves once triggered, immediately if it already was.
+    pub async fn wait(&self) {
+        let mut rx = self.tx.subscribe();
+        // the sender lives in self, so this only returns once the value is true
+        let _ = rx.wait_for(|triggered| *triggered).await;
+    }
+
+    /// Triggers on the first Ctrl-C or SIGTERM.
+    pub fn trigger_on_signal(&self) {
+        let shutdown = self.clone();
+        tokio::spawn(async move {
+            let signal = wait_for_signal().await;
+            println!("> {} received, shutting down...", signal);
+            shutdown.trigger();
+        });
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Waits for Ctrl-C or SIGTERM and returns which one arrived.
+pub async fn wait_for_signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate()).expect("Failed to install SIGTERM handler");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => "Ctrl-C",
+            _ = terminate.recv() => "SIGTERM",
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await.expect("Failed to install Ctrl-C handler");
+        "Ctrl-C"
+    }
+}
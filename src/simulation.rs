@@ -0,0 +1,537 @@
+// seeded sensor simulation
+// every simulated value comes from one RNG owned by the simulator, so the same seed and scenario
+// give the same frames bit for bit, only the wall clock timestamps differ between runs
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::catching::{CatchOutcome, CatchResult};
+use crate::config::{bool_from_env, parse_env};
+use crate::data_structure::*;
+use crate::distributions::Range;
+use crate::now_micros;
+use crate::script::{Fault, ScenarioScript};
+use crate::sensor_model::{FieldModel, SensorField, SensorModels};
+use crate::world::{Landing, World, WorldConfig};
+
+/// Where the object properties of a frame are drawn from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectDistribution {
+    pub velocity: Range,
+    pub mass: Range,
+    pub size: Range,
+    pub x: Range,
+    pub y: Range,
+    // m above the catch plane, as in the world
+    pub height: Range,
+}
+
+impl ObjectDistribution {
+    /// A normal falling object.
+    pub fn normal() -> Self {
+        ObjectDistribution {
+            // v = u + at with u = 0, a = 9.8 and the object caught at 1s, heavier objects a bit faster
+            velocity: Range::new(9.8, 11.8),
+            // 1 - 5g
+            mass: Range::new(1.0, 5.0),
+            // 4 - 5l
+            size: Range::new(4.0, 5.0),
+            // the tube is 4cm in front and 3cm wide, a bit more range for wind
+            x: Range::new(4.0, 7.0),
+            y: Range::new(-5.0, 5.0),
+            // anywhere in the sensor window, the world drops from 1.5m
+            height: Range::new(0.0, 1.5),
+        }
+    }
+
+    /// Something that is not the expected object, like a hand in the tube.
+    pub fn anomalous() -> Self {
+        ObjectDistribution {
+            // very slow or static
+            velocity: Range::new(0.0, 1.0),
+            // hand = 400–600g vs normal 1–5g
+            mass: Range::new(100.0, 600.0),
+            // much larger than the expected object
+            size: Range::new(10.0, 30.0),
+            // very close or far off, like a hand waving or blocking the tube
+            x: Range::new(7.0, 10.0),
+            y: Range::new(7.0, 11.0),
+            // held in the upper part of the window
+            height: Range::new(0.5, 1.5),
+        }
+    }
+}
+
+/// Which object properties an anomalous frame takes from the anomalous distribution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnomalyKind {
+    // every property, the original hand in the tube
+    Hand,
+    Static,
+    Heavy,
+    Oversized,
+    OffCourse,
+}
+
+impl FromStr for AnomalyKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hand" => Ok(AnomalyKind::Hand),
+            "static" => Ok(AnomalyKind::Static),
+            "heavy" => Ok(AnomalyKind::Heavy),
+            "oversized" => Ok(AnomalyKind::Oversized),
+            "off-course" => Ok(AnomalyKind::OffCourse),
+            other => Err(format!("unknown anomaly kind: {}", other)),
+        }
+    }
+}
+
+/// When a frame is anomalous.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AnomalySchedule {
+    // every nth cycle, 0 never
+    Every(u64),
+    // each frame independently with this probability
+    Rate(f32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scenario {
+    pub anomalies: AnomalySchedule,
+    // picked uniformly for each anomalous frame
+    pub anomaly_kinds: Vec<AnomalyKind>,
+    pub normal: ObjectDistribution,
+    pub anomalous: ObjectDistribution,
+    // normal objects fall through a simulated world instead of being drawn per frame
+    pub world: Option<WorldConfig>,
+    // how the true state is sensed, exact when empty
+    pub sensors: Vec<FieldModel>,
+}
+
+impl Default for Scenario {
+    /// Every 10th frame is a hand, as the controller always did.
+    fn default() -> Self {
+        Scenario {
+            anomalies: AnomalySchedule::Every(10),
+            anomaly_kinds: vec![AnomalyKind::Hand],
+            normal: ObjectDistribution::normal(),
+            anomalous: ObjectDistribution::anomalous(),
+            world: None,
+            sensors: Vec::new(),
+        }
+    }
+}
+
+impl Scenario {
+    /// Default scenario adjusted by `ANOMALY_EVERY`, `ANOMALY_RATE` (wins over every) and
+    /// `ANOMALY_KINDS` (comma separated: hand, static, heavy, oversized, off-course),
+    /// `WORLD=1` simulates falling objects (see `WorldConfig::from_env`), `SENSOR_MODEL_FILE` adds sensor models.
+    pub fn from_env() -> Self {
+        let mut scenario = Scenario::default();
+        if let Some(every) = parse_env("ANOMALY_EVERY") {
+            scenario.anomalies = AnomalySchedule::Every(every);
+        }
+        if let Some(rate) = parse_env("ANOMALY_RATE") {
+            scenario.anomalies = AnomalySchedule::Rate(rate);
+        }
+        if let Ok(kinds) = std::env::var("ANOMALY_KINDS") {
+            scenario.anomaly_kinds = kinds
+                .split(',')
+                .map(|kind| kind.trim().parse().expect("Invalid ANOMALY_KINDS"))
+                .collect();
+        }
+        if bool_from_env("WORLD", false) {
+            scenario.world = Some(WorldConfig::from_env());
+        }
+        scenario.sensors = SensorModels::from_env();
+        scenario
+    }
+}
+
+// what the sensor sees before the sensor models
+struct Observation {
+    object_id: u64,
+    object: ObjectData,
+    anomaly: Option<AnomalyKind>,
+    spikes: Vec<(SensorField, f32)>,
+}
+
+pub struct SensorSimulator {
+    pub seed: u64,
+    pub scenario: Scenario,
+    rng: fastrand::Rng,
+    world: Option<World>,
+    sensors: SensorModels,
+    script: Option<ScenarioScript>,
+    // the last frame before the sensor models, and whether a fault was injected into it
+    truth: Option<SensorArmData>,
+    faulted: bool,
+    // scripted spikes on the arm of the last frame, applied once its arm is sensed
+    arm_spikes: Vec<(SensorField, f32)>,
+    // every landing of a scripted run and the catch evaluator's verdict on it, kept for its expectations
+    landings: Vec<Landing>,
+    catches: BTreeMap<u64, CatchResult>,
+    frames: u64,
+    anomalies: u64,
+    dropouts: u64,
+}
+
+impl SensorSimulator {
+    pub fn new(seed: u64, scenario: Scenario) -> Self {
+        // own stream for the world, so anomalies and arm draws do not shift its objects
+        let world = scenario.world.clone().map(|config| World::new(config, seed.wrapping_add(1)));
+        let sensors = SensorModels::new(scenario.sensors.clone(), seed.wrapping_add(2));
+        SensorSimulator {
+            seed,
+            scenario,
+            rng: fastrand::Rng::with_seed(seed),
+            world,
+            sensors,
+            script: None,
+            truth: None,
+            faulted: false,
+            arm_spikes: Vec::new(),
+            landings: Vec::new(),
+            catches: BTreeMap::new(),
+            frames: 0,
+            anomalies: 0,
+            dropouts: 0,
+        }
+    }
+
+    /// Replays `script`: only its drops fall and only its injections are anomalous.
+    pub fn scripted(seed: u64, script: ScenarioScript) -> Self {
+        let scenario = Scenario {
+            anomalies: AnomalySchedule::Every(0),
+            world: Some(script.world.clone()),
+            sensors: script.sensors.clone(),
+            ..Scenario::default()
+        };
+        SensorSimulator {
+            world: Some(World::scripted(script.world.clone(), seed.wrapping_add(1))),
+            script: Some(script),
+            ..Self::new(seed, scenario)
+        }
+    }
+
+    /// Replays `SCENARIO_FILE` when set, the scenario from the env otherwise.
+    /// Seed from the file or `SIM_SEED`, a random one otherwise (printed so the run can be repeated).
+    pub fn from_env() -> Self {
+        let script = ScenarioScript::from_env();
+        let seed = script
+            .as_ref()
+            .and_then(|script| script.seed)
+            .or_else(|| parse_env("SIM_SEED"))
+            .unwrap_or_else(|| fastrand::u64(..));
+        println!("> Simulation seed {} (SIM_SEED to repeat)", seed);
+        match script {
+            Some(script) => {
+                println!("> Replaying scenario {}", script.name);
+                Self::scripted(seed, script)
+            }
+            None => Self::new(seed, Scenario::from_env()),
+        }
+    }
+
+    /// The anomaly kind of this cycle's object, if it is one.
+    fn anomaly(&mut self, cycle: u64) -> Option<AnomalyKind> {
+        let anomalous = match self.scenario.anomalies {
+            AnomalySchedule::Every(0) => false,
+            AnomalySchedule::Every(n) => cycle.is_multiple_of(n),
+            AnomalySchedule::Rate(rate) => self.rng.f32() < rate,
+        };
+        let kinds = &self.scenario.anomaly_kinds;
+        if !anomalous || kinds.is_empty() {
+            return None;
+        }
+        Some(kinds[self.rng.usize(..kinds.len())])
+    }
+
+    /// The object the sensor sees this cycle, its anomaly and the scripted spikes, None when it sees nothing.
+    fn next_object(&mut self, cycle: u64) -> Option<Observation> {
+        let mut kind = self.anomaly(cycle);
+        let mut faults = Vec::new();
+        // every frame is its own object without a world
+        let mut object_id = cycle;
+        let mut object = match &mut self.world {
+            Some(world) => {
+                world.step();
+                if let Some(script) = &self.script {
+                    for drop in script.drops_at(world.tick()) {
+                        world.drop_object(drop.x, drop.y, drop.height, drop.velocity, drop.mass, drop.size);
+                    }
+                    faults.extend(script.faults_at(world.tick()));
+                }
+                if faults.contains(&Fault::Dropout) {
+                    return None;
+                }
+                // nothing falling, only possible in a scripted run
+                let falling = world.observe()?;
+                object_id = falling.id;
+                falling.to_object_data()
+            }
+            None => {
+                let normal = &self.scenario.normal;
+                ObjectData {
+                    object_velocity: normal.velocity.sample(&mut self.rng),
+                    object_mass: normal.mass.sample(&mut self.rng),
+                    object_size: normal.size.sample(&mut self.rng),
+                    object_x: normal.x.sample(&mut self.rng),
+                    object_y: normal.y.sample(&mut self.rng),
+                    object_height: normal.height.sample(&mut self.rng),
+                }
+            }
+        };
+        kind = kind.or(faults.iter().find_map(Fault::anomaly));
+        let anomalous = &self.scenario.anomalous;
+        let rng = &mut self.rng;
+        match kind {
+            None => {}
+            Some(AnomalyKind::Hand) => {
+                object.object_velocity = anomalous.velocity.sample(rng);
+                object.object_mass = anomalous.mass.sample(rng);
+                object.object_size = anomalous.size.sample(rng);
+                object.object_x = anomalous.x.sample(rng);
+                object.object_y = anomalous.y.sample(rng);
+                object.object_height = anomalous.height.sample(rng);
+            }
+            Some(AnomalyKind::Static) => object.object_velocity = anomalous.velocity.sample(rng),
+            Some(AnomalyKind::Heavy) => object.object_mass = anomalous.mass.sample(rng),
+            Some(AnomalyKind::Oversized) => object.object_size = anomalous.size.sample(rng),
+            Some(AnomalyKind::OffCourse) => {
+                object.object_x = anomalous.x.sample(rng);
+                object.object_y = anomalous.y.sample(rng);
+            }
+        }
+        let spikes = faults
+            .iter()
+            .filter_map(|fault| match *fault {
+                Fault::Spike { field, value } => Some((field, value)),
+                _ => None,
+            })
+            .collect();
+        Some(Observation {
+            object_id,
+            object,
+            anomaly: kind,
+            spikes,
+        })
+    }
+
+    /// One frame for `cycle`, with the arm taken from `feedback` when there is some.
+    /// None when the sensor sees nothing, a dropout or a scripted world with nothing falling.
+    pub fn generate(&mut self, cycle: u64, feedback: Option<&FeedbackData>) -> Option<SensorArmData> {
+        let mut frame = self.observe(cycle, feedback)?;
+        self.sense_arm(cycle, &mut frame);
+        Some(frame)
+    }
+
+    /// `generate` with only the object sensed, the arm stays true until `sense_arm`,
+    /// so it can first be moved to the arm the frame ends up with.
+    pub fn observe(&mut self, cycle: u64, feedback: Option<&FeedbackData>) -> Option<SensorArmData> {
+        let Some(Observation {
+            object_id,
+            object: object_data,
+            anomaly: kind,
+            spikes,
+        }) = self.next_object(cycle)
+        else {
+            self.dropouts += 1;
+            self.truth = None;
+            return None;
+        };
+        self.frames += 1;
+        if kind.is_some() || !spikes.is_empty() {
+            self.anomalies += 1;
+        }
+        let mut sensor_data = SensorArmData::new(object_data);
+        let rng = &mut self.rng;
+
+        //realistic segment lengths (upper and lower arm)
+        //l1 = shoulder to elbow (1–4cm), l2 = elbow to wrist (4–7cm)
+        let l1 = Range::new(1.0, 4.0).sample(rng);
+        let l2 = Range::new(4.0, 7.0).sample(rng);
+        //angle clamped to [0, π/2] for safe forward-right region
+        let theta1 = Range::new(0.0, std::f32::consts::FRAC_PI_2).sample(rng);
+        //elbow bend ±90°
+        let theta2 = Range::new(-std::f32::consts::FRAC_PI_2, std::f32::consts::FRAC_PI_2).sample(rng);
+        // drawn even when feedback replaces them, whether feedback arrived in time must not shift the sequence
+        let shoulder_x = Range::new(0.0, 1.0).sample(rng);
+        let shoulder_y = Range::new(-1.5, 1.5).sample(rng);
+        //suggested arm velocity to catch object
+        sensor_data.arm_velocity = Range::new(0.0, 10.0).sample(rng);
+
+        if let Some(feedback) = feedback {
+            sensor_data.apply_feedback(feedback);
+        } else {
+            //using forward kinematics to calculate arm positions
+            //shoulder is the base of the arm, so it is the least variable
+            sensor_data.joints.shoulder_x = shoulder_x;
+            sensor_data.joints.shoulder_y = shoulder_y;
+            //elbow from shoulder + angle + l1, the upper arm segment
+            sensor_data.elbow.elbow_x = (shoulder_x + l1 * theta1.cos()).clamp(0.0, 7.0);
+            sensor_data.elbow.elbow_y = (shoulder_y + l1 * theta1.sin()).clamp(-1.5, 1.5);
+            //wrist is the end of the forearm, direction is the total angle (shoulder + elbow joint)
+            let wrist_x = sensor_data.elbow.elbow_x + l2 * (theta1 + theta2).cos();
+            let wrist_y = sensor_data.elbow.elbow_y + l2 * (theta1 + theta2).sin();
+            sensor_data.wrist.wrist_x = wrist_x.max(shoulder_x);
+            sensor_data.wrist.wrist_y = wrist_y.clamp(-1.5, 1.5);
+        }
+
+        //arm strength is a crude estimate based on F = m * a,
+        //assuming velocity is proportional to acceleration here
+        sensor_data.arm_strength = sensor_data.arm_velocity * sensor_data.object_data.object_mass;
+        self.truth = Some(sensor_data.clone());
+        let sensed_fault = self.sensors.sense_fields(cycle, &mut sensor_data, |field| !field.is_arm());
+        self.faulted = kind.is_some() || !spikes.is_empty() || sensed_fault;
+        let (arm_spikes, object_spikes): (Vec<_>, Vec<_>) = spikes.into_iter().partition(|(field, _)| field.is_arm());
+        for (field, value) in object_spikes {
+            field.set(&mut sensor_data, value);
+        }
+        self.arm_spikes = arm_spikes;
+        sensor_data.timestamp = now_micros();
+        sensor_data.trace.cycle = cycle;
+        sensor_data.trace.object_id = object_id;
+        sensor_data.trace.generated = sensor_data.timestamp;
+        Some(sensor_data)
+    }
+
+    /// Moves the arm of the observed frame, and of its truth, to where `feedback` says it is.
+    pub fn place_arm(&mut self, frame: &mut SensorArmData, feedback: &FeedbackData) {
+        frame.apply_feedback(feedback);
+        if let Some(truth) = &mut self.truth {
+            truth.apply_feedback(feedback);
+        }
+    }
+
+    /// Runs the arm of the observed frame through the sensor models, its faults count towards `faulted`.
+    pub fn sense_arm(&mut self, cycle: u64, frame: &mut SensorArmData) {
+        self.faulted |= self.sensors.sense_fields(cycle, frame, SensorField::is_arm);
+        for (field, value) in self.arm_spikes.drain(..) {
+            field.set(frame, value);
+        }
+    }
+
+    /// Ground truth for the objects that landed since the last call, empty without a world.
+    pub fn settle(&mut self) -> Vec<Landing> {
+        let landings = self.world.as_mut().map_or_else(Vec::new, |world| world.settle());
+        if self.script.is_some() {
+            self.landings.extend(landings.iter().cloned());
+        }
+        landings
+    }
+
+    /// Keeps the catch evaluator's verdicts of a scripted run, its expectations are checked against them.
+    pub fn record_catches(&mut self, outcomes: &[CatchOutcome]) {
+        if self.script.is_some() {
+            self.catches
+                .extend(outcomes.iter().map(|outcome| (outcome.object_id, outcome.result)));
+        }
+    }
+
+    /// The last frame as it really was, before the sensor models.
+    pub fn truth(&self) -> Option<&SensorArmData> {
+        self.truth.as_ref()
+    }
+
+    /// Whether an anomaly or sensor fault was injected into the last frame.
+    pub fn faulted(&self) -> bool {
+        self.faulted
+    }
+
+    pub fn sensors(&self) -> &SensorModels {
+        &self.sensors
+    }
+
+    pub fn script(&self) -> Option<&ScenarioScript> {
+        self.script.as_ref()
+    }
+
+    /// Busy time the script adds to the current cycle.
+    pub fn load(&self) -> Duration {
+        match (&self.script, &self.world) {
+            (Some(script), Some(world)) => script.load_at(world.tick()),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Checks the script's expectations, None when no script is replayed.
+    pub fn check(&self, detected_anomalies: u64) -> Option<Vec<String>> {
+        Some(self.script.as_ref()?.check(&self.landings, &self.catches, detected_anomalies))
+    }
+
+    pub fn world(&self) -> Option<&World> {
+        self.world.as_ref()
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn anomalies(&self) -> u64 {
+        self.anomalies
+    }
+
+    pub fn print_summary(&self) {
+        println!(
+            "> Simulation (seed {}, {:?}): frames={} anomalies={} no_frame={}",
+            self.seed,
+            self.scenario.anomalies,
+            self.frames,
+            self.anomalies,
+            self.dropouts
+        );
+        if let Some(world) = &self.world {
+            world.print_summary();
+        }
+        self.sensors.print_summary();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensor_model::StuckAt;
+
+    #[test]
+    fn allocated_arm_is_sensed_with_its_faults() {
+        // the wrist x reading is stuck at 9 from the start
+        let stuck = FieldModel {
+            field: SensorField::WristX,
+            noise: 0.0,
+            drift: 0.0,
+            quantum: 0.0,
+            stuck: Some(StuckAt {
+                at_ms: 0,
+                duration_ms: None,
+                value: Some(9.0),
+            }),
+            dropout_rate: 0.0,
+            spike_rate: 0.0,
+            spike: 0.0,
+            delay_ms: 0,
+        };
+        let scenario = Scenario {
+            anomalies: AnomalySchedule::Every(0),
+            sensors: vec![stuck],
+            ..Scenario::default()
+        };
+        let mut simulator = SensorSimulator::new(7, scenario);
+        let mut frame = simulator.observe(1, None).expect("the sensor sees an object");
+        assert!(!simulator.faulted());
+
+        let mut feedback = frame.to_feedback(0);
+        feedback.wrist.wrist_x = 2.5;
+        feedback.wrist.wrist_y = -0.5;
+        simulator.place_arm(&mut frame, &feedback);
+        simulator.sense_arm(1, &mut frame);
+
+        assert_eq!(simulator.truth().unwrap().wrist.wrist_x, 2.5);
+        assert_eq!(frame.wrist.wrist_x, 9.0);
+        // unmodelled fields are reported as they are
+        assert_eq!(frame.wrist.wrist_y, -0.5);
+        assert!(simulator.faulted());
+    }
+}
@@ -0,0 +1,354 @@
+// telemetry pipeline
+// the hot path only records a histogram sample and try_sends a small fixed size sample into one bounded
+// queue (std's array channel, lock free on the sending side), a full queue drops the sample and counts it;
+// a single writer thread drains the queue in batches and hands every batch to the configured sinks
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, TrySendError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::config::{duration_from_env, parse_env};
+use crate::histogram::LatencyHistograms;
+use crate::latency_log::{LatencyRecord, LatencyWriter, RunInfo};
+
+pub const DEFAULT_CAPACITY: usize = 4096;
+pub const DEFAULT_BATCH: usize = 256;
+// a partial batch is written after waiting this long
+pub const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SinkKind {
+    // latency_<process>.csv, the schema the report reads
+    Csv,
+    // latency_<process>.jsonl, one LatencyRecord per line
+    JsonLines,
+    // per stage counts and latencies printed on exit, the histograms' summary is printed either way
+    Summary,
+}
+
+impl FromStr for SinkKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(SinkKind::Csv),
+            "jsonl" => Ok(SinkKind::JsonLines),
+            "summary" => Ok(SinkKind::Summary),
+            _ => Err(format!("unknown telemetry sink {}, expected csv, jsonl or summary", s)),
+        }
+    }
+}
+
+/// Where the samples go once they leave the queue.
+pub trait TelemetrySink: Send {
+    fn write_batch(&mut self, batch: &[LatencyRecord]) -> Result<(), Box<dyn Error>>;
+
+    /// Called once after the last batch.
+    fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+impl TelemetrySink for LatencyWriter {
+    fn write_batch(&mut self, batch: &[LatencyRecord]) -> Result<(), Box<dyn Error>> {
+        self.write_all(batch)
+    }
+}
+
+/// Appends records as json lines.
+pub struct JsonLinesSink {
+    writer: BufWriter<File>,
+}
+
+impl JsonLinesSink {
+    pub fn open(path: &str) -> Result<Self, Box<dyn Error>> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(JsonLinesSink {
+            writer: BufWriter::new(file),
+        })
+    }
+}
+
+impl TelemetrySink for JsonLinesSink {
+    fn write_batch(&mut self, batch: &[LatencyRecord]) -> Result<(), Box<dyn Error>> {
+        for record in batch {
+            serde_json::to_writer(&mut self.writer, record)?;
+            self.writer.write_all(b"\n")?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct StageSummary {
+    count: u64,
+    sum_us: u128,
+    min_us: u128,
+    max_us: u128,
+}
+
+/// Keeps per stage counts and prints them when the run ends.
+#[derive(Default)]
+pub struct SummarySink {
+    stages: BTreeMap<String, StageSummary>,
+}
+
+impl TelemetrySink for SummarySink {
+    fn write_batch(&mut self, batch: &[LatencyRecord]) -> Result<(), Box<dyn Error>> {
+        for record in batch {
+            let stage = self.stages.entry(record.stage.clone()).or_insert(StageSummary {
+                count: 0,
+                sum_us: 0,
+                min_us: u128::MAX,
+                max_us: 0,
+            });
+            stage.count += 1;
+            stage.sum_us += record.latency_us;
+            stage.min_us = stage.min_us.min(record.latency_us);
+            stage.max_us = stage.max_us.max(record.latency_us);
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        println!("> Telemetry samples per stage:");
+        for (name, stage) in &self.stages {
+            println!(
+                ">   {}: samples={} min={}µs mean={}µs max={}µs",
+                name,
+                stage.count,
+                stage.min_us,
+                stage.sum_us / stage.count.max(1) as u128,
+                stage.max_us
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TelemetryConfig {
+    pub sinks: Vec<SinkKind>,
+    // samples the queue holds before the hot path starts dropping
+    pub capacity: usize,
+    pub batch: usize,
+    pub flush_interval: Duration,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        TelemetryConfig {
+            // every sink only sees samples with LATENCY_SAMPLES=1, the histograms summarise the rest
+            sinks: vec![SinkKind::Csv],
+            capacity: DEFAULT_CAPACITY,
+            batch: DEFAULT_BATCH,
+            flush_interval: DEFAULT_FLUSH_INTERVAL,
+        }
+    }
+}
+
+impl TelemetryConfig {
+    /// Defaults adjusted by `TELEMETRY_SINKS` (comma separated `csv`, `jsonl`, `summary`),
+    /// `TELEMETRY_CAPACITY`, `TELEMETRY_BATCH` and `TELEMETRY_FLUSH_MS`.
+    pub fn from_env() -> Self {
+        let default = TelemetryConfig::default();
+        let sinks = match std::env::var("TELEMETRY_SINKS") {
+            Ok(sinks) => sinks
+                .split(',')
+                .map(str::trim)
+                .filter(|sink| !sink.is_empty())
+                .map(|sink| sink.parse().unwrap_or_else(|e| panic!("Invalid TELEMETRY_SINKS: {}", e)))
+                .collect(),
+            Err(_) => default.sinks,
+        };
+        TelemetryConfig {
+            sinks,
+            capacity: parse_env("TELEMETRY_CAPACITY").unwrap_or(default.capacity).max(1),
+            batch: parse_env("TELEMETRY_BATCH").unwrap_or(default.batch).max(1),
+            flush_interval: duration_from_env("TELEMETRY_FLUSH_MS", default.flush_interval),
+        }
+    }
+}
+
+// what crosses the queue, the writer turns it into a LatencyRecord
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    stage: &'static str,
+    cycle: u64,
+    start_us: u128,
+    end_us: u128,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    accepted: AtomicU64,
+    // the queue was full
+    dropped: AtomicU64,
+    // in the queue right now, and the most there ever were
+    queued: AtomicU64,
+    peak_queued: AtomicU64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TelemetryStats {
+    pub accepted: u64,
+    pub dropped: u64,
+    pub peak_queued: u64,
+    pub written: u64,
+    pub batches: u64,
+    pub sink_errors: u64,
+    pub capacity: usize,
+}
+
+impl TelemetryStats {
+    pub fn print_summary(&self) {
+        println!(
+            "> Telemetry: accepted={} dropped={} written={} batches={} peak_queued={}/{} sink_errors={}",
+            self.accepted, self.dropped, self.written, self.batches, self.peak_queued, self.capacity, self.sink_errors
+        );
+    }
+}
+
+/// Hot path half of the pipeline, cheap to clone and never blocks.
+#[derive(Clone)]
+pub struct TelemetrySender {
+    pub histograms: Arc<LatencyHistograms>,
+    per_sample: bool,
+    tx: mpsc::SyncSender<Sample>,
+    counters: Arc<Counters>,
+}
+
+impl TelemetrySender {
+    /// Records a stage latency, the sample is dropped and counted when the queue is full.
+    pub fn record(&self, stage: &'static str, cycle: u64, start_us: u128, end_us: u128) {
+        self.histograms.record(stage, end_us.saturating_sub(start_us));
+        if !self.per_sample {
+            return;
+        }
+        let sample = Sample {
+            stage,
+            cycle,
+            start_us,
+            end_us,
+        };
+        // counted before sending so the writer never takes it out first
+        let queued = self.counters.queued.fetch_add(1, Ordering::Relaxed) + 1;
+        match self.tx.try_send(sample) {
+            Ok(()) => {
+                self.counters.accepted.fetch_add(1, Ordering::Relaxed);
+                self.counters.peak_queued.fetch_max(queued, Ordering::Relaxed);
+            }
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                self.counters.queued.fetch_sub(1, Ordering::Relaxed);
+                self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// The writer side, owns the thread draining the queue into the sinks.
+pub struct Telemetry {
+    writer: JoinHandle<TelemetryStats>,
+}
+
+impl Telemetry {
+    /// Starts the writer thread, samples are written once every `TelemetrySender` is dropped and
+    /// `finish` is called. With `run.per_sample` off only the histograms see the samples.
+    pub fn start(run: &RunInfo, histograms: Arc<LatencyHistograms>, config: TelemetryConfig) -> (TelemetrySender, Self) {
+        let sinks: Vec<Box<dyn TelemetrySink>> = config
+            .sinks
+            .iter()
+            .filter_map(|kind| open_sink(*kind, run).map_err(|e| eprintln!("Failed to open {:?} telemetry sink: {}", kind, e)).ok())
+            .collect();
+        let (tx, rx) = mpsc::sync_channel(config.capacity);
+        let counters = Arc::new(Counters::default());
+        let sender = TelemetrySender {
+            histograms,
+            per_sample: run.per_sample,
+            tx,
+            counters: Arc::clone(&counters),
+        };
+        let run = run.clone();
+        let writer = std::thread::Builder::new()
+            .name("telemetry".to_string())
+            .spawn(move || write_batches(rx, sinks, run, config, counters))
+            .expect("Failed to spawn telemetry writer");
+        (sender, Telemetry { writer })
+    }
+
+    /// Waits for the writer to drain the queue, every sender must be dropped first.
+    pub async fn finish(self) -> TelemetryStats {
+        tokio::task::spawn_blocking(move || self.writer.join())
+            .await
+            .expect("Telemetry join task panicked")
+            .expect("Telemetry writer panicked")
+    }
+}
+
+fn open_sink(kind: SinkKind, run: &RunInfo) -> Result<Box<dyn TelemetrySink>, Box<dyn Error>> {
+    Ok(match kind {
+        SinkKind::Csv => Box::new(LatencyWriter::open(run.log_path())?),
+        SinkKind::JsonLines => Box::new(JsonLinesSink::open(&format!("latency_{}.jsonl", run.process))?),
+        SinkKind::Summary => Box::new(SummarySink::default()),
+    })
+}
+
+fn write_batches(
+    rx: mpsc::Receiver<Sample>,
+    mut sinks: Vec<Box<dyn TelemetrySink>>,
+    run: RunInfo,
+    config: TelemetryConfig,
+    counters: Arc<Counters>,
+) -> TelemetryStats {
+    let mut stats = TelemetryStats {
+        capacity: config.capacity,
+        ..TelemetryStats::default()
+    };
+    let mut batch = Vec::with_capacity(config.batch);
+    let mut deadline = Instant::now() + config.flush_interval;
+    loop {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let closed = match rx.recv_timeout(timeout) {
+            Ok(sample) => {
+                counters.queued.fetch_sub(1, Ordering::Relaxed);
+                batch.push(run.record(sample.stage, sample.cycle, sample.start_us, sample.end_us));
+                false
+            }
+            Err(RecvTimeoutError::Timeout) => false,
+            Err(RecvTimeoutError::Disconnected) => true,
+        };
+        if batch.len() >= config.batch || (!batch.is_empty() && (closed || Instant::now() >= deadline)) {
+            for sink in &mut sinks {
+                if let Err(e) = sink.write_batch(&batch) {
+                    stats.sink_errors += 1;
+                    eprintln!("Failed to write telemetry batch: {}", e);
+                }
+            }
+            stats.written += batch.len() as u64;
+            stats.batches += 1;
+            batch.clear();
+        }
+        if Instant::now() >= deadline {
+            deadline = Instant::now() + config.flush_interval;
+        }
+        if closed {
+            break;
+        }
+    }
+    stats.accepted = counters.accepted.load(Ordering::Relaxed);
+    stats.dropped = counters.dropped.load(Ordering::Relaxed);
+    stats.peak_queued = counters.peak_queued.load(Ordering::Relaxed);
+    for sink in &mut sinks {
+        if let Err(e) = sink.finish() {
+            eprintln!("Failed to finish telemetry sink: {}", e);
+        }
+    }
+    stats
+}
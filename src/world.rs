@@ -0,0 +1,325 @@
+// falling object world
+// objects are released in the tube and integrated under gravity, with optional drag and wind,
+// one 5ms tick per frame, so consecutive frames follow the same object down until it lands
+// heights are metres above the catch plane, x / y are cm in the arm's plane like the rest of the frame
+use serde::Deserialize;
+
+use crate::config::parse_env;
+use crate::data_structure::ObjectData;
+use crate::distributions::Range;
+use crate::schedulability::LOOP_PERIOD_US;
+
+pub const GRAVITY: f32 = 9.81;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct WorldConfig {
+    // tube opening, objects are released anywhere inside it
+    pub tube_center: (f32, f32),
+    pub tube_radius: f32,
+    // height of the sensor window above the catch plane, m
+    pub drop_height: f32,
+    // downward speed when entering the window, m/s
+    pub release_velocity: Range,
+    pub mass: Range,
+    pub size: Range,
+    // linear drag coefficient (1/s), 0 for free fall
+    pub drag: f32,
+    // horizontal acceleration from wind, cm/s²
+    pub wind: (f32, f32),
+    // ticks between releases, 0 releases the next object once the previous one landed
+    pub spawn_interval: u64,
+    // seconds per tick
+    pub dt: f32,
+}
+
+impl Default for WorldConfig {
+    fn default() -> Self {
+        WorldConfig {
+            // the tube is 4cm in front and 3cm wide
+            tube_center: (5.5, 0.0),
+            tube_radius: 1.5,
+            // the sensor sees the last 1.5m, objects enter it after about 1s of free fall and
+            // land below 11.8 m/s, inside the controller's expected velocity window
+            drop_height: 1.5,
+            release_velocity: Range::new(9.8, 10.3),
+            mass: Range::new(1.0, 5.0),
+            size: Range::new(4.0, 5.0),
+            drag: 0.0,
+            wind: (0.0, 0.0),
+            spawn_interval: 0,
+            dt: LOOP_PERIOD_US as f32 / 1_000_000.0,
+        }
+    }
+}
+
+impl WorldConfig {
+    /// Defaults adjusted by `WORLD_DROP_HEIGHT` (m), `WORLD_DRAG` (1/s), `WORLD_WIND_X` / `WORLD_WIND_Y` (cm/s²),
+    /// and `WORLD_SPAWN_INTERVAL` (ticks).
+    pub fn from_env() -> Self {
+        let mut config = WorldConfig::default();
+        config.drop_height = parse_env("WORLD_DROP_HEIGHT").unwrap_or(config.drop_height);
+        config.drag = parse_env("WORLD_DRAG").unwrap_or(config.drag);
+        config.wind = (
+            parse_env("WORLD_WIND_X").unwrap_or(config.wind.0),
+            parse_env("WORLD_WIND_Y").unwrap_or(config.wind.1),
+        );
+        config.spawn_interval = parse_env("WORLD_SPAWN_INTERVAL").unwrap_or(config.spawn_interval);
+        config
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FallingObject {
+    pub id: u64,
+    pub released_tick: u64,
+    pub x: f32,
+    pub y: f32,
+    pub height: f32,
+    // downward, m/s
+    pub velocity: f32,
+    // horizontal, cm/s
+    pub vx: f32,
+    pub vy: f32,
+    pub mass: f32,
+    pub size: f32,
+}
+
+impl FallingObject {
+    pub fn to_object_data(&self) -> ObjectData {
+        ObjectData {
+            object_velocity: self.velocity,
+            object_mass: self.mass,
+            object_size: self.size,
+            object_x: self.x,
+            object_y: self.y,
+            object_height: self.height,
+        }
+    }
+}
+
+/// Ground truth for one object once it reached the catch plane.
+/// Whether it was caught is for the controller's catch evaluator to decide.
+#[derive(Debug, Clone)]
+pub struct Landing {
+    pub object_id: u64,
+    pub released_tick: u64,
+    pub landed_tick: u64,
+    pub landed_at: (f32, f32),
+}
+
+pub struct World {
+    pub config: WorldConfig,
+    rng: fastrand::Rng,
+    // off for scripted runs, objects only appear when dropped
+    auto_release: bool,
+    tick: u64,
+    last_release: u64,
+    next_id: u64,
+    in_flight: Vec<FallingObject>,
+    // landed but not handed out yet
+    landed: Vec<(FallingObject, u64)>,
+    landings: u64,
+}
+
+impl World {
+    pub fn new(config: WorldConfig, seed: u64) -> Self {
+        World {
+            config,
+            rng: fastrand::Rng::with_seed(seed),
+            auto_release: true,
+            tick: 0,
+            last_release: 0,
+            next_id: 1,
+            in_flight: Vec::new(),
+            landed: Vec::new(),
+            landings: 0,
+        }
+    }
+
+    /// A world where objects only fall when `drop_object` is called.
+    pub fn scripted(config: WorldConfig, seed: u64) -> Self {
+        World {
+            auto_release: false,
+            ..Self::new(config, seed)
+        }
+    }
+
+    /// Releases an object at the current tick, at the drop height unless `height` is given.
+    pub fn drop_object(&mut self, x: f32, y: f32, height: Option<f32>, velocity: f32, mass: f32, size: f32) -> u64 {
+        let id = self.next_id;
+        self.in_flight.push(FallingObject {
+            id,
+            released_tick: self.tick,
+            x,
+            y,
+            height: height.unwrap_or(self.config.drop_height),
+            velocity,
+            vx: 0.0,
+            vy: 0.0,
+            mass,
+            size,
+        });
+        self.next_id += 1;
+        self.last_release = self.tick;
+        id
+    }
+
+    fn release(&mut self) {
+        let config = &self.config;
+        let rng = &mut self.rng;
+        // uniform over the tube opening
+        let r = config.tube_radius * rng.f32().sqrt();
+        let angle = rng.f32() * std::f32::consts::TAU;
+        let object = FallingObject {
+            id: self.next_id,
+            released_tick: self.tick,
+            x: config.tube_center.0 + r * angle.cos(),
+            y: config.tube_center.1 + r * angle.sin(),
+            height: config.drop_height,
+            velocity: config.release_velocity.sample(rng),
+            vx: 0.0,
+            vy: 0.0,
+            mass: config.mass.sample(rng),
+            size: config.size.sample(rng),
+        };
+        self.next_id += 1;
+        self.last_release = self.tick;
+        self.in_flight.push(object);
+    }
+
+    /// Advances one tick, releasing a new object when one is due.
+    pub fn step(&mut self) {
+        self.tick += 1;
+        let WorldConfig { drag, wind, dt, .. } = self.config;
+        for object in &mut self.in_flight {
+            // semi-implicit euler, velocity first
+            object.velocity += (GRAVITY - drag * object.velocity) * dt;
+            object.height -= object.velocity * dt;
+            object.vx += (wind.0 - drag * object.vx) * dt;
+            object.vy += (wind.1 - drag * object.vy) * dt;
+            object.x += object.vx * dt;
+            object.y += object.vy * dt;
+        }
+        let tick = self.tick;
+        let (landed, in_flight): (Vec<_>, Vec<_>) = self.in_flight.drain(..).partition(|o| o.height <= 0.0);
+        self.in_flight = in_flight;
+        self.landed.extend(landed.into_iter().map(|object| (object, tick)));
+        // after landing, so there is always an object for the sensor to see
+        let interval = self.config.spawn_interval;
+        if !self.auto_release {
+            return;
+        }
+        if self.in_flight.is_empty() || (interval > 0 && tick - self.last_release >= interval) {
+            self.release();
+        }
+    }
+
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// The object the sensor sees, the one released first among those still falling.
+    pub fn observe(&self) -> Option<&FallingObject> {
+        self.in_flight.first()
+    }
+
+    /// Where and when the objects that landed since the last call reached the catch plane.
+    pub fn settle(&mut self) -> Vec<Landing> {
+        let landings: Vec<Landing> = self
+            .landed
+            .drain(..)
+            .map(|(object, landed_tick)| Landing {
+                object_id: object.id,
+                released_tick: object.released_tick,
+                landed_tick,
+                landed_at: (object.x, object.y),
+            })
+            .collect();
+        self.landings += landings.len() as u64;
+        landings
+    }
+
+    pub fn landings(&self) -> u64 {
+        self.landings
+    }
+
+    pub fn print_summary(&self) {
+        println!(
+            "> World: released={} landed={} in_flight={}",
+            self.next_id - 1,
+            self.landings,
+            self.in_flight.len()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catching::fall_time;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    #[test]
+    fn step_integrates_velocity_then_height() {
+        let mut world = World::scripted(WorldConfig::default(), 1);
+        world.drop_object(5.0, 0.0, None, 10.0, 2.0, 4.5);
+        world.step();
+        let dt = world.config.dt;
+        let object = world.observe().expect("still falling");
+        let velocity = 10.0 + GRAVITY * dt;
+        assert!(close(object.velocity, velocity));
+        assert!(close(object.height, 1.5 - velocity * dt));
+        assert_eq!((object.x, object.y), (5.0, 0.0));
+    }
+
+    #[test]
+    fn wind_pushes_the_object_sideways() {
+        let config = WorldConfig {
+            wind: (100.0, -100.0),
+            ..WorldConfig::default()
+        };
+        let mut world = World::scripted(config, 1);
+        world.drop_object(5.0, 0.0, None, 10.0, 2.0, 4.5);
+        world.step();
+        world.step();
+        let dt = world.config.dt;
+        let object = world.observe().expect("still falling");
+        // 1 then 2 ticks worth of wind speed
+        assert!(close(object.x, 5.0 + 100.0 * dt * dt * 3.0));
+        assert!(close(object.y, -100.0 * dt * dt * 3.0));
+    }
+
+    #[test]
+    fn object_lands_on_the_tick_of_its_analytic_fall() {
+        let mut world = World::scripted(WorldConfig::default(), 1);
+        let id = world.drop_object(5.0, 0.0, None, 10.0, 2.0, 4.5);
+        let mut landings = Vec::new();
+        while landings.is_empty() {
+            assert!(world.tick() < 100, "never landed");
+            world.step();
+            landings = world.settle();
+        }
+        assert_eq!(landings[0].object_id, id);
+        let ticks = fall_time(1.5, 10.0) / world.config.dt;
+        assert!((landings[0].landed_tick as f32 - ticks).abs() <= 1.0);
+        assert!(world.observe().is_none(), "a scripted world releases nothing on its own");
+        assert_eq!(world.landings(), 1);
+    }
+
+    #[test]
+    fn next_object_is_released_once_the_last_one_landed() {
+        let mut world = World::new(WorldConfig::default(), 1);
+        world.step();
+        let first = world.observe().expect("released on the first tick").id;
+        while world.settle().is_empty() {
+            world.step();
+        }
+        let next = world.observe().expect("released as the first one landed");
+        assert_eq!(next.id, first + 1);
+        assert_eq!(next.released_tick, world.tick());
+    }
+}